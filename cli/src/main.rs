@@ -0,0 +1,370 @@
+use std::path::Path;
+use std::process::exit;
+use serde::Serialize;
+use metrefiddler::import::{import_midi_file, ImportOptions};
+use metrefiddler::metre::combined_metre_data::CombinedMetreData;
+use metrefiddler::metre::metre_data::MetreData;
+use metrefiddler::chord::Chord;
+use metrefiddler::params::{ChordMode, ParamsSnapShot, RatchetMode, ThresholdMode};
+use metrefiddler::render::{export_midi, RENDER_SAMPLE_RATE};
+use metrefiddler::util::{dry_wet, get_start_times};
+
+const USAGE: &str = "Usage: metrefiddler-cli [OPTIONS] <RQQ_A> [<RQQ_B> <INTERPOLATION>]
+
+Print durations, start times, GNSM and indispensability values of a metre. When two metres
+and an interpolation value (between 0.0 and 1.0) are given, also print how their beats are
+paired and the resulting beats.
+With --export-midi, write the notes Send Midi would play into a MIDI file instead.
+With --import-midi, the onsets of a MIDI file are used as the first metre.
+
+Options:
+  --json                     Print JSON instead of tables
+  --export-midi <FILE>       Render the pattern into a new Standard MIDI File
+  --bars <N>                 Number of bars to export (default 4)
+  --duration <0.1-20>        Metric duration, in seconds or crotchets with --use-bpm (default 1)
+  --use-bpm                  Metric duration is given in crotchets
+  --tempo <BPM>              Tempo of the MIDI file (default 120)
+  --one-pitch                Send all notes on the same pitch
+  --chords <INTERVALS>       Play a chord, e.g. \"0 4 7\", with more voices on important beats
+  --arpeggio <INTERVALS>     Play one tone of a chord per beat, chosen by its importance
+  --chord-root <0-127>       The root note of the chord (default 48)
+  --ratchet <even|metric>    Repeat the most important beats, evenly or like their group
+  --ratchet-threshold <0-1>  Ratchet threshold, 0 only ratchets the first beat (default 0)
+  --ratchet-repeats <2-16>   Number of even ratchet repeats (default 4)
+  --ratchet-ramp <-1-1>      Velocity ramp of the ratchet repeats (default 0)
+  --import-midi <FILE>       Infer metre A from the onsets of a MIDI file
+  --import-bar <N>           Import only this bar, counting from 1 (default: whole file)
+  --grid <STEPS>             Snap imported onsets to this many steps per quarter note
+  --tolerance <QUARTERS>     Merge imported onsets closer than this (default 0)
+  --velocity-weights         Louder imported onsets start larger groups
+  --accent-mode              Only two distinct velocities
+  --no-interpolate-durs      Interleave the beats of A and B instead of interpolating durations
+  --no-interpolate-indisp    Don't interpolate indispensability values
+  --vel-min <0-127>          Minimum velocity (default 0)
+  --vel-max <0-127>          Maximum velocity (default 127)
+  --skew <0-1>               Velocity skew (default 0.5)
+  --lower-threshold <0-1>    Lower threshold (default 0)
+  --upper-threshold <0-1>    Upper threshold (default 1)
+  --threshold-mode <MODE>    window, top-n, stratum, inverted or density (default window)
+  -h, --help                 Print this message";
+
+/// The analysis of a single metre.
+#[derive(Serialize)]
+struct MetreReport {
+    rqq: String,
+    beats: Vec<BeatRow>,
+}
+
+#[derive(Serialize)]
+struct BeatRow {
+    index: usize,
+    start: f32,
+    duration: f32,
+    gnsm: usize,
+    indispensability: usize,
+}
+
+/// A pair of durations from the InterpolationData.
+#[derive(Serialize)]
+struct PairRow {
+    index: usize,
+    duration_a: f32,
+    duration_b: f32,
+    interpolated: f32,
+}
+
+/// A beat as the plugin would play it.
+#[derive(Serialize)]
+struct ResultRow {
+    index: usize,
+    start: f32,
+    duration: f32,
+    origin: String,
+    indispensability: usize,
+    velocity: f32,
+    passes_thresholds: bool,
+}
+
+#[derive(Serialize)]
+struct Report {
+    metre_a: MetreReport,
+    metre_b: Option<MetreReport>,
+    interpolation: f32,
+    pairs: Vec<PairRow>,
+    beats: Vec<ResultRow>,
+}
+
+struct Options {
+    rqqs: Vec<String>,
+    interpolation: f32,
+    json: bool,
+    snapshot: ParamsSnapShot,
+    export_path: Option<String>,
+    bars: usize,
+    tempo: f64,
+}
+
+fn main() {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            exit(1);
+        }
+    };
+
+    if let Some(path) = &options.export_path {
+        match export(&options, Path::new(path)) {
+            Ok(nr_of_notes) => println!("Exported {} notes to {}", nr_of_notes, path),
+            Err(err) => {
+                eprintln!("{}", err);
+                exit(1);
+            }
+        }
+        return;
+    }
+
+    match analyse(&options) {
+        Ok(report) => {
+            if options.json {
+                match serde_json::to_string_pretty(&report) {
+                    Ok(json) => println!("{}", json),
+                    Err(err) => {
+                        eprintln!("Failed to serialize report: {}", err);
+                        exit(1);
+                    }
+                }
+            } else {
+                print_tables(&report);
+            }
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            exit(1);
+        }
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut rqqs = Vec::new();
+    let mut json = false;
+    let mut export_path = None;
+    let mut bars = 4;
+    let mut tempo = 120.0;
+    let mut import_path = None;
+    let mut import_options = ImportOptions::default();
+    let mut snapshot = ParamsSnapShot {
+        vel_min: 0.0,
+        vel_max: 127.0,
+        ..ParamsSnapShot::default()
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                exit(0);
+            }
+            "--json" => json = true,
+            "--export-midi" => export_path = Some(args.next().ok_or("Missing value for --export-midi")?),
+            "--bars" => bars = parse_value(&arg, args.next(), 1.0, 9999.0)? as usize,
+            "--duration" => snapshot.metric_dur = parse_value(&arg, args.next(), 0.1, 20.0)?,
+            "--use-bpm" => snapshot.use_bpm = true,
+            "--tempo" => tempo = parse_value(&arg, args.next(), 1.0, 999.0)? as f64,
+            "--one-pitch" => snapshot.output_one_pitch = true,
+            "--chords" | "--arpeggio" => {
+                let intervals = args.next().ok_or_else(|| format!("Missing value for {}", arg))?;
+                snapshot.chord = Chord::try_from(intervals.as_str())?;
+                snapshot.chord_mode = if arg == "--chords" { ChordMode::Chords } else { ChordMode::Arpeggio };
+            }
+            "--chord-root" => snapshot.chord_root = parse_value(&arg, args.next(), 0.0, 127.0)? as u8,
+            "--ratchet" => snapshot.ratchet_mode = match args.next().as_deref() {
+                Some("even") => RatchetMode::Even,
+                Some("metric") => RatchetMode::Metric,
+                _ => return Err("--ratchet expects 'even' or 'metric'".to_string()),
+            },
+            "--ratchet-threshold" => snapshot.ratchet_threshold = parse_value(&arg, args.next(), 0.0, 1.0)?,
+            "--ratchet-repeats" => snapshot.ratchet_repeats = parse_value(&arg, args.next(), 2.0, 16.0)? as usize,
+            "--ratchet-ramp" => snapshot.ratchet_ramp = parse_value(&arg, args.next(), -1.0, 1.0)?,
+            "--import-midi" => import_path = Some(args.next().ok_or("Missing value for --import-midi")?),
+            "--import-bar" => import_options.bar = Some(parse_value(&arg, args.next(), 1.0, 9999.0)? as usize - 1),
+            "--grid" => import_options.grid = Some(parse_value(&arg, args.next(), 1.0, 960.0)? as u32),
+            "--tolerance" => import_options.tolerance = parse_value(&arg, args.next(), 0.0, 4.0)? as f64,
+            "--velocity-weights" => import_options.velocities_as_weights = true,
+            "--accent-mode" => snapshot.many_velocities = false,
+            "--no-interpolate-durs" => snapshot.interpolate_durs = false,
+            "--no-interpolate-indisp" => snapshot.interpolate_indisp = false,
+            "--vel-min" => snapshot.vel_min = parse_value(&arg, args.next(), 0.0, 127.0)?,
+            "--vel-max" => snapshot.vel_max = parse_value(&arg, args.next(), 0.0, 127.0)?,
+            "--skew" => snapshot.vel_skew = parse_value(&arg, args.next(), 0.0, 1.0)?,
+            "--lower-threshold" => snapshot.lower_threshold = parse_value(&arg, args.next(), 0.0, 1.0)?,
+            "--upper-threshold" => snapshot.upper_threshold = parse_value(&arg, args.next(), 0.0, 1.0)?,
+            "--threshold-mode" => snapshot.threshold_mode = match args.next().as_deref() {
+                Some("window") => ThresholdMode::Window,
+                Some("top-n") => ThresholdMode::TopN,
+                Some("stratum") => ThresholdMode::Stratum,
+                Some("inverted") => ThresholdMode::Inverted,
+                Some("density") => ThresholdMode::Density,
+                _ => return Err("--threshold-mode expects window, top-n, stratum, inverted or density".to_string()),
+            },
+            _ if arg.starts_with("--") => return Err(format!("Unknown option '{}'", arg)),
+            _ => rqqs.push(arg),
+        }
+    }
+
+    if let Some(path) = import_path {
+        let metre = import_midi_file(Path::new(&path), &import_options)?;
+        rqqs.insert(0, metre.string);
+    }
+
+    let interpolation = match rqqs.len() {
+        1 => 0.0,
+        3 => {
+            let value = rqqs.pop().unwrap_or_default();
+            parse_value("<INTERPOLATION>", Some(value), 0.0, 1.0)?
+        }
+        _ => return Err("Expected either one RQQ string or two RQQ strings and an interpolation value".to_string()),
+    };
+    snapshot.interpolate = interpolation;
+
+    Ok(Options { rqqs, interpolation, json, snapshot, export_path, bars, tempo })
+}
+
+fn parse_value(name: &str, value: Option<String>, min: f32, max: f32) -> Result<f32, String> {
+    let value = value.ok_or_else(|| format!("Missing value for {}", name))?;
+    match value.parse::<f32>() {
+        Ok(x) if (min..=max).contains(&x) => Ok(x),
+        _ => Err(format!("{} must be a number between {} and {}, got '{}'", name, min, max, value)),
+    }
+}
+
+fn metre_report(metre: &MetreData) -> MetreReport {
+    let starts = get_start_times(&metre.durations);
+    MetreReport {
+        rqq: metre.string.clone(),
+        beats: (0..metre.durations.len())
+            .map(|index| BeatRow {
+                index,
+                start: starts[index],
+                duration: metre.durations[index],
+                gnsm: metre.gnsm[index],
+                indispensability: metre.value[index],
+            })
+            .collect(),
+    }
+}
+
+fn parse_metres(options: &Options) -> Result<(MetreData, Option<MetreData>), String> {
+    let metre_a = MetreData::try_from(options.rqqs[0].as_str())
+        .map_err(|err| format!("Failed to parse '{}': {}", options.rqqs[0], err))?;
+    let metre_b = match options.rqqs.get(1) {
+        Some(rqq) => Some(MetreData::try_from(rqq.as_str())
+            .map_err(|err| format!("Failed to parse '{}': {}", rqq, err))?),
+        None => None,
+    };
+
+    Ok((metre_a, metre_b))
+}
+
+fn export(options: &Options, path: &Path) -> Result<usize, String> {
+    let (metre_a, metre_b) = parse_metres(options)?;
+    let combined = CombinedMetreData::new(metre_a.clone(), metre_b.unwrap_or(metre_a))?;
+
+    export_midi(combined, options.snapshot, options.bars, options.tempo, RENDER_SAMPLE_RATE, path)
+}
+
+fn analyse(options: &Options) -> Result<Report, String> {
+    let (metre_a, metre_b) = parse_metres(options)?;
+
+    let combined = CombinedMetreData::new(metre_a.clone(), metre_b.clone().unwrap_or_else(|| metre_a.clone()))?;
+    let snapshot = &options.snapshot;
+    let interpolation = options.interpolation;
+
+    let pairs = if metre_b.is_some() {
+        combined.interpolation_data()
+            .duration_pairs()
+            .iter()
+            .enumerate()
+            .map(|(index, &(duration_a, duration_b))| PairRow {
+                index,
+                duration_a,
+                duration_b,
+                interpolated: dry_wet(duration_a, duration_b, interpolation),
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    // Ask the plugin's own lookup for the beat in the middle of each resulting duration.
+    let durations: Vec<f32> = if snapshot.interpolate_durs {
+        combined.get_interpolated_durations(interpolation).collect()
+    } else {
+        combined.get_interleaved_durations(interpolation).collect()
+    };
+    let starts = get_start_times(&durations);
+
+    let beats = durations.iter()
+        .zip(starts.iter())
+        .map(|(&duration, &start)| {
+            let (index, _, indisp_val, nr_beats, origin) = combined.get_indisp_data_at(
+                start + duration / 2.0,
+                interpolation,
+                snapshot.interpolate_durs,
+                snapshot.interpolate_indisp,
+            );
+            ResultRow {
+                index,
+                start,
+                duration,
+                origin: format!("{:?}", origin),
+                indispensability: indisp_val,
+                velocity: snapshot.velocity(indisp_val, nr_beats) * 127.0,
+                passes_thresholds: snapshot.is_within_thresholds(&combined, index, indisp_val, nr_beats),
+            }
+        })
+        .collect();
+
+    Ok(Report {
+        metre_a: metre_report(&metre_a),
+        metre_b: metre_b.as_ref().map(metre_report),
+        interpolation,
+        pairs,
+        beats,
+    })
+}
+
+fn print_metre(name: &str, metre: &MetreReport) {
+    println!("{}: {}", name, metre.rqq);
+    println!("{:>5} {:>10} {:>10} {:>5} {:>7}", "beat", "start", "duration", "gnsm", "indisp");
+    for beat in &metre.beats {
+        println!("{:>5} {:>10.6} {:>10.6} {:>5} {:>7}",
+                 beat.index, beat.start, beat.duration, beat.gnsm, beat.indispensability);
+    }
+    println!();
+}
+
+fn print_tables(report: &Report) {
+    print_metre("Metre A", &report.metre_a);
+
+    if let Some(metre_b) = &report.metre_b {
+        print_metre("Metre B", metre_b);
+
+        println!("Duration pairs (interpolation {}):", report.interpolation);
+        println!("{:>5} {:>10} {:>10} {:>12}", "pair", "A", "B", "interpolated");
+        for pair in &report.pairs {
+            println!("{:>5} {:>10.6} {:>10.6} {:>12.6}",
+                     pair.index, pair.duration_a, pair.duration_b, pair.interpolated);
+        }
+        println!();
+    }
+
+    println!("Resulting beats:");
+    println!("{:>5} {:>10} {:>10} {:>7} {:>7} {:>8} {:>6}",
+             "beat", "start", "duration", "origin", "indisp", "velocity", "passes");
+    for beat in &report.beats {
+        println!("{:>5} {:>10.6} {:>10.6} {:>7} {:>7} {:>8.1} {:>6}",
+                 beat.index, beat.start, beat.duration, beat.origin, beat.indispensability,
+                 beat.velocity, if beat.passes_thresholds { "yes" } else { "no" });
+    }
+}
@@ -0,0 +1,20 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use metrefiddler::metre::interpolation::interpolation_data::InterpolationData;
+use num_rational::Rational64;
+
+fn to_durations(input: &[(u8, u8)]) -> Vec<Rational64> {
+    input.iter()
+        .take(32)
+        .map(|&(numer, denom)| Rational64::new(numer as i64, denom as i64 + 1))
+        .collect()
+}
+
+fuzz_target!(|input: (Vec<(u8, u8)>, Vec<(u8, u8)>, Vec<u8>, Vec<u8>)| {
+    let (durations_a, durations_b, gnsm_a, gnsm_b) = input;
+    let gnsm_a: Vec<usize> = gnsm_a.iter().take(32).map(|&x| x as usize).collect();
+    let gnsm_b: Vec<usize> = gnsm_b.iter().take(32).map(|&x| x as usize).collect();
+
+    let _ = InterpolationData::new_from_durs_and_gnsm(&to_durations(&durations_a), &to_durations(&durations_b), &gnsm_a, &gnsm_b);
+});
@@ -0,0 +1,20 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use metrefiddler::metre::combined_metre_data::CombinedMetreData;
+use metrefiddler::metre::metre_data::MetreData;
+
+// Two metres separated by a '|', if both parse they are also interpolated.
+fuzz_target!(|input: &str| {
+    let mut parts = input.splitn(2, '|');
+    let metre_a = MetreData::try_from(parts.next().unwrap_or_default());
+
+    if let (Ok(metre_a), Some(rqq_b)) = (metre_a, parts.next()) {
+        if let Ok(metre_b) = MetreData::try_from(rqq_b) {
+            if let Ok(combined) = CombinedMetreData::new(metre_a, metre_b) {
+                let _ = combined.get_indisp_data_at(0.5, 0.5, true, true);
+                let _ = combined.get_indisp_data_at(0.5, 0.5, false, false);
+            }
+        }
+    }
+});
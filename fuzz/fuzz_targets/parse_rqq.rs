@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use metrefiddler::metre::rqq::parse_rqq;
+
+fuzz_target!(|input: &str| {
+    if let Ok(rqq) = parse_rqq(input) {
+        let _ = rqq.to_gnsm();
+    }
+});
@@ -0,0 +1,22 @@
+use rtrb::{Consumer, Producer, RingBuffer};
+
+/// How many beats the audio thread can report before the Gui has to catch up. Beats reported
+/// to a full queue are dropped.
+pub const BEAT_EVENT_QUEUE_LEN: usize = 64;
+
+/// A beat that was triggered by the audio thread, either by sending a note or by an incoming note.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BeatEvent {
+    /// The index of the beat within the current (interpolated or interleaved) durations.
+    pub beat_idx: usize,
+    pub indisp_val: usize,
+    /// The velocity (between 0.0 and 1.0) the beat has or would have had.
+    pub velocity: f32,
+    /// Whether the beat passed the thresholds.
+    pub passed: bool,
+}
+
+/// A lock-free queue from the audio thread to the Gui.
+pub fn beat_event_queue() -> (Producer<BeatEvent>, Consumer<BeatEvent>) {
+    RingBuffer::new(BEAT_EVENT_QUEUE_LEN)
+}
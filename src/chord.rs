@@ -0,0 +1,140 @@
+use std::fmt;
+use serde::{Deserialize, Serialize};
+
+/// The most tones a chord or scale can have.
+pub const MAX_CHORD_TONES: usize = 8;
+/// The largest interval above the root, four octaves.
+const MAX_INTERVAL: u8 = 48;
+
+/// A chord or scale, as intervals in semitones above its root. Send Midi plays it in the chord
+/// modes, where the metric weight of a beat decides which tones sound. Persisted as its string.
+/// ```
+/// use metrefiddler::chord::Chord;
+///
+/// let chord = Chord::try_from("0 4 7 11").unwrap();
+/// // The most important beat gets the whole chord and the root an octave below.
+/// assert_eq!(chord.voicing(48, 0, 3).collect::<Vec<_>>(), vec![36, 48, 52, 55, 59]);
+/// // The least important beat only gets the highest tone.
+/// assert_eq!(chord.voicing(48, 3, 3).collect::<Vec<_>>(), vec![59]);
+/// // Arpeggios climb through the chord as the beats get less important.
+/// assert_eq!((0..6).map(|indisp_val| chord.arpeggio_note(48, indisp_val)).collect::<Vec<_>>(),
+///            vec![36, 48, 52, 55, 59, 60]);
+/// // As a scale, C# is between C and D, so it becomes the lower one.
+/// assert_eq!(chord.quantize(48, 61), 60);
+/// assert_eq!(chord.quantize(48, 63), 64);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct Chord {
+    intervals: [u8; MAX_CHORD_TONES],
+    len: usize,
+}
+
+impl Default for Chord {
+    fn default() -> Self {
+        Self::try_from("0 4 7").expect("The default chord should always be valid")
+    }
+}
+
+impl Chord {
+    /// The intervals in ascending order, without duplicates.
+    pub fn intervals(&self) -> &[u8] {
+        &self.intervals[..self.len]
+    }
+
+    /// The notes for a beat with this indispensability value (0 is the most important beat). The
+    /// more important the beat, the more voices it gets, added from the highest tone downwards.
+    /// The most important beat also gets the lowest tone an octave below, as a bass note.
+    pub fn voicing(&self, root: u8, indisp_val: usize, max_indisp_val: usize) -> impl Iterator<Item = u8> + '_ {
+        let weight = if max_indisp_val == 0 {
+            1.0
+        } else {
+            1.0 - indisp_val.min(max_indisp_val) as f32 / max_indisp_val as f32
+        };
+        let nr_of_voices = 1 + (weight * (self.len - 1) as f32).round() as usize;
+        let bass = (indisp_val == 0).then(|| transpose(root, self.intervals[0] as i32 - 12));
+
+        bass.into_iter()
+            .chain(self.intervals()[self.len - nr_of_voices..].iter().map(move |&interval| transpose(root, interval as i32)))
+    }
+
+    /// A single note for a beat with this indispensability value. The most important beat gets the
+    /// bass note, the others climb through the tones and octaves of the chord.
+    pub fn arpeggio_note(&self, root: u8, indisp_val: usize) -> u8 {
+        match indisp_val.checked_sub(1) {
+            None => transpose(root, self.intervals[0] as i32 - 12),
+            Some(step) => {
+                let octave = (step / self.len) as i32;
+                transpose(root, self.intervals[step % self.len] as i32 + 12 * octave)
+            },
+        }
+    }
+
+    /// Move a note to the nearest pitch class of the chord, played as a scale from its root. When
+    /// two are equally close, the lower one wins.
+    pub fn quantize(&self, root: u8, note: u8) -> u8 {
+        let in_scale = |note: i32| {
+            let pitch_class = (note - root as i32).rem_euclid(12);
+            self.intervals().iter().any(|&interval| interval as i32 % 12 == pitch_class)
+        };
+        (0..=6)
+            .flat_map(|distance| [-distance, distance])
+            .find(|&distance| in_scale(note as i32 + distance))
+            .map_or(note, |distance| transpose(note, distance))
+    }
+}
+
+/// A note some semitones above (or below) the root, kept within the MIDI range.
+pub(crate) fn transpose(root: u8, semitones: i32) -> u8 {
+    (root as i32 + semitones).clamp(0, 127) as u8
+}
+
+impl TryFrom<&str> for Chord {
+    type Error = String;
+
+    /// Parse intervals in semitones, separated by spaces or commas.
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let mut intervals = value
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|s| !s.is_empty())
+            .map(|s| match s.parse::<u8>() {
+                Ok(interval) if interval <= MAX_INTERVAL => Ok(interval),
+                _ => Err(format!("'{}' is not an interval between 0 and {}", s, MAX_INTERVAL)),
+            })
+            .collect::<Result<Vec<u8>, String>>()?;
+        intervals.sort();
+        intervals.dedup();
+
+        if intervals.is_empty() {
+            return Err("a chord needs at least one interval".to_string());
+        }
+        if intervals.len() > MAX_CHORD_TONES {
+            return Err(format!("a chord can have at most {} intervals", MAX_CHORD_TONES));
+        }
+
+        let mut chord = Self { intervals: [0; MAX_CHORD_TONES], len: intervals.len() };
+        chord.intervals[..intervals.len()].copy_from_slice(&intervals);
+        Ok(chord)
+    }
+}
+
+impl TryFrom<String> for Chord {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::try_from(value.as_str())
+    }
+}
+
+impl fmt::Display for Chord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let intervals: Vec<String> = self.intervals().iter().map(|interval| interval.to_string()).collect();
+        write!(f, "{}", intervals.join(" "))
+    }
+}
+
+impl From<Chord> for String {
+    fn from(chord: Chord) -> Self {
+        chord.to_string()
+    }
+}
@@ -0,0 +1,73 @@
+use std::fmt;
+use serde::{Deserialize, Serialize};
+
+/// The CC numbers whose incoming values are scaled by the metric weight. Persisted as its string.
+/// ```
+/// use metrefiddler::controller_scaling::CcSelection;
+///
+/// let selection = CcSelection::try_from("74, 1").unwrap();
+/// assert!(selection.contains(1) && selection.contains(74));
+/// assert!(!selection.contains(7));
+/// assert_eq!(selection.to_string(), "1 74");
+/// assert!(CcSelection::try_from("128").is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct CcSelection {
+    // One bit per CC number.
+    ccs: u128,
+}
+
+impl Default for CcSelection {
+    /// The filter cutoff.
+    fn default() -> Self {
+        Self::try_from("74").expect("The default CC selection should always be valid")
+    }
+}
+
+impl CcSelection {
+    pub fn contains(&self, cc: u8) -> bool {
+        cc < 128 && self.ccs & (1 << cc) != 0
+    }
+
+    pub fn ccs(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..128).filter(|&cc| self.contains(cc))
+    }
+}
+
+impl TryFrom<&str> for CcSelection {
+    type Error = String;
+
+    /// Parse CC numbers, separated by spaces or commas.
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let mut ccs = 0;
+        for s in value.split(|c: char| c.is_whitespace() || c == ',').filter(|s| !s.is_empty()) {
+            match s.parse::<u8>() {
+                Ok(cc) if cc < 128 => ccs |= 1 << cc,
+                _ => return Err(format!("'{}' is not a CC number between 0 and 127", s)),
+            }
+        }
+        Ok(Self { ccs })
+    }
+}
+
+impl TryFrom<String> for CcSelection {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::try_from(value.as_str())
+    }
+}
+
+impl fmt::Display for CcSelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ccs: Vec<String> = self.ccs().map(|cc| cc.to_string()).collect();
+        write!(f, "{}", ccs.join(" "))
+    }
+}
+
+impl From<CcSelection> for String {
+    fn from(selection: CcSelection) -> Self {
+        selection.to_string()
+    }
+}
@@ -1,17 +1,21 @@
 use std::ops::DerefMut;
-use nih_plug::prelude::{Editor};
+use std::path::Path;
+use nih_plug::prelude::{AsyncExecutor, Editor, Param};
 use vizia_plug::vizia::prelude::*;
 use vizia_plug::widgets::*;
+use vizia_plug::widgets::util::ModifiersExt;
 use vizia_plug::{create_vizia_editor, ViziaState, ViziaTheming};
 use vizia_plug::vizia::icons::ICON_SETTINGS;
-use std::sync::{Arc};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
 use atomic_float::AtomicF32;
+use rtrb::Consumer;
 use nih_plug::{nih_log};
 use serde::de::Unexpected::Str;
-use crate::{MetreFiddlerParams};
+use crate::{prepare_metre_slots, MetreFiddler, MetreFiddlerParams, MetreFiddlerTask};
 use crate::editor::MetreFiddlerEvent::*;
-use crate::gui::metre_input::{MetreAorB, MetreInput};
+use crate::gui::metre_input::{clamp_input_height, MetreAorB, MetreInput};
 use crate::gui::metre_input::MetreAorB::{MetreA, MetreB};
 use crate::gui::param_binding::ParamBinding;
 use crate::gui::param_display_knob::ParamDisplayKnob;
@@ -19,9 +23,22 @@ use crate::gui::param_slider_vertical::{ParamSliderV, ParamSliderVExt};
 use crate::gui::param_slider_vertical::ParamSliderStyle::{Scaled};
 use crate::gui::param_label::{ParamLabel};
 use crate::gui::param_slider_knob::{ParamSliderKnob, ParamSliderKnobExt};
+use crate::gui::bar_display::BarDisplay;
+use crate::gui::metre_overlay::MetreOverlay;
 use crate::gui::param_ticks::ParamTicks;
-use crate::metre::interpolation::interpolation_data::InterpolationData;
+use crate::gui::rqq_tree_view::RqqTreeView;
+use crate::beat_event::BeatEvent;
+use crate::chord::Chord;
+use crate::controller_scaling::CcSelection;
+use crate::pitch_transform::TransposeTable;
+use crate::history::{Edit, SettingToggle};
+use crate::import::{import_midi_file, ImportOptions};
+use crate::metre::combined_metre_data::CombinedMetreData;
 use crate::metre::metre_data::MetreData;
+use crate::metre::rqq_tree::{RqqNode, TreeEdit};
+use crate::params::{NoteOutput, ParamsSnapShot, VelocityMode};
+use crate::preset::{all_presets, save_user_preset, user_preset_dir, Preset, PresetSettings, PresetTarget, USER_CATEGORY};
+use crate::render::{export_dir, MidiExport};
 
 // TODO Click+Alt does not seem to work properly with vizia-plug? it just sometimes detects alt and
 //  sometimes it doesn't. (only on linux)
@@ -54,9 +71,10 @@ const NEW_STYLE: &str = r#"
 pub(crate) struct Data {
     pub(crate) params: Arc<MetreFiddlerParams>,
     pub(crate) screen: MetreFiddlerScreen,
-    pub(crate) settings: Settings,
-    pub(crate) interpolation_data_snapshot: InterpolationData,
+    pub(crate) metre_data_snapshot: CombinedMetreData,
+    pub(crate) metre_switches: usize,
     pub(crate) textbox_expanded: bool,
+    pub(crate) metre_input_height: f32,
     pub(crate) text_input_a: String,
     pub(crate) text_input_b: String,
     pub(crate) display_b: bool,
@@ -65,15 +83,39 @@ pub(crate) struct Data {
     pub(crate) display_metre_validity: bool,
     pub(crate) displayed_position: Arc<AtomicF32>,
     pub(crate) check_for_phase_reset_toggle: bool,   // this is toggled for every frame until the phase_reset button has been reset
+    pub(crate) export_bars: usize,
+    pub(crate) export_message: String,
+    pub(crate) export_dir_input: String,
+    pub(crate) async_executor: AsyncExecutor<MetreFiddler>,
+    pub(crate) presets: Vec<Preset>,
+    pub(crate) preset_name: String,
+    pub(crate) preset_message: String,
+    pub(crate) chord_input: String,
+    pub(crate) transpose_input: String,
+    pub(crate) metric_cc_input: String,
+    pub(crate) import_path: String,
+    pub(crate) import_bar: usize,   // 0 imports the whole file
+    pub(crate) import_quantize: bool,
+    pub(crate) import_velocities: bool,
+    pub(crate) import_message: String,
+    pub(crate) tree_selection: Vec<usize>,
+    pub(crate) tree_message: String,
+    pub(crate) beat_events: Arc<Mutex<Consumer<BeatEvent>>>,
 }
 
-#[derive(Clone, PartialEq, Eq)]
+/// The settings parameters, as far as the Gui shows them.
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub(crate) struct Settings {
     pub(crate) interpolate_durations: bool,
     pub(crate) interpolate_indisp: bool,
     pub(crate) many_velocities: bool,
     pub(crate) midi_out_one_note: bool,
     pub(crate) retain_metric_phase: bool,
+    pub(crate) midi_clock_sync: bool,
+    pub(crate) send_midi_clock: bool,
+    pub(crate) send_group_notes: bool,
+    pub(crate) quantize_to_chord: bool,
+    pub(crate) scale_aftertouch: bool,
 }
 
 impl vizia_plug::vizia::prelude::Data for Settings {
@@ -82,11 +124,36 @@ impl vizia_plug::vizia::prelude::Data for Settings {
     }
 }
 
+impl Settings {
+    fn from_params(params: &Arc<MetreFiddlerParams>) -> Self {
+        Self {
+            interpolate_durations: params.interpolate_durations.value(),
+            interpolate_indisp: params.interpolate_indisp.value(),
+            many_velocities: params.velocity_mode.value() == VelocityMode::Graded,
+            midi_out_one_note: params.note_output.value() == NoteOutput::OnePitch,
+            retain_metric_phase: params.retain_metric_phase.value(),
+            midi_clock_sync: params.midi_clock_sync.value(),
+            send_midi_clock: params.send_midi_clock.value(),
+            send_group_notes: params.send_group_notes.value(),
+            quantize_to_chord: params.quantize_to_chord.value(),
+            scale_aftertouch: params.scale_aftertouch.value(),
+        }
+    }
+}
+
+// The settings are read from the parameters, so the Gui follows their automation.
+fn settings_lens() -> impl Lens<Target = Settings> {
+    Data::params.map(Settings::from_params)
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub(crate) enum MetreFiddlerScreen {
     Main,
     Settings,
     Info,
+    Presets,
+    Tree,
+    Overlay,
 }
 
 impl vizia_plug::vizia::prelude::Data for MetreFiddlerScreen {
@@ -104,77 +171,134 @@ pub(crate) enum MetreFiddlerEvent {
     ToggleManyVelocities,
     ToggleMidiOutput,
     ToggleRetainPhase,
+    ToggleMidiClockSync,
+    ToggleSendMidiClock,
+    ToggleSendGroupNotes,
+    ToggleQuantizeToChord,
+    ToggleScaleAftertouch,
     TriggerPhaseReset,
     RevertPhaseReset,
     ToggleCheckForPhaseReset,
     ToggleAB,
     ShowValidity(bool),
     ExpandTextBox(bool),
+    ResizeMetreInput(f32),
+    SetScale(f64),
+    ChangeExportBars(i32),
+    ExportMidi,
+    SetExportDir(String),
+    LoadPreset(usize, PresetTarget),
+    SetPresetName(String),
+    SavePreset,
+    SetChord(String),
+    SetTransposeTable(String),
+    SetMetricCcs(String),
+    SetImportPath(String),
+    ChangeImportBar(i32),
+    ToggleImportQuantize,
+    ToggleImportVelocities,
+    ImportMidi(MetreAorB),
+    Undo,
+    Redo,
+    SelectTreeNode(Vec<usize>),
+    EditTree(TreeEdit),
+    CheckMetreSwitch,
+    CheckExport,
 }
 
 impl Model for Data {
     fn event(&mut self, cx: &mut EventContext, event: &mut Event) {
         event.map(|my_event, _meta| match my_event {
             UpdateString(new_text, which) => {
-                match MetreData::try_from(new_text.as_str()) {
-                    Ok(new_metre_data) => {
-                        let mut metric_data = self.params.combined_metre_data.lock().unwrap();
-
-                        match which {
-                            MetreA => {
-                                if self.text_input_a != *new_text {
-                                    self.text_input_a = new_text.clone();
-                                }
-                                metric_data.deref_mut().set_metre_a(new_metre_data);
-                            },
-                            MetreB => {
-                                if self.text_input_b != *new_text {
-                                    self.text_input_b = new_text.clone();
-                                }
-                                metric_data.deref_mut().set_metre_b(new_metre_data);
-                            },
-                        }
+                let old_text = {
+                    let metric_data = self.params.combined_metre_data.lock().unwrap();
+                    match which {
+                        MetreA => metric_data.metre_a().string.clone(),
+                        MetreB => metric_data.metre_b().string.clone(),
+                    }
+                };
 
-                        self.max_threshold = metric_data.metre_a().max.max(metric_data.metre_b().max);
-                        self.interpolation_data_snapshot = metric_data.interpolation_data().clone();
-                        self.last_input_is_valid = true;
-                        if self.settings.interpolate_durations {
-                            self.params.current_nr_of_beats.store(metric_data.get_interpolated_durations(self.params.interpolate_a_b.value()).count(), Release);
-                        } else {
-                            self.params.current_nr_of_beats.store(metric_data.get_interleaved_durations(self.params.interpolate_a_b.value()).count(), Release);
-                        }
-                    },
-                    Err(err_string) => {
-                        nih_log!("Failed to parse string: '{}': {}", new_text, err_string);
-                        self.last_input_is_valid = false;
-                    },
+                if self.set_metre_string(new_text, *which) && old_text != *new_text {
+                    self.record_edit(match which {
+                        MetreA => Edit::MetreA { old: old_text, new: new_text.clone() },
+                        MetreB => Edit::MetreB { old: old_text, new: new_text.clone() },
+                    });
                 }
             }
             SetScreen(screen) => {
+                if *screen == MetreFiddlerScreen::Presets {
+                    self.presets = all_presets();
+                }
                 self.screen = *screen;
             }
             ToggleInterpolateDurs => {
-                self.params.interpolate_durations.store(!self.params.interpolate_durations.load(Relaxed), Relaxed);
-                self.settings.interpolate_durations = !self.settings.interpolate_durations;
+                self.toggle_setting(cx, SettingToggle::InterpolateDurations);
             }
             ToggleInterpolateIndisp => {
-                self.params.interpolate_indisp.store(!self.params.interpolate_indisp.load(Relaxed), Relaxed);
-                self.settings.interpolate_indisp = !self.settings.interpolate_indisp;
+                self.toggle_setting(cx, SettingToggle::InterpolateIndisp);
             }
             ToggleManyVelocities => {
-                self.params.many_velocities.store(!self.params.many_velocities.load(Relaxed), Relaxed);
-                self.settings.many_velocities = !self.settings.many_velocities;
+                self.toggle_setting(cx, SettingToggle::ManyVelocities);
             }
             ToggleMidiOutput => {
-                self.params.midi_out_one_note.store(!self.params.midi_out_one_note.load(Relaxed), Relaxed);
-                self.settings.midi_out_one_note = !self.settings.midi_out_one_note;
+                self.toggle_setting(cx, SettingToggle::MidiOutOneNote);
             }
             ToggleRetainPhase => {
-                self.params.retain_metric_phase.store(!self.params.retain_metric_phase.load(Relaxed), Relaxed);
-                self.settings.retain_metric_phase = !self.settings.retain_metric_phase;
+                self.toggle_setting(cx, SettingToggle::RetainMetricPhase);
+            }
+            ToggleMidiClockSync => {
+                self.toggle_setting(cx, SettingToggle::MidiClockSync);
+            }
+            ToggleSendMidiClock => {
+                self.toggle_setting(cx, SettingToggle::SendMidiClock);
+            }
+            ToggleSendGroupNotes => {
+                self.toggle_setting(cx, SettingToggle::SendGroupNotes);
+            }
+            ToggleQuantizeToChord => {
+                self.toggle_setting(cx, SettingToggle::QuantizeToChord);
+            }
+            ToggleScaleAftertouch => {
+                self.toggle_setting(cx, SettingToggle::ScaleAftertouch);
             }
             ToggleAB => {
                 self.display_b = !self.display_b;
+                self.tree_selection.clear();
+                self.record_edit(Edit::SwitchAB);
+            }
+            SelectTreeNode(path) => {
+                self.tree_selection = path.clone();
+                self.tree_message.clear();
+            }
+            EditTree(edit) => {
+                let (text, which) = if self.display_b {
+                    (&self.text_input_b, MetreB)
+                } else {
+                    (&self.text_input_a, MetreA)
+                };
+
+                match RqqNode::parse(text).and_then(|mut tree| {
+                    tree.edit(&self.tree_selection, *edit)?;
+                    Ok(tree)
+                }) {
+                    Ok(tree) => {
+                        self.tree_message.clear();
+                        cx.emit(UpdateString(tree.to_string(), which));
+                    },
+                    Err(err_string) => self.tree_message = err_string,
+                }
+            }
+            Undo => {
+                let edit = self.params.edit_history.lock().unwrap().undo();
+                if let Some(edit) = edit {
+                    self.apply_edit(cx, edit, true);
+                }
+            }
+            Redo => {
+                let edit = self.params.edit_history.lock().unwrap().redo();
+                if let Some(edit) = edit {
+                    self.apply_edit(cx, edit, false);
+                }
             }
             TriggerPhaseReset => {
                 self.params.reset_info.store(true, Release);
@@ -206,10 +330,361 @@ impl Model for Data {
             ExpandTextBox(expand) => {
                 self.textbox_expanded = *expand;
             }
+            SetScale(scale) => {
+                cx.set_user_scale_factor(*scale);
+            }
+            CheckMetreSwitch => {
+                let metre_switches = self.params.metre_switches.load(Acquire);
+                if metre_switches != self.metre_switches {
+                    self.metre_switches = metre_switches;
+                    let params = self.params.clone();
+                    self.update_from_metre_data(&params.combined_metre_data.lock().unwrap());
+                }
+            }
+            ResizeMetreInput(change) => {
+                self.metre_input_height = clamp_input_height(self.metre_input_height + change);
+            }
+            ChangeExportBars(change) => {
+                self.export_bars = (self.export_bars as i32 + change).clamp(1, 999) as usize;
+            }
+            ExportMidi => {
+                // Rendering up to 999 bars takes a while, so it happens in the background.
+                let export = MidiExport {
+                    combined_metre_data: self.params.combined_metre_data.lock().unwrap().clone(),
+                    snapshot: self.params.snapshot(),
+                    nr_of_bars: self.export_bars,
+                    tempo: self.params.host_tempo.load(Relaxed) as f64,
+                };
+                self.export_message = "Exporting...".to_string();
+                self.async_executor.execute_background(MetreFiddlerTask::ExportMidi(Box::new(export)));
+            }
+            SetExportDir(text) => {
+                // An empty folder exports to the home directory.
+                let text = text.trim().to_string();
+                match export_dir(&text) {
+                    Ok(dir) => {
+                        *self.params.export_dir.lock().unwrap() = text.clone();
+                        self.export_message = format!("Exporting to {}", dir.display());
+                        self.export_dir_input = text;
+                    }
+                    Err(err_string) => {
+                        self.export_message = err_string;
+                        self.export_dir_input = self.params.export_dir.lock().unwrap().clone();
+                    }
+                }
+            }
+            CheckExport => {
+                if let Some(message) = self.params.export_message.lock().unwrap().take() {
+                    self.export_message = message;
+                }
+            }
+            LoadPreset(idx, target) => {
+                let Some(preset) = self.presets.get(*idx).cloned() else {
+                    return;
+                };
+                let params = self.params.clone();
+                let mut snapshot = params.snapshot();
+                let mut metric_data = params.combined_metre_data.lock().unwrap();
+                let old_a = metric_data.metre_a().string.clone();
+                let old_b = metric_data.metre_b().string.clone();
+
+                match preset.apply(*target, metric_data.deref_mut(), &mut snapshot) {
+                    Ok(()) => {
+                        if *target == PresetTarget::Everything && preset.settings.is_some() {
+                            self.apply_preset_settings(cx, &snapshot);
+                        }
+                        self.update_from_metre_data(&metric_data);
+                        // Only the metres can be undone, not the settings of the preset.
+                        if metric_data.metre_a().string != old_a {
+                            self.record_edit(Edit::MetreA { old: old_a, new: metric_data.metre_a().string.clone() });
+                        }
+                        if metric_data.metre_b().string != old_b {
+                            self.record_edit(Edit::MetreB { old: old_b, new: metric_data.metre_b().string.clone() });
+                        }
+                        self.display_b = *target == PresetTarget::MetreB;
+                        self.preset_message = format!("Loaded '{}'", preset.name);
+                    },
+                    Err(err_string) => {
+                        nih_log!("Failed to load preset '{}': {}", preset.name, err_string);
+                        self.preset_message = err_string;
+                    },
+                }
+            }
+            SetPresetName(name) => {
+                self.preset_name = name.clone();
+            }
+            SavePreset => {
+                let metric_data = self.params.combined_metre_data.lock().unwrap();
+                let preset = Preset {
+                    name: self.preset_name.trim().to_string(),
+                    category: USER_CATEGORY.to_string(),
+                    metre_a: metric_data.metre_a().string.clone(),
+                    metre_b: Some(metric_data.metre_b().string.clone()),
+                    settings: Some(PresetSettings::from_snapshot(&self.params.snapshot())),
+                };
+                drop(metric_data);
+
+                self.preset_message = match user_preset_dir()
+                    .ok_or_else(|| "No directory for user presets found".to_string())
+                    .and_then(|dir| save_user_preset(&dir, &preset)) {
+                    Ok(path) => {
+                        self.presets = all_presets();
+                        format!("Saved to {}", path.display())
+                    },
+                    Err(err_string) => {
+                        nih_log!("Failed to save preset: {}", err_string);
+                        err_string
+                    },
+                };
+            }
+            SetChord(text) => {
+                // Only lock while copying, the audio thread reads the chord every buffer.
+                let chord = match Chord::try_from(text.as_str()) {
+                    Ok(new_chord) => {
+                        *self.params.chord.lock().unwrap() = new_chord;
+                        new_chord
+                    }
+                    Err(err_string) => {
+                        nih_log!("Failed to set the chord: {}", err_string);
+                        *self.params.chord.lock().unwrap()
+                    }
+                };
+                // Show the chord as it is played, or the last valid one.
+                self.chord_input = chord.to_string();
+            }
+            SetTransposeTable(text) => {
+                let table = match TransposeTable::try_from(text.as_str()) {
+                    Ok(new_table) => {
+                        *self.params.transpose_table.lock().unwrap() = new_table;
+                        new_table
+                    }
+                    Err(err_string) => {
+                        nih_log!("Failed to set the transpose table: {}", err_string);
+                        *self.params.transpose_table.lock().unwrap()
+                    }
+                };
+                self.transpose_input = table.to_string();
+            }
+            SetMetricCcs(text) => {
+                let selection = match CcSelection::try_from(text.as_str()) {
+                    Ok(new_selection) => {
+                        *self.params.metric_ccs.lock().unwrap() = new_selection;
+                        new_selection
+                    }
+                    Err(err_string) => {
+                        nih_log!("Failed to set the metric CCs: {}", err_string);
+                        *self.params.metric_ccs.lock().unwrap()
+                    }
+                };
+                self.metric_cc_input = selection.to_string();
+            }
+            SetImportPath(path) => {
+                self.import_path = path.clone();
+            }
+            ChangeImportBar(change) => {
+                self.import_bar = (self.import_bar as i32 + change).clamp(0, 9999) as usize;
+            }
+            ToggleImportQuantize => {
+                self.import_quantize = !self.import_quantize;
+            }
+            ToggleImportVelocities => {
+                self.import_velocities = !self.import_velocities;
+            }
+            ImportMidi(which) => {
+                let options = ImportOptions {
+                    bar: self.import_bar.checked_sub(1),
+                    // 16th notes
+                    grid: self.import_quantize.then_some(4),
+                    tolerance: 0.0,
+                    velocities_as_weights: self.import_velocities,
+                };
+
+                match import_midi_file(Path::new(self.import_path.trim()), &options) {
+                    Ok(metre) => {
+                        self.import_message = format!("Imported {}", metre.string);
+                        self.display_b = matches!(which, MetreB);
+                        cx.emit(UpdateString(metre.string, *which));
+                    },
+                    Err(err_string) => {
+                        nih_log!("Failed to import MIDI file: {}", err_string);
+                        self.import_message = err_string;
+                    },
+                }
+            }
+        });
+
+        // Ctrl+Z to undo, Ctrl+Shift+Z or Ctrl+Y to redo (Cmd instead of Ctrl on macOS)
+        event.map(|window_event, meta| match window_event {
+            WindowEvent::KeyDown(Code::KeyZ, _) if cx.modifiers().command() => {
+                cx.emit(if cx.modifiers().shift() { Redo } else { Undo });
+                meta.consume();
+            },
+            WindowEvent::KeyDown(Code::KeyY, _) if cx.modifiers().command() => {
+                cx.emit(Redo);
+                meta.consume();
+            },
+            _ => (),
         });
     }
 }
 
+impl Data {
+    /// Parse text and set it as metre A or B. Returns whether the text was a valid metre.
+    fn set_metre_string(&mut self, text: &str, which: MetreAorB) -> bool {
+        let params = self.params.clone();
+        let mut metric_data = params.combined_metre_data.lock().unwrap();
+
+        let result = MetreData::try_from(text)
+            .and_then(|new_metre_data| match which {
+                MetreA => metric_data.deref_mut().set_metre_a(new_metre_data),
+                MetreB => metric_data.deref_mut().set_metre_b(new_metre_data),
+            });
+
+        match result {
+            Ok(()) => {
+                self.update_from_metre_data(&metric_data);
+                drop(metric_data);
+                self.store_in_metre_slot(text, which);
+                true
+            },
+            Err(err_string) => {
+                nih_log!("Failed to parse string: '{}': {}", text, err_string);
+                self.last_input_is_valid = false;
+                false
+            },
+        }
+    }
+
+    /// Keep an edited metre in the selected slot. Metre data that is waiting for the next bar
+    /// is prepared again, so the edit is not lost when the audio thread switches to it.
+    fn store_in_metre_slot(&mut self, text: &str, which: MetreAorB) {
+        let slot_a = (self.params.metre_slot_a.value() - 1) as usize;
+        let slot_b = (self.params.metre_slot_b.value() - 1) as usize;
+        {
+            let mut metre_slots = self.params.metre_slots.lock().unwrap();
+            match which {
+                MetreA => metre_slots.set_slot_a(slot_a, text),
+                MetreB => metre_slots.set_slot_b(slot_b, text),
+            }
+        }
+
+        let is_pending = self.params.pending_metre_data.lock().unwrap().is_some();
+        if is_pending {
+            prepare_metre_slots(&self.params, slot_a, slot_b);
+        }
+    }
+
+    /// Toggle a setting and record it in the edit history.
+    fn toggle_setting(&mut self, cx: &mut EventContext, toggle: SettingToggle) {
+        let old = self.setting_value(toggle);
+        self.set_setting(cx, toggle, !old);
+        self.record_edit(Edit::Setting { toggle, old, new: !old });
+    }
+
+    fn setting_value(&self, toggle: SettingToggle) -> bool {
+        let params = &self.params;
+        match toggle {
+            SettingToggle::InterpolateDurations => params.interpolate_durations.value(),
+            SettingToggle::InterpolateIndisp => params.interpolate_indisp.value(),
+            SettingToggle::ManyVelocities => params.velocity_mode.value() == VelocityMode::Graded,
+            SettingToggle::MidiOutOneNote => params.note_output.value() == NoteOutput::OnePitch,
+            SettingToggle::RetainMetricPhase => params.retain_metric_phase.value(),
+            SettingToggle::MidiClockSync => params.midi_clock_sync.value(),
+            SettingToggle::SendMidiClock => params.send_midi_clock.value(),
+            SettingToggle::SendGroupNotes => params.send_group_notes.value(),
+            SettingToggle::QuantizeToChord => params.quantize_to_chord.value(),
+            SettingToggle::ScaleAftertouch => params.scale_aftertouch.value(),
+        }
+    }
+
+    fn set_setting(&self, cx: &mut EventContext, toggle: SettingToggle, value: bool) {
+        let params = &self.params;
+        match toggle {
+            SettingToggle::InterpolateDurations => set_parameter(cx, &params.interpolate_durations, value),
+            SettingToggle::InterpolateIndisp => set_parameter(cx, &params.interpolate_indisp, value),
+            SettingToggle::ManyVelocities => set_parameter(cx, &params.velocity_mode,
+                if value { VelocityMode::Graded } else { VelocityMode::Accents }),
+            SettingToggle::MidiOutOneNote => set_parameter(cx, &params.note_output,
+                if value { NoteOutput::OnePitch } else { NoteOutput::ByIndispensability }),
+            SettingToggle::RetainMetricPhase => set_parameter(cx, &params.retain_metric_phase, value),
+            SettingToggle::MidiClockSync => set_parameter(cx, &params.midi_clock_sync, value),
+            SettingToggle::SendMidiClock => set_parameter(cx, &params.send_midi_clock, value),
+            SettingToggle::SendGroupNotes => set_parameter(cx, &params.send_group_notes, value),
+            SettingToggle::QuantizeToChord => set_parameter(cx, &params.quantize_to_chord, value),
+            SettingToggle::ScaleAftertouch => set_parameter(cx, &params.scale_aftertouch, value),
+        }
+    }
+
+    fn record_edit(&mut self, edit: Edit) {
+        self.params.edit_history.lock().unwrap().push(edit);
+    }
+
+    /// Revert an edit (undo) or apply it again (redo).
+    fn apply_edit(&mut self, cx: &mut EventContext, edit: Edit, undo: bool) {
+        match edit {
+            Edit::MetreA { old, new } => {
+                self.set_metre_string(if undo { &old } else { &new }, MetreA);
+                self.display_b = false;
+            },
+            Edit::MetreB { old, new } => {
+                self.set_metre_string(if undo { &old } else { &new }, MetreB);
+                self.display_b = true;
+            },
+            Edit::SwitchAB => self.display_b = !self.display_b,
+            Edit::Setting { toggle, old, new } => self.set_setting(cx, toggle, if undo { old } else { new }),
+        }
+    }
+
+    /// Update everything the Gui displays about the metres after they changed.
+    fn update_from_metre_data(&mut self, metric_data: &CombinedMetreData) {
+        if self.text_input_a != metric_data.metre_a().string {
+            self.text_input_a = metric_data.metre_a().string.clone();
+        }
+        if self.text_input_b != metric_data.metre_b().string {
+            self.text_input_b = metric_data.metre_b().string.clone();
+        }
+
+        self.max_threshold = metric_data.metre_a().max.max(metric_data.metre_b().max);
+        self.metre_data_snapshot = metric_data.clone();
+        self.last_input_is_valid = true;
+        if self.params.interpolate_durations.value() {
+            self.params.current_nr_of_beats.store(metric_data.get_interpolated_durations(self.params.interpolate_a_b.value()).count(), Release);
+        } else {
+            self.params.current_nr_of_beats.store(metric_data.get_interleaved_durations(self.params.interpolate_a_b.value()).count(), Release);
+        }
+    }
+
+    /// Set the thresholds, velocities and settings that a preset stores.
+    fn apply_preset_settings(&mut self, cx: &mut EventContext, snapshot: &ParamsSnapShot) {
+        set_parameter(cx, &self.params.lower_threshold, snapshot.lower_threshold);
+        set_parameter(cx, &self.params.upper_threshold, snapshot.upper_threshold);
+        set_parameter(cx, &self.params.threshold_mode, snapshot.threshold_mode);
+        set_parameter(cx, &self.params.velocity_min, snapshot.vel_min);
+        set_parameter(cx, &self.params.velocity_max, snapshot.vel_max);
+        set_parameter(cx, &self.params.velocity_skew, snapshot.vel_skew);
+
+        set_parameter(cx, &self.params.interpolate_durations, snapshot.interpolate_durs);
+        set_parameter(cx, &self.params.interpolate_indisp, snapshot.interpolate_indisp);
+        set_parameter(cx, &self.params.velocity_mode,
+                      if snapshot.many_velocities { VelocityMode::Graded } else { VelocityMode::Accents });
+        set_parameter(cx, &self.params.note_output,
+                      if snapshot.output_one_pitch { NoteOutput::OnePitch } else { NoteOutput::ByIndispensability });
+    }
+}
+
+// Set a parameter from the Gui, so that the host is informed about the change.
+fn set_parameter<P: Param>(cx: &mut EventContext, param: &P, value: P::Plain) {
+    cx.emit(ParamEvent::BeginSetParameter(param).upcast());
+    cx.emit(ParamEvent::SetParameter(param, value).upcast());
+    cx.emit(ParamEvent::EndSetParameter(param).upcast());
+}
+
+/// How often the Gui checks whether the audio thread switched to other metre slots.
+const METRE_SWITCH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The sizes the settings offer, as factors of the default size. The resize handle allows any size.
+const EDITOR_SCALES: [f64; 5] = [0.75, 1.0, 1.25, 1.5, 2.0];
+
 // Makes sense to also define this here, makes it a bit easier to keep track of
 pub(crate) fn default_state() -> Arc<ViziaState> {
     ViziaState::new(|| (500, 350))
@@ -218,25 +693,19 @@ pub(crate) fn default_state() -> Arc<ViziaState> {
 pub(crate) fn create(
     params: Arc<MetreFiddlerParams>,
     editor_state: Arc<ViziaState>,
+    beat_events: Arc<Mutex<Consumer<BeatEvent>>>,
+    async_executor: AsyncExecutor<MetreFiddler>,
 ) -> Option<Box<dyn Editor>> {
     create_vizia_editor(editor_state, ViziaTheming::Custom, move |cx, _| {
         // add new styling
         let _ = cx.add_stylesheet(NEW_STYLE);
 
         let metric_data = params.combined_metre_data.lock().unwrap();
-        let settings = Settings {
-            interpolate_durations: params.interpolate_durations.load(Relaxed),
-            interpolate_indisp: params.interpolate_indisp.load(Relaxed),
-            many_velocities: params.many_velocities.load(Relaxed),
-            midi_out_one_note: params.midi_out_one_note.load(Relaxed),
-            retain_metric_phase: params.retain_metric_phase.load(Relaxed),
-        };
-        
         Data {
             params: params.clone(),
             screen: MetreFiddlerScreen::Main,
-            settings,
-            interpolation_data_snapshot: metric_data.interpolation_data().clone(),
+            metre_data_snapshot: metric_data.clone(),
+            metre_switches: params.metre_switches.load(Acquire),
             max_threshold: metric_data.metre_a().max.max(metric_data.metre_b().max),
             text_input_a: metric_data.metre_a().string.clone(),
             text_input_b: metric_data.metre_b().string.clone(),
@@ -246,6 +715,25 @@ pub(crate) fn create(
             displayed_position: params.displayed_position.clone(),
             check_for_phase_reset_toggle: false,
             textbox_expanded: false,
+            metre_input_height: 120.0,
+            export_bars: 4,
+            export_message: String::new(),
+            export_dir_input: params.export_dir.lock().unwrap().clone(),
+            async_executor: async_executor.clone(),
+            presets: vec![],
+            preset_name: String::new(),
+            preset_message: String::new(),
+            chord_input: params.chord.lock().unwrap().to_string(),
+            transpose_input: params.transpose_table.lock().unwrap().to_string(),
+            metric_cc_input: params.metric_ccs.lock().unwrap().to_string(),
+            import_path: String::new(),
+            import_bar: 1,
+            import_quantize: true,
+            import_velocities: false,
+            import_message: String::new(),
+            tree_selection: vec![],
+            tree_message: String::new(),
+            beat_events: beat_events.clone(),
         }
             .build(cx);
 
@@ -255,6 +743,16 @@ pub(crate) fn create(
             cx.emit(ToggleCheckForPhaseReset);
         });
 
+        // The audio thread switches the metres when the metre slots change, and exports finish
+        // in the background.
+        let timer = cx.add_timer(METRE_SWITCH_INTERVAL, None, |cx, action| {
+            if let TimerAction::Tick(_) = action {
+                cx.emit(CheckMetreSwitch);
+                cx.emit(CheckExport);
+            }
+        });
+        cx.start_timer(timer);
+
         VStack::new(cx, |cx| {
 
             Binding::new(cx, Data::screen, |cx, visible_screen| {
@@ -262,6 +760,9 @@ pub(crate) fn create(
                     MetreFiddlerScreen::Settings => {
                         settings_window(cx);
                     },
+                    MetreFiddlerScreen::Presets => {
+                        presets_window(cx);
+                    },
                     MetreFiddlerScreen::Main => {
                         // Upper Part of the Plugin
                         VStack::new(cx, |cx| {
@@ -271,6 +772,24 @@ pub(crate) fn create(
                         // Lower Part of the Plugin
                         lower_part(cx);
                     }
+                    MetreFiddlerScreen::Tree => {
+                        // Upper Part of the Plugin
+                        VStack::new(cx, |cx| {
+                            tree_window(cx);
+                        })
+                            .height(Stretch(3.0));
+                        // Lower Part of the Plugin
+                        lower_part(cx);
+                    }
+                    MetreFiddlerScreen::Overlay => {
+                        // Upper Part of the Plugin
+                        VStack::new(cx, |cx| {
+                            overlay_window(cx);
+                        })
+                            .height(Stretch(3.0));
+                        // Lower Part of the Plugin
+                        lower_part(cx);
+                    }
                     MetreFiddlerScreen::Info => {
                         // Upper Part of the Plugin
                         VStack::new(cx, |cx| {
@@ -283,12 +802,16 @@ pub(crate) fn create(
                 };
             });
         })
-            // I have no clue, why I have to hardcode this? But without this, the HStacks are
-            // not stretched properly
-          .width(Pixels(500.0));
+            .width(Stretch(1.0));
 
-        // this doesn't work?
-        // ResizeHandle::new(cx).background_color(Color::red());
+        // Dragging the corner resizes the editor. The columns and slider rows stretch with it, and
+        // the size is saved in the editor state.
+        ResizeHandle::new(cx)
+            .position_type(PositionType::Absolute)
+            .right(Pixels(0.0))
+            .bottom(Pixels(0.0))
+            .width(Pixels(16.0))
+            .height(Pixels(16.0));
     })
 }
 
@@ -334,7 +857,7 @@ fn upper_part(cx: &mut Context) {
                     ParamSliderKnob::new(cx, Data::params, |params|
                         &params.velocity_skew)
                         .set_vertical(true);
-                    Binding::new(cx, Data::settings, |cx, settings | {
+                    Binding::new(cx, settings_lens(), |cx, settings | {
                         if settings.get(cx).many_velocities {
                             Label::new(cx, "skew");
                         } else {
@@ -387,39 +910,49 @@ fn upper_part(cx: &mut Context) {
             .alignment(Alignment::Center)
             .width(Stretch(2.0));
 
-        // The Threshold Sliders
+        // The Threshold Sliders, labelled and scaled by the threshold mode
         VStack::new(cx, |cx| {
-            HStack::new(cx, |cx| {
-                Binding::new(cx, Data::max_threshold, |cx, max| {
+            ParamBinding::new(cx, Data::params, |params| &params.threshold_mode, |cx, mode| {
+                let mode = Data::params.get(cx).threshold_mode.preview_plain(mode);
+                let (lower_label, upper_label) = mode.slider_labels();
+                Binding::new(cx, Data::max_threshold, move |cx, max| {
                     let max_val = max.get(cx);
+                    Binding::new(cx, Data::metre_data_snapshot.map(|metric_data| metric_data.max_stratum()), move |cx, max_stratum| {
+                        let factor = mode.slider_scale(max_val, max_stratum.get(cx));
 
-                    Element::new(cx)
-                        .width(Pixels(10.0));
-                    // min thresh
-                    VStack::new(cx, |cx| {
-                        ParamSliderV::new(cx, Data::params, |params|
-                            &params.lower_threshold)
-                            .set_style(Scaled {factor: max_val});
-                        Label::new(cx, "min");
-                    })
-                        .padding_top(Pixels(20.0))
-                        .alignment(Alignment::Center);
-                    // max thresh
-                    VStack::new(cx, |cx| {
-                        ParamSliderV::new(cx, Data::params, |params|
-                            &params.upper_threshold)
-                            .set_style(Scaled { factor: max_val });
-                        Label::new(cx, "max");
-                    })
-                        .padding_top(Pixels(20.0))
-                        .alignment(Alignment::Center);
-                    Element::new(cx)
-                        .width(Pixels(10.0));
+                        HStack::new(cx, |cx| {
+                            Element::new(cx)
+                                .width(Pixels(10.0));
+                            // min thresh
+                            VStack::new(cx, |cx| {
+                                ParamSliderV::new(cx, Data::params, |params|
+                                    &params.lower_threshold)
+                                    .set_style(Scaled { factor });
+                                Label::new(cx, lower_label);
+                            })
+                                .padding_top(Pixels(20.0))
+                                .alignment(Alignment::Center);
+                            // max thresh
+                            VStack::new(cx, |cx| {
+                                ParamSliderV::new(cx, Data::params, |params|
+                                    &params.upper_threshold)
+                                    .set_style(Scaled { factor });
+                                Label::new(cx, upper_label);
+                            })
+                                .padding_top(Pixels(20.0))
+                                .alignment(Alignment::Center);
+                            Element::new(cx)
+                                .width(Pixels(10.0));
+                        });
+                    });
                 });
-            });
-            
+            }).alignment(Alignment::Center);
+
             Label::new(cx, "Thresholds")
-                .font_weight(FontWeightKeyword::Bold)
+                .font_weight(FontWeightKeyword::Bold);
+            ParamSlider::new(cx, Data::params, |params| &params.threshold_mode)
+                .width(Pixels(90.0))
+                .height(Pixels(20.0))
                 .padding_bottom(Pixels(20.0));
         })
             .alignment(Alignment::Center)
@@ -457,7 +990,7 @@ fn duration_position(cx: &mut Context) {
 
                         ParamSlider::new(cx, Data::params, |params|
                             &params.metric_dur_selector)
-                            .width(Pixels(200.0));
+                            .width(Percentage(80.0));
 
                         HStack::new(cx, |cx| {
                             // BPM Toggle
@@ -514,19 +1047,36 @@ fn duration_position(cx: &mut Context) {
             ZStack::new(cx, |cx| {
                 // The ticks on the position bar
                 VStack::new(cx, |cx| {
-                    Binding::new(cx, Data::settings,|cx, settings| {
+                    Binding::new(cx, settings_lens(),|cx, settings| {
+                        let settings = settings.get(cx);
                         ParamBinding::new(
                             cx,
                             Data::params,
                             |params| &params.interpolate_a_b,
                             move |cx, interpolate| {
-                                ParamTicks::new(
-                                    cx,
-                                    200.0,
-                                    Data::interpolation_data_snapshot,
-                                    interpolate,
-                                    settings.get(cx).interpolate_durations)
-                                    .height(Pixels(20.0));
+                                // The thresholds are linear from 0.0 to 1.0, so their normalized
+                                // values are their plain values.
+                                ParamBinding::new(cx, Data::params, |params| &params.lower_threshold, move |cx, lower_threshold| {
+                                    ParamBinding::new(cx, Data::params, |params| &params.upper_threshold, move |cx, upper_threshold| {
+                                        ParamBinding::new(cx, Data::params, |params| &params.threshold_mode, move |cx, threshold_mode| {
+                                            let snapshot = ParamsSnapShot {
+                                                interpolate,
+                                                lower_threshold,
+                                                upper_threshold,
+                                                threshold_mode: Data::params.get(cx).threshold_mode.preview_plain(threshold_mode),
+                                                interpolate_durs: settings.interpolate_durations,
+                                                interpolate_indisp: settings.interpolate_indisp,
+                                                ..ParamsSnapShot::default()
+                                            };
+                                            ParamTicks::new(
+                                                cx,
+                                                Data::metre_data_snapshot,
+                                                snapshot)
+                                                .width(Percentage(80.0))
+                                                .height(Pixels(20.0));
+                                        }).alignment(Alignment::Center);
+                                    }).alignment(Alignment::Center);
+                                }).alignment(Alignment::Center);
                             }).alignment(Alignment::Center);
                     });
                 })
@@ -546,18 +1096,41 @@ fn duration_position(cx: &mut Context) {
                                 Data::displayed_position
                                     .map(|position| position.load(Relaxed)))
                                 .height(Pixels(20.0))
-                                .width(Pixels(200.0));
+                                .width(Percentage(80.0));
                         } else {
                             ParamSliderKnob::new(cx, Data::params, |params|
                                 &params.bar_position)
                                 .height(Pixels(20.0))
-                                .width(Pixels(200.0));
+                                .width(Percentage(80.0));
                         }
                     })
                         .alignment(Alignment::Center);
                 })
                     .alignment(Alignment::Center);
             });
+
+            // The beats of the current bar, flashing when they are triggered
+            Binding::new(cx, settings_lens(), |cx, settings| {
+                let interpolate_durations = settings.get(cx).interpolate_durations;
+                ParamBinding::new(
+                    cx,
+                    Data::params,
+                    |params| &params.interpolate_a_b,
+                    move |cx, interpolate| {
+                        Binding::new(cx, Data::metre_data_snapshot, move |cx, metre_data| {
+                            let durations: Vec<f32> = if interpolate_durations {
+                                metre_data.get(cx).get_interpolated_durations(interpolate).collect()
+                            } else {
+                                metre_data.get(cx).get_interleaved_durations(interpolate).collect()
+                            };
+                            BarDisplay::new(cx, Data::params.get(cx), Data::beat_events.get(cx), &durations)
+                                .width(Percentage(80.0))
+                                .height(Pixels(16.0));
+                        });
+                    })
+                    .alignment(Alignment::Center)
+                    .height(Pixels(24.0));
+            });
         })
             .alignment(Alignment::TopCenter)
             .height(Stretch(0.2));
@@ -572,8 +1145,26 @@ fn lower_part(cx: &mut Context) {
 
         // First Row: Textfield, info and feedback:
         HStack::new(cx, |cx| {
-            // Info Button
-            VStack::new(cx, |cx| {
+            // Overlay, Tree Editor and Info Buttons
+            HStack::new(cx, |cx| {
+                Button::new(cx,
+                            |cx| Label::new(cx, "a/b"))
+                    .on_press(|cx| {
+                        match Data::screen.get(cx) {
+                            MetreFiddlerScreen::Overlay => cx.emit(SetScreen(MetreFiddlerScreen::Main)),
+                            _ => cx.emit(SetScreen(MetreFiddlerScreen::Overlay)),
+                        }
+                    });
+                Element::new(cx).width(Pixels(5.0));
+                Button::new(cx,
+                            |cx| Label::new(cx, "tree"))
+                    .on_press(|cx| {
+                        match Data::screen.get(cx) {
+                            MetreFiddlerScreen::Tree => cx.emit(SetScreen(MetreFiddlerScreen::Main)),
+                            _ => cx.emit(SetScreen(MetreFiddlerScreen::Tree)),
+                        }
+                    });
+                Element::new(cx).width(Pixels(5.0));
                 Button::new(cx,
                             |cx| Label::new(cx, "info"))
                     .on_press(|cx| {
@@ -581,10 +1172,10 @@ fn lower_part(cx: &mut Context) {
                             MetreFiddlerScreen::Info => cx.emit(SetScreen(MetreFiddlerScreen::Main)),
                             _ => cx.emit(SetScreen(MetreFiddlerScreen::Info)),
                         }
-                    })
-                    .position_type(PositionType::Absolute)
-                    .right(Pixels(10.0));
-            });
+                    });
+                Element::new(cx).width(Pixels(10.0));
+            })
+                .alignment(Alignment::Right);
 
             // Metre Input for A or B
             VStack::new(cx, |cx| {
@@ -593,19 +1184,21 @@ fn lower_part(cx: &mut Context) {
                         if expanded.get(cx) {
                             Popup::new(cx, |cx| {
                                 if display.get(cx) {
-                                    MetreInput::new(cx, Data::text_input_b, MetreB);
+                                    MetreInput::new(cx, Data::text_input_b, MetreB, true)
+                                        .navigable(true);
                                 } else {
-                                    MetreInput::new(cx, Data::text_input_a, MetreA);
+                                    MetreInput::new(cx, Data::text_input_a, MetreA, true)
+                                        .navigable(true);
                                 }
                             })
                                 .lock_focus_to_within() // automatically move into popup textbox
                                 .placement(Placement::Over)
                                 .background_color(Color::yellowgreen())
-                                .height(Pixels(75.0)); // TODO adjust size or add scrollable view in future?
+                                .height(Data::metre_input_height.map(|height| Pixels(*height)));
                         } else if display.get(cx) {
-                            MetreInput::new(cx, Data::text_input_b, MetreB);
+                            MetreInput::new(cx, Data::text_input_b, MetreB, false);
                         } else {
-                                MetreInput::new(cx, Data::text_input_a, MetreA);
+                            MetreInput::new(cx, Data::text_input_a, MetreA, false);
                         }
                     });
                 });
@@ -659,6 +1252,21 @@ fn lower_part(cx: &mut Context) {
                                 cx.emit(ToggleAB)
                             })
                             .alignment(Alignment::Center);
+
+                        Element::new(cx).width(Pixels(5.0));
+
+                        // The slot of the displayed metre
+                        Label::new(cx, "slot").alignment(Alignment::Center);
+                        Element::new(cx).width(Pixels(3.0));
+                        if display.get(cx) {
+                            ParamSlider::new(cx, Data::params, |params| &params.metre_slot_b)
+                                .width(Pixels(32.0))
+                                .height(Pixels(24.0));
+                        } else {
+                            ParamSlider::new(cx, Data::params, |params| &params.metre_slot_a)
+                                .width(Pixels(32.0))
+                                .height(Pixels(24.0));
+                        }
                     });
 
                     Element::new(cx).width(Pixels(10.0));
@@ -683,8 +1291,12 @@ fn lower_part(cx: &mut Context) {
                     .alignment(Alignment::Center)
                     .width(Stretch(3.0));
 
-                // Settings
+                // Presets and Settings
                 HStack::new(cx, |cx| {
+                    Button::new(cx, |cx| Label::new(cx, "presets"))
+                        .on_press(|cx| cx.emit(SetScreen(MetreFiddlerScreen::Presets)));
+                    Element::new(cx)
+                        .width(Pixels(10.0));
                     ZStack::new(cx, |cx| {
                         Svg::new(cx, ICON_SETTINGS).width(Stretch(1.0)).height(Stretch(1.0));
                     })
@@ -719,7 +1331,7 @@ fn settings_window(cx: &mut Context) {
 
     // Settings
     ScrollView::new(cx, |cx| {
-        Binding::new(cx, Data::settings, |cx, settings| {
+        Binding::new(cx, settings_lens(), |cx, settings| {
             VStack::new(cx, |cx| {
                 settings_button(cx, settings.get(cx).interpolate_durations, "Interpolate Durations".to_string(), ToggleInterpolateDurs);
                 settings_divider(cx);
@@ -730,6 +1342,34 @@ fn settings_window(cx: &mut Context) {
                 settings_button(cx, !settings.get(cx).midi_out_one_note, "Send different Pitches According to Indispensability".to_string(), ToggleMidiOutput);
                 settings_divider(cx);
                 settings_button(cx, settings.get(cx).retain_metric_phase, "Retain Metric Phase when changing \nMetric Duration during Playback".to_string(), ToggleRetainPhase);
+                settings_divider(cx);
+                settings_button(cx, settings.get(cx).midi_clock_sync, "Sync to incoming MIDI Clock and Song\nPosition instead of the Host (CLAP only)".to_string(), ToggleMidiClockSync);
+                settings_divider(cx);
+                settings_button(cx, settings.get(cx).send_midi_clock, "Send MIDI Clock following the Metric\nDuration (CLAP only)".to_string(), ToggleSendMidiClock);
+                settings_divider(cx);
+                settings_button(cx, settings.get(cx).send_group_notes, "Send a Note at every Group Start, \nhigher Strata get higher Pitches".to_string(), ToggleSendGroupNotes);
+                settings_divider(cx);
+                marker_row(cx);
+                settings_divider(cx);
+                chord_row(cx);
+                settings_divider(cx);
+                transpose_row(cx);
+                settings_divider(cx);
+                timing_row(cx);
+                settings_divider(cx);
+                ratchet_row(cx);
+                settings_divider(cx);
+                settings_button(cx, settings.get(cx).quantize_to_chord, "Quantize Incoming Notes to the Chord".to_string(), ToggleQuantizeToChord);
+                settings_divider(cx);
+                metric_cc_row(cx);
+                settings_divider(cx);
+                settings_button(cx, settings.get(cx).scale_aftertouch, "Scale Incoming Aftertouch like the Metric CCs".to_string(), ToggleScaleAftertouch);
+                settings_divider(cx);
+                export_row(cx);
+                settings_divider(cx);
+                import_row(cx);
+                settings_divider(cx);
+                size_row(cx);
             });
         })
     })
@@ -753,6 +1393,177 @@ fn settings_window(cx: &mut Context) {
         .height(Pixels(60.0));
 }
 
+// Edit the displayed metre as nested boxes instead of text.
+fn tree_window(cx: &mut Context) {
+    Element::new(cx)
+        .background_color(RGBA::rgba(250, 250, 250, 255))
+        .height(Pixels(10.0));
+
+    HStack::new(cx, |cx| {
+        Element::new(cx).width(Pixels(10.0));
+        Binding::new(cx, Data::display_b, |cx, display_b| {
+            VStack::new(cx, |cx| {
+                if display_b.get(cx) {
+                    Label::new(cx, "Metre B")
+                        .font_weight(FontWeightKeyword::Bold)
+                        .height(Pixels(20.0));
+                    RqqTreeView::new(cx, Data::text_input_b, Data::tree_selection)
+                        .height(Stretch(1.0));
+                } else {
+                    Label::new(cx, "Metre A")
+                        .font_weight(FontWeightKeyword::Bold)
+                        .height(Pixels(20.0));
+                    RqqTreeView::new(cx, Data::text_input_a, Data::tree_selection)
+                        .height(Stretch(1.0));
+                }
+            });
+        });
+        Element::new(cx).width(Pixels(10.0));
+    })
+        .height(Stretch(1.0));
+
+    // Edits of the selected beat or group
+    HStack::new(cx, |cx| {
+        Element::new(cx).width(Pixels(10.0));
+        for (label, edit) in [
+            ("Split 2", TreeEdit::Split(2)),
+            ("Split 3", TreeEdit::Split(3)),
+            ("Merge", TreeEdit::MergeWithNext),
+            ("Group", TreeEdit::GroupWithNext),
+            ("Ungroup", TreeEdit::Ungroup),
+            ("Refine", TreeEdit::Refine),
+        ] {
+            Button::new(cx, move |cx| Label::new(cx, label))
+                .on_press(move |cx| cx.emit(EditTree(edit)));
+            Element::new(cx).width(Pixels(4.0));
+        }
+    })
+        .height(Pixels(32.0));
+
+    HStack::new(cx, |cx| {
+        Element::new(cx).width(Pixels(10.0));
+        Label::new(cx, Data::tree_message.map(|message| {
+            if message.is_empty() {
+                String::from("Click to select, drag sideways to resize against the next beat.")
+            } else {
+                message.clone()
+            }
+        }))
+            .font_size(11.0);
+    })
+        .height(Pixels(20.0));
+}
+
+fn overlay_window(cx: &mut Context) {
+    Element::new(cx)
+        .background_color(RGBA::rgba(250, 250, 250, 255))
+        .height(Pixels(10.0));
+
+    HStack::new(cx, |cx| {
+        Element::new(cx).width(Pixels(10.0));
+        VStack::new(cx, |cx| {
+            Label::new(cx, "Metre A")
+                .font_weight(FontWeightKeyword::Bold)
+                .height(Pixels(20.0));
+            ParamBinding::new(
+                cx,
+                Data::params,
+                |params| &params.interpolate_a_b,
+                |cx, interpolate| {
+                    MetreOverlay::new(cx, Data::metre_data_snapshot, interpolate)
+                        .width(Stretch(1.0))
+                        .height(Stretch(1.0));
+                })
+                .height(Stretch(1.0));
+            Label::new(cx, "Metre B")
+                .font_weight(FontWeightKeyword::Bold)
+                .height(Pixels(20.0));
+        });
+        Element::new(cx).width(Pixels(10.0));
+    })
+        .height(Stretch(1.0));
+}
+
+fn presets_window(cx: &mut Context) {
+    Element::new(cx).height(Pixels(25.0));
+    Label::new(cx, "Presets")
+        .alignment(Alignment::Center)
+        .width(Stretch(1.0))
+        .font_family(vec![FamilyOwned::Named(String::from(NOTO_SANS))])
+        .font_weight(FontWeightKeyword::Thin)
+        .font_size(40.0)
+        .height(Pixels(50.0));
+
+    Element::new(cx).height(Pixels(5.0));
+
+    // Factory and User Presets, grouped by category
+    ScrollView::new(cx, |cx| {
+        Binding::new(cx, Data::presets, |cx, presets| {
+            VStack::new(cx, |cx| {
+                let presets = presets.get(cx);
+                let mut category = String::new();
+                for (idx, preset) in presets.iter().enumerate() {
+                    if preset.category != category {
+                        category = preset.category.clone();
+                        HStack::new(cx, |cx| {
+                            Element::new(cx).width(Pixels(48.0));
+                            Label::new(cx, category.clone())
+                                .font_weight(FontWeightKeyword::Bold);
+                        })
+                            .alignment(Alignment::BottomLeft)
+                            .height(Pixels(30.0));
+                    }
+                    preset_row(cx, idx, preset);
+                }
+            });
+        });
+    })
+        .width(Stretch(1.0))
+        .height(Stretch(1.0));
+
+    // Save the current metres and settings as a User Preset
+    HStack::new(cx, |cx| {
+        Element::new(cx).width(Pixels(48.0));
+        Textbox::new(cx, Data::preset_name)
+            .on_edit(|cx, text| cx.emit(SetPresetName(text)))
+            .on_submit(|cx, text, _| cx.emit(SetPresetName(text)))
+            .width(Pixels(150.0));
+        Element::new(cx).width(Pixels(10.0));
+        Button::new(cx, |cx| Label::new(cx, "Save"))
+            .on_press(|cx| cx.emit(SavePreset));
+        Element::new(cx).width(Pixels(10.0));
+        Button::new(cx, |cx| Label::new(cx, "back"))
+            .on_press(|cx| cx.emit(SetScreen(MetreFiddlerScreen::Main)));
+    })
+        .alignment(Alignment::Left)
+        .height(Pixels(35.0));
+
+    HStack::new(cx, |cx| {
+        Element::new(cx).width(Pixels(48.0));
+        Label::new(cx, Data::preset_message)
+            .font_size(11.0);
+    })
+        .alignment(Alignment::Left)
+        .height(Pixels(25.0));
+}
+
+// Buttons to load a preset into A, B or everything, and its name.
+fn preset_row(cx: &mut Context, idx: usize, preset: &Preset) {
+    let name = preset.name.clone();
+    HStack::new(cx, move |cx| {
+        Element::new(cx).width(Pixels(48.0));
+        for (label, target) in [("A", PresetTarget::MetreA), ("B", PresetTarget::MetreB), ("All", PresetTarget::Everything)] {
+            Button::new(cx, move |cx| Label::new(cx, label))
+                .on_press(move |cx| cx.emit(LoadPreset(idx, target)))
+                .width(Pixels(40.0));
+        }
+        Element::new(cx).width(Pixels(24.0));
+        Label::new(cx, name.clone());
+    })
+        .alignment(Alignment::Left)
+        .height(Pixels(28.0));
+}
+
 fn settings_button(cx: &mut Context, is_on: bool, label: String, event: MetreFiddlerEvent) {
     HStack::new(cx, move |cx| {
         Element::new(cx).width(Pixels(48.0));
@@ -772,6 +1583,239 @@ fn settings_button(cx: &mut Context, is_on: bool, label: String, event: MetreFid
         .alignment(Alignment::Left);
 }
 
+// Render the current pattern (as Send Midi would play it) into a MIDI file.
+fn export_row(cx: &mut Context) {
+    HStack::new(cx, |cx| {
+        Element::new(cx).width(Pixels(48.0));
+        Button::new(cx, |cx| Label::new(cx, "Export"))
+            .width(Pixels(50.0))
+            .on_press(|cx| cx.emit(ExportMidi));
+        Element::new(cx).width(Pixels(24.0));
+        Button::new(cx, |cx| Label::new(cx, "-"))
+            .on_press(|cx| cx.emit(ChangeExportBars(-1)));
+        Label::new(cx, Data::export_bars.map(|bars| format!(" {} Bars ", bars)));
+        Button::new(cx, |cx| Label::new(cx, "+"))
+            .on_press(|cx| cx.emit(ChangeExportBars(1)));
+        Element::new(cx).width(Pixels(12.0));
+        Label::new(cx, "as MIDI File");
+    })
+        .alignment(Alignment::Left);
+
+    HStack::new(cx, |cx| {
+        Element::new(cx).width(Pixels(48.0));
+        Label::new(cx, "Folder");
+        Element::new(cx).width(Pixels(12.0));
+        Textbox::new(cx, Data::export_dir_input)
+            .on_submit(|cx, text, _| cx.emit(SetExportDir(text)))
+            .width(Stretch(1.0));
+        Element::new(cx).width(Pixels(48.0));
+    })
+        .alignment(Alignment::Left)
+        .height(Pixels(35.0));
+
+    HStack::new(cx, |cx| {
+        Element::new(cx).width(Pixels(48.0));
+        Label::new(cx, Data::export_message)
+            .font_size(11.0);
+    })
+        .alignment(Alignment::Left)
+        .height(Pixels(20.0));
+}
+
+// How the start of a bar is marked, and the lowest note for group starts.
+fn marker_row(cx: &mut Context) {
+    HStack::new(cx, |cx| {
+        Element::new(cx).width(Pixels(48.0));
+        Label::new(cx, "Bar Start");
+        Element::new(cx).width(Pixels(12.0));
+        ParamSlider::new(cx, Data::params, |params| &params.bar_marker)
+            .width(Pixels(80.0));
+        Element::new(cx).width(Pixels(6.0));
+        ParamSlider::new(cx, Data::params, |params| &params.bar_marker_number)
+            .width(Pixels(60.0));
+        Element::new(cx).width(Pixels(12.0));
+        Label::new(cx, "Group Notes from");
+        Element::new(cx).width(Pixels(6.0));
+        ParamSlider::new(cx, Data::params, |params| &params.group_note_base)
+            .width(Pixels(60.0));
+    })
+        .alignment(Alignment::Left)
+        .height(Pixels(32.0));
+}
+
+// Send Midi can play a chord, given as intervals in semitones above the root.
+fn chord_row(cx: &mut Context) {
+    HStack::new(cx, |cx| {
+        Element::new(cx).width(Pixels(48.0));
+        Label::new(cx, "Chord");
+        Element::new(cx).width(Pixels(12.0));
+        ParamSlider::new(cx, Data::params, |params| &params.chord_mode)
+            .width(Pixels(80.0));
+        Element::new(cx).width(Pixels(6.0));
+        Textbox::new(cx, Data::chord_input)
+            .on_submit(|cx, text, _| cx.emit(SetChord(text)))
+            .width(Pixels(120.0));
+        Element::new(cx).width(Pixels(12.0));
+        Label::new(cx, "Root");
+        Element::new(cx).width(Pixels(6.0));
+        ParamSlider::new(cx, Data::params, |params| &params.chord_root)
+            .width(Pixels(60.0));
+    })
+        .alignment(Alignment::Left)
+        .height(Pixels(32.0));
+}
+
+// Incoming notes can be transposed by the rank or stratum of their position.
+fn transpose_row(cx: &mut Context) {
+    HStack::new(cx, |cx| {
+        Element::new(cx).width(Pixels(48.0));
+        Label::new(cx, "Transpose");
+        Element::new(cx).width(Pixels(12.0));
+        ParamSlider::new(cx, Data::params, |params| &params.transpose_mode)
+            .width(Pixels(100.0));
+        Element::new(cx).width(Pixels(6.0));
+        Textbox::new(cx, Data::transpose_input)
+            .on_submit(|cx, text, _| cx.emit(SetTransposeTable(text)))
+            .width(Pixels(160.0));
+    })
+        .alignment(Alignment::Left)
+        .height(Pixels(32.0));
+}
+
+// Incoming notes on strong and weak beats can be moved in time, in milliseconds.
+fn timing_row(cx: &mut Context) {
+    HStack::new(cx, |cx| {
+        Element::new(cx).width(Pixels(48.0));
+        Label::new(cx, "Timing Strong");
+        Element::new(cx).width(Pixels(12.0));
+        ParamSlider::new(cx, Data::params, |params| &params.timing_strong)
+            .width(Pixels(80.0));
+        Element::new(cx).width(Pixels(12.0));
+        Label::new(cx, "Weak");
+        Element::new(cx).width(Pixels(6.0));
+        ParamSlider::new(cx, Data::params, |params| &params.timing_weak)
+            .width(Pixels(80.0));
+    })
+        .alignment(Alignment::Left)
+        .height(Pixels(32.0));
+}
+
+// Beats up to the ratchet threshold are repeated, evenly or like the group they belong to.
+fn ratchet_row(cx: &mut Context) {
+    HStack::new(cx, |cx| {
+        Element::new(cx).width(Pixels(48.0));
+        Label::new(cx, "Ratchet");
+        Element::new(cx).width(Pixels(12.0));
+        ParamSlider::new(cx, Data::params, |params| &params.ratchet_mode)
+            .width(Pixels(80.0));
+        Element::new(cx).width(Pixels(6.0));
+        ParamSlider::new(cx, Data::params, |params| &params.ratchet_threshold)
+            .width(Pixels(60.0));
+        Element::new(cx).width(Pixels(12.0));
+        Label::new(cx, "Repeats");
+        Element::new(cx).width(Pixels(6.0));
+        ParamSlider::new(cx, Data::params, |params| &params.ratchet_repeats)
+            .width(Pixels(50.0));
+        Element::new(cx).width(Pixels(12.0));
+        Label::new(cx, "Ramp");
+        Element::new(cx).width(Pixels(6.0));
+        ParamSlider::new(cx, Data::params, |params| &params.ratchet_ramp)
+            .width(Pixels(60.0));
+    })
+        .alignment(Alignment::Left)
+        .height(Pixels(32.0));
+}
+
+// Incoming values of these CC numbers are lowered on weak beats, by up to the depth.
+fn metric_cc_row(cx: &mut Context) {
+    HStack::new(cx, |cx| {
+        Element::new(cx).width(Pixels(48.0));
+        Label::new(cx, "Metric CCs");
+        Element::new(cx).width(Pixels(12.0));
+        Textbox::new(cx, Data::metric_cc_input)
+            .on_submit(|cx, text, _| cx.emit(SetMetricCcs(text)))
+            .width(Pixels(120.0));
+        Element::new(cx).width(Pixels(12.0));
+        Label::new(cx, "Depth");
+        Element::new(cx).width(Pixels(6.0));
+        ParamSlider::new(cx, Data::params, |params| &params.metric_cc_depth)
+            .width(Pixels(80.0));
+    })
+        .alignment(Alignment::Left)
+        .height(Pixels(32.0));
+}
+
+// Scale the whole editor to one of a few sizes.
+fn size_row(cx: &mut Context) {
+    HStack::new(cx, |cx| {
+        Element::new(cx).width(Pixels(48.0));
+        Label::new(cx, "Size");
+        Element::new(cx).width(Pixels(12.0));
+        for scale in EDITOR_SCALES {
+            Button::new(cx, move |cx| Label::new(cx, format!("{}%", (scale * 100.0).round())))
+                .on_press(move |cx| cx.emit(SetScale(scale)));
+            Element::new(cx).width(Pixels(4.0));
+        }
+    })
+        .alignment(Alignment::Left)
+        .height(Pixels(32.0));
+}
+
+// Import the onsets of a MIDI file as metre A or B.
+fn import_row(cx: &mut Context) {
+    HStack::new(cx, |cx| {
+        Element::new(cx).width(Pixels(48.0));
+        Textbox::new(cx, Data::import_path)
+            .on_edit(|cx, text| cx.emit(SetImportPath(text)))
+            .on_submit(|cx, text, _| cx.emit(SetImportPath(text)))
+            .width(Stretch(1.0));
+        Element::new(cx).width(Pixels(12.0));
+        Button::new(cx, |cx| Label::new(cx, "Import as A"))
+            .on_press(|cx| cx.emit(ImportMidi(MetreA)));
+        Element::new(cx).width(Pixels(6.0));
+        Button::new(cx, |cx| Label::new(cx, "as B"))
+            .on_press(|cx| cx.emit(ImportMidi(MetreB)));
+        Element::new(cx).width(Pixels(48.0));
+    })
+        .alignment(Alignment::Left)
+        .height(Pixels(35.0));
+
+    HStack::new(cx, |cx| {
+        Element::new(cx).width(Pixels(48.0));
+        Button::new(cx, |cx| Label::new(cx, "-"))
+            .on_press(|cx| cx.emit(ChangeImportBar(-1)));
+        Label::new(cx, Data::import_bar.map(|bar| {
+            if *bar == 0 { String::from(" Whole File ") } else { format!(" Bar {} ", bar) }
+        }));
+        Button::new(cx, |cx| Label::new(cx, "+"))
+            .on_press(|cx| cx.emit(ChangeImportBar(1)));
+        Element::new(cx).width(Pixels(12.0));
+        Binding::new(cx, Data::import_quantize, |cx, quantize| {
+            Button::new(cx, |cx| Label::new(cx, "16ths"))
+                .class("red_button")
+                .checked(quantize.get(cx))
+                .on_press(|cx| cx.emit(ToggleImportQuantize));
+        });
+        Element::new(cx).width(Pixels(6.0));
+        Binding::new(cx, Data::import_velocities, |cx, velocities| {
+            Button::new(cx, |cx| Label::new(cx, "Velocities as Weights"))
+                .class("red_button")
+                .checked(velocities.get(cx))
+                .on_press(|cx| cx.emit(ToggleImportVelocities));
+        });
+    })
+        .alignment(Alignment::Left)
+        .height(Pixels(35.0));
+
+    HStack::new(cx, |cx| {
+        Element::new(cx).width(Pixels(48.0));
+        Label::new(cx, Data::import_message)
+            .font_size(11.0);
+    })
+        .alignment(Alignment::Left)
+        .height(Pixels(20.0));
+}
+
 fn settings_divider(cx: &mut Context) {
     HStack::new(cx, |cx| {
         Element::new(cx).width(Pixels(150.0));
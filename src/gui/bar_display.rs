@@ -0,0 +1,127 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::Ordering::Relaxed;
+use std::time::{Duration, Instant};
+use rtrb::Consumer;
+use vizia_plug::vizia::prelude::*;
+use vizia_plug::vizia::vg;
+use crate::beat_event::BeatEvent;
+use crate::params::MetreFiddlerParams;
+use crate::util::get_start_times;
+
+/// How often the playhead moves and the beat events are read.
+const FRAME_INTERVAL: Duration = Duration::from_millis(16);
+/// How long a triggered beat stays lit.
+const FLASH_DURATION: Duration = Duration::from_millis(300);
+
+enum BarDisplayEvent {
+    Frame,
+}
+
+/// One bar with a box for every beat and a running playhead. Every beat flashes when it is
+/// triggered: red, as dark as its velocity, when it passed the thresholds and grey otherwise.
+pub struct BarDisplay {
+    params: Arc<MetreFiddlerParams>,
+    beat_events: Arc<Mutex<Consumer<BeatEvent>>>,
+    /// The start time and duration of every beat.
+    beats: Vec<(f32, f32)>,
+    /// The last event of every beat and when it arrived.
+    flashes: Vec<Option<(BeatEvent, Instant)>>,
+    position: f32,
+}
+
+impl BarDisplay {
+    pub fn new(
+        cx: &mut Context,
+        params: Arc<MetreFiddlerParams>,
+        beat_events: Arc<Mutex<Consumer<BeatEvent>>>,
+        durations: &[f32],
+    ) -> Handle<'_, Self> {
+        Self {
+            params,
+            beat_events,
+            beats: get_start_times(durations).into_iter().zip(durations.iter().copied()).collect(),
+            flashes: vec![None; durations.len()],
+            position: 0.0,
+        }
+            .build(cx, |cx| {
+                let timer = cx.add_timer(FRAME_INTERVAL, None, |cx, action| {
+                    if let TimerAction::Tick(_) = action {
+                        cx.emit(BarDisplayEvent::Frame);
+                    }
+                });
+                cx.start_timer(timer);
+            })
+            .hoverable(false)
+    }
+
+    fn read_beat_events(&mut self) {
+        // The Gui is the only consumer, so this never waits for the audio thread.
+        let Ok(mut beat_events) = self.beat_events.try_lock() else {
+            return;
+        };
+        while let Ok(beat) = beat_events.pop() {
+            if let Some(flash) = self.flashes.get_mut(beat.beat_idx) {
+                *flash = Some((beat, Instant::now()));
+            }
+        }
+    }
+}
+
+impl View for BarDisplay {
+    fn element(&self) -> Option<&'static str> {
+        Some("bar-display")
+    }
+
+    fn event(&mut self, cx: &mut EventContext, event: &mut Event) {
+        event.map(|bar_event, meta| match bar_event {
+            BarDisplayEvent::Frame => {
+                self.read_beat_events();
+                self.position = if self.params.use_position.value() {
+                    self.params.bar_position.value()
+                } else {
+                    self.params.displayed_position.load(Relaxed)
+                };
+                cx.needs_redraw();
+                meta.consume();
+            },
+        });
+    }
+
+    fn draw(&self, cx: &mut DrawContext, canvas: &vg::Canvas) {
+        let bounds = cx.bounds();
+        let scale = cx.scale_factor();
+        let now = Instant::now();
+
+        let mut fill = vg::Paint::default();
+        fill.set_anti_alias(true);
+        let mut stroke = vg::Paint::default();
+        stroke.set_anti_alias(true);
+        stroke.set_style(vg::PaintStyle::Stroke);
+        stroke.set_stroke_width(scale);
+        stroke.set_color(vg::Color::BLACK);
+
+        for (&(start, duration), flash) in self.beats.iter().zip(&self.flashes) {
+            let rect = vg::Rect::from_xywh(bounds.x + start * bounds.w, bounds.y, duration * bounds.w, bounds.h);
+
+            if let Some((beat, time)) = flash {
+                let age = now.duration_since(*time).as_secs_f32() / FLASH_DURATION.as_secs_f32();
+                if age < 1.0 {
+                    let alpha = 1.0 - age;
+                    let color = if beat.passed {
+                        vg::Color::from_argb((alpha * (64.0 + 191.0 * beat.velocity)) as u8, 172, 53, 53)
+                    } else {
+                        vg::Color::from_argb((alpha * 64.0) as u8, 0, 0, 0)
+                    };
+                    fill.set_color(color);
+                    canvas.draw_rect(rect, &fill);
+                }
+            }
+            canvas.draw_rect(rect, &stroke);
+        }
+
+        // The playhead
+        let x = bounds.x + self.position.clamp(0.0, 1.0) * bounds.w;
+        stroke.set_stroke_width(2.0 * scale);
+        canvas.draw_line((x, bounds.y), (x, bounds.bottom()), &stroke);
+    }
+}
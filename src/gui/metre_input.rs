@@ -1,9 +1,28 @@
-use nih_plug::nih_log;
 use vizia_plug::vizia::prelude::*;
+use vizia_plug::widgets::util::ModifiersExt;
 use crate::editor::MetreFiddlerEvent;
+use crate::metre::rqq_syntax::{find_syntax_errors, format_rqq, matching_bracket, tokenize, RqqToken, RqqTokenKind};
 
+/// The format button wraps lists that are longer than this many characters.
+const FORMAT_WIDTH: usize = 40;
+const LINE_HEIGHT: f32 = 16.0;
+/// The colors of brackets and numbers, by the depth of their list.
+const DEPTH_COLORS: [(u8, u8, u8); 5] = [(0, 0, 0), (172, 53, 53), (53, 101, 172), (46, 130, 70), (150, 105, 30)];
+/// Dragging the resize handle can't make the expanded input smaller or larger than this.
+const MIN_HEIGHT: f32 = 60.0;
+const MAX_HEIGHT: f32 = 400.0;
+
+/// A code editor for RQQ strings. Brackets and numbers are colored by their depth, the bracket
+/// next to the cursor is shown with its partner, and problems are marked where they are.
+/// Collapsed, it shows the metre on one line and expands when it is clicked or typed into.
 #[derive(Lens)]
-pub struct MetreInput {}
+pub struct MetreInput {
+    text: String,
+    /// The byte index of the cursor in text.
+    cursor: usize,
+    expanded: bool,
+    which: MetreAorB,
+}
 
 #[derive(Clone, Copy, Debug)]
 pub enum MetreAorB {
@@ -11,54 +30,329 @@ pub enum MetreAorB {
     MetreB,
 }
 
+#[derive(Clone, Copy, Debug)]
+enum MetreInputEvent {
+    SetCursor(usize),
+    Format,
+}
+
 impl MetreInput {
     pub fn new<L>(
         cx: &mut Context,
         text_data: L,
         which: MetreAorB,
+        expanded: bool,
     ) -> Handle<'_, Self>
     where
         L: Lens<Target = String>,
     {
-        Self {}
-            .build(cx,|cx| {
-                Textbox::new_multiline(cx, text_data, false)
-                    .on_edit(|cx, _| {
-                        cx.emit(MetreFiddlerEvent::ShowValidity(false))
-                    })
-                    .on_submit(move |cx, text, _| {
-                        cx.emit(MetreFiddlerEvent::ExpandTextBox(false));
-                        cx.emit(MetreFiddlerEvent::ShowValidity(true));
-                        cx.emit(MetreFiddlerEvent::UpdateString(text, which));
-                    })
-                    .height(Stretch(1.0))
-                    .width(Stretch(3.0));
+        let text = text_data.get(cx);
+        Self {
+            cursor: text.len(),
+            text,
+            expanded,
+            which,
+        }
+            .build(cx, move |cx| {
+                if expanded {
+                    VStack::new(cx, |cx| {
+                        ScrollView::new(cx, |cx| {
+                            Binding::new(cx, MetreInput::text, |cx, text| {
+                                Binding::new(cx, MetreInput::cursor, move |cx, cursor| {
+                                    Self::lines(cx, &text.get(cx), Some(cursor.get(cx)));
+                                });
+                            });
+                        })
+                            .height(Stretch(1.0));
+
+                        // The first problem, formatting and resizing
+                        HStack::new(cx, |cx| {
+                            Label::new(cx, MetreInput::text.map(|text| {
+                                find_syntax_errors(text).first()
+                                    .map(|error| error.message.clone())
+                                    .unwrap_or_default()
+                            }))
+                                .font_size(11.0)
+                                .width(Stretch(1.0));
+                            ClickCell::new(cx, MetreInputEvent::Format, |cx| {
+                                Label::new(cx, "format")
+                                    .font_size(11.0)
+                                    .hoverable(false);
+                            })
+                                .width(Auto)
+                                .cursor(CursorIcon::Hand);
+                            Element::new(cx).width(Pixels(8.0));
+                            ResizeHandle::new(cx)
+                                .width(Pixels(16.0))
+                                .height(Pixels(LINE_HEIGHT));
+                        })
+                            .height(Pixels(20.0));
+                    });
+                } else {
+                    // Follow the metre when it changes elsewhere, e.g. through undo or presets.
+                    ScrollView::new(cx, |cx| {
+                        Binding::new(cx, text_data, |cx, text| {
+                            Self::lines(cx, &text.get(cx).replace('\n', " "), None);
+                        });
+                    });
+                }
             })
     }
+
+    /// Draw every character on its own, so it can be colored and clicked.
+    fn lines(cx: &mut Context, text: &str, cursor: Option<usize>) {
+        let mut token_at: Vec<Option<RqqToken>> = vec![None; text.len()];
+        for token in tokenize(text) {
+            token_at[token.start..token.end].fill(Some(token));
+        }
+        let errors = find_syntax_errors(text);
+        // The bracket at or before the cursor and its partner
+        let matched: Vec<usize> = cursor.into_iter()
+            .flat_map(|cursor| [Some(cursor), cursor.checked_sub(1)])
+            .flatten()
+            .filter(|&idx| text.get(idx..).is_some_and(|rest| rest.starts_with(['(', ')'])))
+            .find_map(|idx| matching_bracket(text, idx).map(|other| vec![idx, other]))
+            .unwrap_or_default();
+
+        VStack::new(cx, |cx| {
+            let mut line_start = 0;
+            for line in text.split('\n') {
+                HStack::new(cx, |cx| {
+                    for (offset, c) in line.char_indices() {
+                        let idx = line_start + offset;
+                        if cursor == Some(idx) {
+                            Self::cursor(cx);
+                        }
+
+                        let (r, g, b) = match token_at[idx] {
+                            Some(RqqToken { kind: RqqTokenKind::Invalid, .. }) => (128, 128, 128),
+                            Some(token) => DEPTH_COLORS[token.depth.saturating_sub(1) % DEPTH_COLORS.len()],
+                            None => (0, 0, 0),
+                        };
+                        let background = if matched.contains(&idx) {
+                            Color::rgba(255, 210, 0, 160)
+                        } else if errors.iter().any(|error| error.start <= idx && idx < error.end.max(error.start + 1)) {
+                            Color::rgba(172, 53, 53, 70)
+                        } else {
+                            Color::rgba(0, 0, 0, 0)
+                        };
+
+                        ClickCell::new(cx, MetreInputEvent::SetCursor(idx), move |cx| {
+                            Label::new(cx, c.to_string())
+                                .color(Color::rgb(r, g, b))
+                                .hoverable(false);
+                        })
+                            .background_color(background)
+                            .width(Auto)
+                            .height(Pixels(LINE_HEIGHT));
+                    }
+
+                    // Clicking right of a line puts the cursor at its end.
+                    let line_end = line_start + line.len();
+                    if cursor == Some(line_end) {
+                        Self::cursor(cx);
+                    }
+                    ClickCell::new(cx, MetreInputEvent::SetCursor(line_end), |_| {})
+                        .width(Stretch(1.0))
+                        .min_width(Pixels(4.0))
+                        .height(Pixels(LINE_HEIGHT));
+                })
+                    .height(Pixels(LINE_HEIGHT));
+                line_start += line.len() + 1;
+            }
+        })
+            .height(Auto)
+            .min_width(Stretch(1.0))
+            .width(Auto);
+    }
+
+    fn cursor(cx: &mut Context) {
+        Element::new(cx)
+            .background_color(Color::black())
+            .width(Pixels(1.0))
+            .height(Pixels(LINE_HEIGHT));
+    }
+
+    fn insert(&mut self, cx: &mut EventContext, text: &str) {
+        self.text.insert_str(self.cursor, text);
+        self.cursor += text.len();
+        cx.emit(MetreFiddlerEvent::ShowValidity(false));
+    }
+
+    fn previous_char_boundary(&self) -> Option<usize> {
+        self.text[..self.cursor].char_indices().next_back().map(|(idx, _)| idx)
+    }
+
+    fn next_char_boundary(&self) -> Option<usize> {
+        self.text[self.cursor..].chars().next().map(|c| self.cursor + c.len_utf8())
+    }
+
+    /// Move the cursor to the same column in the line before (-1) or after (1).
+    fn move_lines(&mut self, direction: isize) {
+        let line_start = self.text[..self.cursor].rfind('\n').map_or(0, |idx| idx + 1);
+        let column = self.text[line_start..self.cursor].chars().count();
+        let target_start = if direction < 0 {
+            match line_start.checked_sub(1) {
+                Some(previous_end) => self.text[..previous_end].rfind('\n').map_or(0, |idx| idx + 1),
+                None => return,
+            }
+        } else {
+            match self.text[self.cursor..].find('\n') {
+                Some(offset) => self.cursor + offset + 1,
+                None => return,
+            }
+        };
+        let target_line = self.text[target_start..].split('\n').next().unwrap_or("");
+        self.cursor = target_start + target_line.char_indices()
+            .nth(column)
+            .map_or(target_line.len(), |(idx, _)| idx);
+    }
+
+    fn key(&mut self, cx: &mut EventContext, code: Code) {
+        match code {
+            Code::Enter | Code::NumpadEnter if cx.modifiers().shift() => self.insert(cx, "\n"),
+            Code::Enter | Code::NumpadEnter => {
+                cx.emit(MetreFiddlerEvent::ExpandTextBox(false));
+                cx.emit(MetreFiddlerEvent::ShowValidity(true));
+                cx.emit(MetreFiddlerEvent::UpdateString(self.text.clone(), self.which));
+            },
+            Code::Backspace => if let Some(idx) = self.previous_char_boundary() {
+                self.text.replace_range(idx..self.cursor, "");
+                self.cursor = idx;
+                cx.emit(MetreFiddlerEvent::ShowValidity(false));
+            },
+            Code::Delete => if let Some(idx) = self.next_char_boundary() {
+                self.text.replace_range(self.cursor..idx, "");
+                cx.emit(MetreFiddlerEvent::ShowValidity(false));
+            },
+            Code::ArrowLeft => self.cursor = self.previous_char_boundary().unwrap_or(self.cursor),
+            Code::ArrowRight => self.cursor = self.next_char_boundary().unwrap_or(self.cursor),
+            Code::ArrowUp => self.move_lines(-1),
+            Code::ArrowDown => self.move_lines(1),
+            Code::Home => self.cursor = self.text[..self.cursor].rfind('\n').map_or(0, |idx| idx + 1),
+            Code::End => self.cursor += self.text[self.cursor..].find('\n').unwrap_or(self.text.len() - self.cursor),
+            Code::KeyF if cx.modifiers().command() && cx.modifiers().shift() => cx.emit(MetreInputEvent::Format),
+            _ => (),
+        }
+    }
 }
 
 impl View for MetreInput {
     fn element(&self) -> Option<&'static str> {
-        Some("param-ticks")
+        Some("metre-input")
     }
 
     fn event(&mut self, cx: &mut EventContext, event: &mut Event) {
-        event.map(|window_event, _meta| match window_event {
+        event.map(|input_event, meta| {
+            match input_event {
+                MetreInputEvent::SetCursor(idx) => self.cursor = *idx,
+                MetreInputEvent::Format => if let Ok(formatted) = format_rqq(&self.text, FORMAT_WIDTH) {
+                    self.text = formatted;
+                    self.cursor = self.text.len();
+                },
+            }
+            meta.consume();
+        });
+
+        event.map(|window_event, meta| match window_event {
             // Return
             WindowEvent::KeyDown(_, Some(Key::Escape)) | WindowEvent::FocusOut => {
                 cx.emit(MetreFiddlerEvent::ExpandTextBox(false));
             },
+            // Editing, which must not reach the undo shortcuts of the editor
+            WindowEvent::KeyDown(code, _) if self.expanded => {
+                self.key(cx, *code);
+                meta.consume();
+            },
+            WindowEvent::CharInput(c) if self.expanded && !c.is_control() => {
+                self.insert(cx, &c.to_string());
+                meta.consume();
+            },
+            WindowEvent::MouseDown(MouseButton::Left) if self.expanded => {
+                cx.focus();
+            },
             // Focus
             WindowEvent::MouseDown(MouseButton::Left) |
             WindowEvent::KeyDown(_, _) => {
                 cx.emit(MetreFiddlerEvent::ExpandTextBox(true));
-            }
-            // TODO scrolling should work out of the box but even this doesn't work?
-            WindowEvent::MouseScroll(x, y) => {
-                nih_log!("scrolling!");
-                cx.emit(TextEvent::Scroll(*x, *y))
             },
             _ => (),
         })
     }
-}
\ No newline at end of file
+}
+
+/// Emits its event to the MetreInput when clicked, without taking the focus from it.
+struct ClickCell {
+    event: MetreInputEvent,
+}
+
+impl ClickCell {
+    fn new(cx: &mut Context, event: MetreInputEvent, content: impl FnOnce(&mut Context)) -> Handle<'_, Self> {
+        Self { event }.build(cx, content)
+    }
+}
+
+impl View for ClickCell {
+    fn element(&self) -> Option<&'static str> {
+        Some("click-cell")
+    }
+
+    fn event(&mut self, cx: &mut EventContext, event: &mut Event) {
+        event.map(|window_event, _| {
+            if let WindowEvent::MouseDown(MouseButton::Left) = window_event {
+                cx.emit(self.event);
+            }
+        });
+    }
+}
+
+/// Drag it up or down to change the height of the expanded MetreInput.
+struct ResizeHandle {
+    drag_start: Option<f32>,
+}
+
+impl ResizeHandle {
+    fn new(cx: &mut Context) -> Handle<'_, Self> {
+        Self { drag_start: None }
+            .build(cx, |cx| {
+                Label::new(cx, "↕")
+                    .font_size(11.0)
+                    .hoverable(false);
+            })
+            .cursor(CursorIcon::NsResize)
+    }
+}
+
+impl View for ResizeHandle {
+    fn element(&self) -> Option<&'static str> {
+        Some("resize-handle")
+    }
+
+    fn event(&mut self, cx: &mut EventContext, event: &mut Event) {
+        event.map(|window_event, meta| match window_event {
+            WindowEvent::MouseDown(MouseButton::Left) => {
+                self.drag_start = Some(cx.mouse().cursor_y);
+                cx.capture();
+                meta.consume();
+            },
+            WindowEvent::MouseMove(_, y) => {
+                if let Some(start) = self.drag_start {
+                    cx.emit(MetreFiddlerEvent::ResizeMetreInput((*y - start) / cx.scale_factor()));
+                    self.drag_start = Some(*y);
+                }
+            },
+            WindowEvent::MouseUp(MouseButton::Left) => {
+                if self.drag_start.take().is_some() {
+                    cx.release();
+                    meta.consume();
+                }
+            },
+            _ => (),
+        });
+    }
+}
+
+/// Keep a height for the expanded MetreInput within its limits.
+pub fn clamp_input_height(height: f32) -> f32 {
+    height.clamp(MIN_HEIGHT, MAX_HEIGHT)
+}
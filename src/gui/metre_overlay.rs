@@ -0,0 +1,107 @@
+use vizia_plug::vizia::prelude::*;
+use vizia_plug::vizia::vg;
+use crate::metre::combined_metre_data::CombinedMetreData;
+use crate::util::{dry_wet, get_start_times};
+
+/// Space above row A and below row B, in pixels.
+const ROW_PADDING: f32 = 6.0;
+/// Height of the tick for the most important beat of a metre.
+const MAX_TICK_HEIGHT: f32 = 16.0;
+
+/// Draws metre A on the top row and metre B on the bottom row, with a line between the starts of
+/// every pair of beats that are interpolated with each other. The interpolated beats are drawn
+/// on these lines, so they move between the rows as interpolate_a_b changes.
+#[derive(Lens)]
+pub struct MetreOverlay {
+    /// The start time and normalized weight (1.0 is the most important) of every beat of A.
+    beats_a: Vec<(f32, f32)>,
+    beats_b: Vec<(f32, f32)>,
+    /// The start times of every duration pair and whether both of its durations are beats.
+    pairs: Vec<(f32, f32, bool)>,
+    interpolate: f32,
+}
+
+impl MetreOverlay {
+    pub fn new<L>(
+        cx: &mut Context,
+        metre_data: L,
+        interpolate: f32,
+    ) -> Handle<'_, Self>
+    where
+        L: Lens<Target = CombinedMetreData>,
+    {
+        let metre_data = metre_data.get(cx);
+        let beats = |durations: &[f32], values: &[usize], max: usize| -> Vec<(f32, f32)> {
+            get_start_times(durations).into_iter()
+                .zip(values)
+                .map(|(start, &value)| (start, 1.0 - value as f32 / max.max(1) as f32))
+                .collect()
+        };
+        let interpolation_data = metre_data.interpolation_data();
+
+        Self {
+            beats_a: beats(&metre_data.metre_a().durations, &metre_data.metre_a().value, metre_data.metre_a().max),
+            beats_b: beats(&metre_data.metre_b().durations, &metre_data.metre_b().value, metre_data.metre_b().max),
+            pairs: interpolation_data.start_time_pairs().into_iter()
+                .zip(interpolation_data.duration_pairs())
+                .map(|((start_a, start_b), &(dur_a, dur_b))| (start_a, start_b, dur_a > 0.0 && dur_b > 0.0))
+                .collect(),
+            interpolate,
+        }
+            .build(cx, |_| {})
+            .hoverable(false)
+    }
+
+    fn draw_ticks(canvas: &vg::Canvas, bounds: BoundingBox, beats: &[(f32, f32)], y: f32, direction: f32, scale: f32, paint: &vg::Paint) {
+        for &(start, weight) in beats {
+            let x = bounds.x + start * bounds.w;
+            let height = (4.0 + weight * (MAX_TICK_HEIGHT - 4.0)) * scale;
+            canvas.draw_line((x, y), (x, y + direction * height), paint);
+        }
+        // The end of the bar
+        canvas.draw_line((bounds.right(), y), (bounds.right(), y + direction * MAX_TICK_HEIGHT * scale), paint);
+    }
+}
+
+impl View for MetreOverlay {
+    fn element(&self) -> Option<&'static str> {
+        Some("metre-overlay")
+    }
+
+    fn draw(&self, cx: &mut DrawContext, canvas: &vg::Canvas) {
+        let bounds = cx.bounds();
+        let scale = cx.scale_factor();
+        let top = bounds.y + ROW_PADDING * scale;
+        let bottom = bounds.bottom() - ROW_PADDING * scale;
+        let row_a = top + MAX_TICK_HEIGHT * scale;
+        let row_b = bottom - MAX_TICK_HEIGHT * scale;
+
+        let mut paint = vg::Paint::default();
+        paint.set_anti_alias(true);
+        paint.set_style(vg::PaintStyle::Stroke);
+        paint.set_stroke_width(scale);
+
+        // The pairing lines, lighter where a beat is paired with nothing.
+        for &(start_a, start_b, both) in &self.pairs {
+            paint.set_color(vg::Color::from_argb(if both { 120 } else { 50 }, 0, 0, 0));
+            canvas.draw_line(
+                (bounds.x + start_a * bounds.w, row_a),
+                (bounds.x + start_b * bounds.w, row_b),
+                &paint);
+        }
+
+        // Metre A grows up from its row, B down from its row.
+        paint.set_color(vg::Color::BLACK);
+        Self::draw_ticks(canvas, bounds, &self.beats_a, row_a, -1.0, scale, &paint);
+        Self::draw_ticks(canvas, bounds, &self.beats_b, row_b, 1.0, scale, &paint);
+
+        // The interpolated beats, travelling along the pairing lines.
+        paint.set_style(vg::PaintStyle::Fill);
+        paint.set_color(vg::Color::from_argb(255, 172, 53, 53));
+        let y = dry_wet(row_a, row_b, self.interpolate);
+        for &(start_a, start_b, _) in &self.pairs {
+            let x = bounds.x + dry_wet(start_a, start_b, self.interpolate) * bounds.w;
+            canvas.draw_circle((x, y), 2.5 * scale, &paint);
+        }
+    }
+}
@@ -4,4 +4,6 @@ pub mod param_slider_knob;
 pub mod param_display_knob;
 pub mod param_ticks;
 pub mod param_binding;
-pub mod metre_input;
\ No newline at end of file
+pub mod metre_input;
+pub mod rqq_tree_view;pub mod metre_overlay;
+pub mod bar_display;
@@ -1,108 +1,122 @@
 use vizia_plug::vizia::prelude::*;
-use crate::metre::beat_origin::BeatOrigin;
-use crate::metre::beat_origin::BeatOrigin::Both;
-use crate::metre::interpolation::interpolation_data::InterpolationData;
-use crate::util::{get_durations};
+use vizia_plug::vizia::vg;
+use crate::metre::combined_metre_data::CombinedMetreData;
+use crate::params::ParamsSnapShot;
+
+/// Height of the tick for the least important beat.
+const MIN_TICK_HEIGHT: f32 = 4.0;
+/// Height of the tick for the most important beat (the start of the bar).
+const MAX_TICK_HEIGHT: f32 = 16.0;
+/// Space left and right of the ticks, so the outer ones line up with the ends of the position bar.
+const PADDING: f32 = 1.0;
 
 #[derive(Lens)]
 pub struct ParamTicks {}
 
 impl ParamTicks {
+    /// Draw a tick at the start of every beat, as high as the beat is indispensable. Beats that
+    /// currently pass the thresholds are highlighted. Only the interpolation, threshold and
+    /// interpolation settings of the snapshot are used.
     pub fn new<L>(
         cx: &mut Context,
-        width_pixels: f32,
-        interpolation_data: L,
-        interpolate: f32,
-        interpolate_durs: bool,
+        metre_data: L,
+        snapshot: ParamsSnapShot,
     ) -> Handle<'_, Self>
     where
-        L: Lens<Target = InterpolationData>,
+        L: Lens<Target = CombinedMetreData>,
     {
         Self {}
             .build(
                 cx,
                 |cx| {
-                    Binding::new(cx, interpolation_data, move |cx, data| {
-                        Self::ticks(cx, data, interpolate, interpolate_durs, width_pixels);
+                    Binding::new(cx, metre_data, move |cx, data| {
+                        TickMarks::new(&data.get(cx), snapshot)
+                            .build(cx, |_| {})
+                            .width(Stretch(1.0))
+                            .height(Stretch(1.0))
+                            .hoverable(false);
                     });
                 }
             )
-            .width(Pixels(width_pixels))
             .hoverable(false)
     }
+}
 
-    fn ticks(
-        cx: &mut Context,
-        interpolation_data: impl Lens<Target = InterpolationData>,
-        interpolate: f32,
-        interpolate_durs: bool,
-        width_px: f32,
-    ) {
-        HStack::new(cx, |cx| {
-            Element::new(cx)
-                .background_color(Color::black())
-                .width(Pixels(1.0))
-                .height(Pixels(10.0));
-
-            // TODO clean this up a bit. Actually we could just draw one tick gui for two metres
-            // on top of each other...?
+impl View for ParamTicks {
+    fn element(&self) -> Option<&'static str> {
+        Some("param-ticks")
+    }
+}
 
-            let durations: Vec<f32>;
-            let opacity_ids: Vec<BeatOrigin>;
+/// The ticks themselves. They are placed when they are drawn, so they stay exact at every size
+/// and scale of the window.
+struct TickMarks {
+    /// The start time, normalized weight (1.0 is the most important), opacity and whether it
+    /// passes the thresholds of every tick, including the one at the end of the bar.
+    ticks: Vec<(f32, f32, u8, bool)>,
+}
 
-            if interpolate_durs {
-                durations = interpolation_data.get(cx).get_interpolated_durations(interpolate).collect();
-                opacity_ids = vec![Both; durations.len()];
+impl TickMarks {
+    fn new(metre_data: &CombinedMetreData, snapshot: ParamsSnapShot) -> Self {
+        let max_indisp_val = metre_data.max_indisp_val();
+        let beats = metre_data.get_beat_weights(snapshot.interpolate, snapshot.interpolate_durs, snapshot.interpolate_indisp);
+        let nr_beats = beats.len();
+        let tick = |start: f32, beat_idx: usize, indisp_val: usize, opacity: u8| {
+            let weight = if max_indisp_val == 0 {
+                1.0
             } else {
-                let interpolation_data = interpolation_data.get(cx);
-                let starts = interpolation_data.unique_start_times();
-                let inits = interpolation_data.unique_start_time_origins();
-                
-                durations = get_durations(starts).collect();
-                opacity_ids = inits[1..].to_vec();
+                1.0 - indisp_val as f32 / max_indisp_val as f32
             };
+            (start, weight, opacity, snapshot.is_within_thresholds(metre_data, beat_idx, indisp_val, nr_beats))
+        };
 
-            let sum: f32 = durations.iter().sum();
-            let nr_of_ticks = durations.len();
-            let mut current_sum: f32 = 0.0;
-            let mut last_sum: f32 = 0.0;
-            let nr_of_pixels = (width_px.round() as usize).saturating_sub(2).saturating_sub(nr_of_ticks);
+        let sum: f32 = beats.iter().map(|(dur, _, _)| dur).sum();
+        let mut start: f32 = 0.0;
+        let mut ticks: Vec<(f32, f32, u8, bool)> = beats.iter()
+            .enumerate()
+            .map(|(beat_idx, &(dur, indisp_val, origin))| {
+                // calculate opacity (MetreA fades out, MetreB fades in, both stay)
+                let opacity = if snapshot.interpolate_durs { 255 } else { origin.to_opacity(snapshot.interpolate) };
+                let result = tick(start / sum, beat_idx, indisp_val, opacity);
+                start += dur;
+                result
+            })
+            .collect();
+        // The end of the bar is the start of the next one.
+        if let Some(&(_, first_indisp_val, _)) = beats.first() {
+            ticks[0].2 = 255;
+            ticks.push(tick(1.0, 0, first_indisp_val, 255));
+        }
 
-            for (dur, origin) in durations.iter().zip(opacity_ids) {
-                let float_pixels: f32 = dur / sum * nr_of_pixels as f32;
-                current_sum += float_pixels;
-                let width_in_pixels: f32 = current_sum.round() - last_sum.round();
-                last_sum += float_pixels;
+        Self { ticks }
+    }
+}
 
-                let opacity = if interpolate_durs {
-                    255
-                } else {
-                    // calculate opacity (init_opacity -1.0 -> MetreA, 0.0 -> MetreB, 1.0 -> both)
-                    origin.to_opacity(interpolate)
-                };
-                let color: Color = Color::rgba(0,0,0, opacity);
+impl View for TickMarks {
+    fn element(&self) -> Option<&'static str> {
+        Some("tick-marks")
+    }
 
+    fn draw(&self, cx: &mut DrawContext, canvas: &vg::Canvas) {
+        let bounds = cx.bounds();
+        let scale = cx.scale_factor();
+        let padding = PADDING * scale;
+        let center = bounds.y + bounds.h / 2.0;
 
-                // Draw the empty Space and the Ticks
-                Element::new(cx)
-                    .width(Pixels(width_in_pixels))
-                    .height(Pixels(10.0));
+        let mut paint = vg::Paint::default();
+        paint.set_style(vg::PaintStyle::Stroke);
+        paint.set_stroke_width(scale);
 
-                // TODO would we want the ticks to be different heights depending on indisp_val?
-                Element::new(cx)
-                    .background_color(color)
-                    .width(Pixels(1.0))
-                    .height(Pixels(10.0));
-            }
-        })
-            .padding_left(Pixels(1.0))
-            .padding_right(Pixels(1.0))
-            .alignment(Alignment::Center);
+        for &(start, weight, opacity, within_thresholds) in &self.ticks {
+            // Snap to the middle of a physical pixel, so the ticks stay sharp.
+            let x = (bounds.x + padding + start * (bounds.w - 2.0 * padding - scale)).floor() + 0.5 * scale;
+            let half_height = (MIN_TICK_HEIGHT + weight * (MAX_TICK_HEIGHT - MIN_TICK_HEIGHT)) * scale / 2.0;
+            paint.set_color(if within_thresholds {
+                vg::Color::from_argb(opacity, 172, 53, 53)
+            } else {
+                vg::Color::from_argb(opacity, 0, 0, 0)
+            });
+            canvas.draw_line((x, center - half_height), (x, center + half_height), &paint);
+        }
     }
 }
-
-impl View for ParamTicks {
-    fn element(&self) -> Option<&'static str> {
-        Some("param-ticks")
-    }
-}
\ No newline at end of file
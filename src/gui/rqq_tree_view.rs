@@ -0,0 +1,126 @@
+use vizia_plug::vizia::prelude::*;
+use crate::editor::MetreFiddlerEvent;
+use crate::metre::rqq_tree::{RqqNode, TreeEdit};
+
+/// Dragging a box by this many pixels moves one unit of proportion to or from its next sibling.
+const DRAG_STEP_PX: f32 = 12.0;
+
+/// Shows an RQQ string as nested boxes, each as wide as its duration. Clicking a box selects it,
+/// dragging it to the side resizes it against its next sibling.
+#[derive(Lens)]
+pub struct RqqTreeView {}
+
+impl RqqTreeView {
+    pub fn new<L, S>(
+        cx: &mut Context,
+        rqq: L,
+        selection: S,
+    ) -> Handle<'_, Self>
+    where
+        L: Lens<Target = String>,
+        S: Lens<Target = Vec<usize>>,
+    {
+        Self {}
+            .build(cx, move |cx| {
+                Binding::new(cx, rqq, move |cx, rqq| {
+                    Binding::new(cx, selection, move |cx, selection| {
+                        let selection = selection.get(cx);
+                        match RqqNode::parse(&rqq.get(cx)) {
+                            Ok(tree) => Self::children(cx, &tree, vec![], &selection),
+                            Err(_) => {
+                                Label::new(cx, "This metre can't be shown as a tree");
+                            },
+                        }
+                    });
+                });
+            })
+    }
+
+    fn children(cx: &mut Context, node: &RqqNode, path: Vec<usize>, selection: &[usize]) {
+        HStack::new(cx, |cx| {
+            for (idx, child) in node.children().iter().enumerate() {
+                let mut child_path = path.clone();
+                child_path.push(idx);
+                Self::node(cx, child, child_path, selection);
+            }
+        })
+            .height(Stretch(1.0));
+    }
+
+    fn node(cx: &mut Context, node: &RqqNode, path: Vec<usize>, selection: &[usize]) {
+        let background = if path == selection {
+            Color::rgba(172, 53, 53, 160)
+        } else {
+            Color::rgba(0, 0, 0, (path.len() * 12).min(255) as u8)
+        };
+        let label = node.proportion().to_string();
+        let is_group = matches!(node, RqqNode::Group(..));
+
+        TreeNodeBox::new(cx, path.clone(), |cx| {
+            VStack::new(cx, |cx| {
+                Label::new(cx, label)
+                    .font_size(11.0)
+                    .height(Pixels(16.0))
+                    .width(Stretch(1.0));
+                if is_group {
+                    Self::children(cx, node, path, selection);
+                }
+            });
+        })
+            .background_color(background)
+            .border_width(Pixels(1.0))
+            .border_color(Color::black())
+            .width(Stretch(node.proportion() as f32));
+    }
+}
+
+impl View for RqqTreeView {
+    fn element(&self) -> Option<&'static str> {
+        Some("rqq-tree-view")
+    }
+}
+
+/// A single beat or group in the RqqTreeView.
+#[derive(Lens)]
+pub struct TreeNodeBox {
+    path: Vec<usize>,
+    /// The cursor position where the current drag started.
+    drag_start: Option<f32>,
+}
+
+impl TreeNodeBox {
+    pub fn new(cx: &mut Context, path: Vec<usize>, content: impl FnOnce(&mut Context)) -> Handle<'_, Self> {
+        Self { path, drag_start: None }
+            .build(cx, content)
+    }
+}
+
+impl View for TreeNodeBox {
+    fn element(&self) -> Option<&'static str> {
+        Some("tree-node-box")
+    }
+
+    fn event(&mut self, cx: &mut EventContext, event: &mut Event) {
+        event.map(|window_event, meta| match window_event {
+            WindowEvent::MouseDown(MouseButton::Left) => {
+                cx.emit(MetreFiddlerEvent::SelectTreeNode(self.path.clone()));
+                self.drag_start = Some(cx.mouse().cursor_x);
+                cx.capture();
+                meta.consume();
+            },
+            WindowEvent::MouseUp(MouseButton::Left) => {
+                if let Some(start) = self.drag_start.take() {
+                    cx.release();
+                    // The tree is rebuilt after every edit, so the resize is applied at the end
+                    // of the drag.
+                    let steps = ((cx.mouse().cursor_x - start) / (DRAG_STEP_PX * cx.scale_factor())).round() as i64;
+                    if steps != 0 {
+                        cx.emit(MetreFiddlerEvent::EditTree(TreeEdit::Resize(steps)));
+                    }
+                    meta.consume();
+                }
+            },
+            _ => (),
+        });
+    }
+}
@@ -0,0 +1,198 @@
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::sync::{Arc, MutexGuard};
+use std::sync::atomic::Ordering::Relaxed;
+use nih_plug::prelude::*;
+use crate::{execute_task, HostContext, MetreFiddler, MetreFiddlerTask, NoteEventIO};
+use crate::beat_event::BeatEvent;
+use crate::metre::combined_metre_data::CombinedMetreData;
+use crate::metre::metre_slots::MetreSlots;
+use crate::metre::metric_phase::MetricPhase;
+use crate::midi_clock::MidiSystemMessage;
+use crate::params::{MetreFiddlerParams, ParamsSnapShot};
+
+/// The parts of the host's transport that MetreFiddler uses.
+#[derive(Debug, Clone, Copy)]
+pub struct HeadlessTransport {
+    pub playing: bool,
+    pub tempo: Option<f64>,
+}
+
+impl Default for HeadlessTransport {
+    fn default() -> Self {
+        Self {
+            playing: true,
+            tempo: Some(120.0),
+        }
+    }
+}
+
+/// Stands in for the host's ProcessContext: feeds incoming events to the processing, collects
+/// everything it sends and runs background tasks right away.
+struct HeadlessContext {
+    transport: HeadlessTransport,
+    params: Arc<MetreFiddlerParams>,
+    incoming: VecDeque<NoteEvent<MidiSystemMessage>>,
+    outgoing: Vec<NoteEvent<MidiSystemMessage>>,
+    latency_samples: Cell<Option<u32>>,
+}
+
+impl NoteEventIO for HeadlessContext {
+    fn next_event(&mut self) -> Option<PluginNoteEvent<MetreFiddler>> {
+        self.incoming.pop_front()
+    }
+
+    fn send_event(&mut self, event: PluginNoteEvent<MetreFiddler>) {
+        self.outgoing.push(event)
+    }
+}
+
+impl HostContext for HeadlessContext {
+    fn playing(&self) -> bool {
+        self.transport.playing
+    }
+
+    fn tempo(&self) -> Option<f64> {
+        self.transport.tempo
+    }
+
+    /// Parameters can only be set by a host, so the values stay those of the snapshot.
+    fn snapshot(&self, _params: &MetreFiddlerParams, last: &ParamsSnapShot) -> ParamsSnapShot {
+        *last
+    }
+
+    fn set_latency_samples(&self, samples: u32) {
+        self.latency_samples.set(Some(samples))
+    }
+
+    fn execute_background(&self, task: MetreFiddlerTask) {
+        execute_task(&self.params, task)
+    }
+}
+
+/// Runs the plugin's processing without a host or GUI. Each call to process() is handled like one
+/// buffer in the host and runs through the same code as MetreFiddler::process(): incoming note
+/// events are filtered and modified, Send Midi notes are generated and NoteOffs are scheduled
+/// across buffers.
+///
+/// All parameter values come from the snapshot and are not smoothed. The snapshot can be changed
+/// between buffers with snapshot_mut().
+pub struct HeadlessProcessor {
+    plugin: MetreFiddler,
+    latency_samples: u32,
+}
+
+impl HeadlessProcessor {
+    pub fn new(combined_metre_data: CombinedMetreData, snapshot: ParamsSnapShot, sample_rate: f32) -> Self {
+        let params = MetreFiddlerParams::default();
+        *params.combined_metre_data.lock().unwrap() = combined_metre_data;
+
+        Self {
+            plugin: MetreFiddler {
+                params: Arc::new(params),
+                params_snapshot: snapshot,
+                sample_rate,
+                offline: true,
+                ..MetreFiddler::default()
+            },
+            latency_samples: 0,
+        }
+    }
+
+    pub fn snapshot(&self) -> &ParamsSnapShot {
+        &self.plugin.params_snapshot
+    }
+
+    pub fn snapshot_mut(&mut self) -> &mut ParamsSnapShot {
+        &mut self.plugin.params_snapshot
+    }
+
+    pub fn metric_phase(&self) -> &MetricPhase {
+        &self.plugin.metric_phase
+    }
+
+    pub fn sample_rate(&self) -> f32 {
+        self.plugin.sample_rate
+    }
+
+    /// The latency the plugin last reported to the host, in samples. Every sent event is this late.
+    pub fn latency_samples(&self) -> u32 {
+        self.latency_samples
+    }
+
+    /// The tempo last reported to the Gui, from the host or the incoming MIDI clock.
+    pub fn tempo(&self) -> f32 {
+        self.plugin.params.host_tempo.load(Relaxed)
+    }
+
+    /// The metres that are currently used.
+    pub fn combined_metre_data(&self) -> CombinedMetreData {
+        self.plugin.params.combined_metre_data.lock().unwrap().clone()
+    }
+
+    /// The stored metres that the slot_a and slot_b of the snapshot select.
+    pub fn metre_slots(&self) -> MutexGuard<'_, MetreSlots> {
+        self.plugin.params.metre_slots.lock().unwrap()
+    }
+
+    /// Take the beats that were reported to the Gui since the last call, at most
+    /// BEAT_EVENT_QUEUE_LEN of them.
+    pub fn take_beat_events(&mut self) -> Vec<BeatEvent> {
+        let mut consumer = self.plugin.beat_event_consumer.lock().unwrap();
+        std::iter::from_fn(|| consumer.pop().ok()).collect()
+    }
+
+    /// Reset the metric phase, like the Reset Phase button does.
+    pub fn reset_phase(&mut self) {
+        self.plugin.metric_phase.reset()
+    }
+
+    /// Process one buffer of nr_samples samples. The incoming events must be sorted by their
+    /// timing, which is relative to the start of this buffer. Returns all events the plugin sent
+    /// during this buffer. Background tasks are done right away, so newly selected metre slots are
+    /// prepared before the buffer is processed and switched to at the next bar.
+    pub fn process(
+        &mut self,
+        transport: HeadlessTransport,
+        nr_samples: usize,
+        incoming: impl IntoIterator<Item = NoteEvent<MidiSystemMessage>>,
+    ) -> Vec<NoteEvent<MidiSystemMessage>> {
+        let mut context = HeadlessContext {
+            transport,
+            params: self.plugin.params.clone(),
+            incoming: incoming.into_iter().collect(),
+            outgoing: Vec::new(),
+            latency_samples: Cell::new(None),
+        };
+        self.plugin.process_buffer(nr_samples, &mut context);
+        if let Some(latency_samples) = context.latency_samples.get() {
+            self.latency_samples = latency_samples;
+        }
+
+        context.outgoing
+    }
+
+    /// Process nr_samples samples in buffers of buffer_size without incoming events. Returns the
+    /// sent events together with their position in samples, counted from the first sample.
+    pub fn process_samples(
+        &mut self,
+        transport: HeadlessTransport,
+        nr_samples: u64,
+        buffer_size: usize,
+    ) -> Vec<(u64, NoteEvent<MidiSystemMessage>)> {
+        let mut events = Vec::new();
+        let mut buffer_start: u64 = 0;
+
+        while buffer_start < nr_samples {
+            let buffer_len = buffer_size.min((nr_samples - buffer_start) as usize);
+            events.extend(
+                self.process(transport, buffer_len, [])
+                    .into_iter()
+                    .map(|event| (buffer_start + event.timing() as u64, event))
+            );
+            buffer_start += buffer_len as u64;
+        }
+
+        events
+    }
+}
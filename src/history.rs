@@ -0,0 +1,72 @@
+use std::collections::VecDeque;
+use serde::{Deserialize, Serialize};
+
+/// How many edits can be undone. The history is saved with the plugin state, so this is kept
+/// short.
+pub const MAX_HISTORY_LEN: usize = 32;
+
+/// The settings that can be toggled in the settings screen.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum SettingToggle {
+    InterpolateDurations,
+    InterpolateIndisp,
+    ManyVelocities,
+    MidiOutOneNote,
+    RetainMetricPhase,
+    MidiClockSync,
+    SendMidiClock,
+    SendGroupNotes,
+    QuantizeToChord,
+    ScaleAftertouch,
+}
+
+/// A single edit in the editor, with everything needed to undo and redo it.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub enum Edit {
+    MetreA { old: String, new: String },
+    MetreB { old: String, new: String },
+    /// Switching the textbox between metre A and B
+    SwitchAB,
+    /// A toggled setting. Its value is set exactly, in case the host changed it since.
+    Setting { toggle: SettingToggle, old: bool, new: bool },
+}
+
+/// Undo and redo stacks for the edits in the editor.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct EditHistory {
+    undo: VecDeque<Edit>,
+    redo: Vec<Edit>,
+}
+
+impl EditHistory {
+    /// Record a new edit. This discards everything that could be redone.
+    pub fn push(&mut self, edit: Edit) {
+        self.redo.clear();
+        self.undo.push_back(edit);
+        while self.undo.len() > MAX_HISTORY_LEN {
+            self.undo.pop_front();
+        }
+    }
+
+    /// The edit to revert, which is then available for redo.
+    pub fn undo(&mut self) -> Option<Edit> {
+        let edit = self.undo.pop_back()?;
+        self.redo.push(edit.clone());
+        Some(edit)
+    }
+
+    /// The edit to apply again, which is then available for undo.
+    pub fn redo(&mut self) -> Option<Edit> {
+        let edit = self.redo.pop()?;
+        self.undo.push_back(edit.clone());
+        Some(edit)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+}
@@ -0,0 +1,223 @@
+use std::iter::once;
+use std::path::Path;
+use midly::{MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
+use num_integer::Integer;
+use crate::metre::metre_data::MetreData;
+
+/// Imported hierarchies are flattened below this many levels.
+const MAX_IMPORT_DEPTH: usize = 16;
+
+/// How the onsets of a MIDI file are turned into a metre.
+#[derive(Clone, Copy, Debug)]
+pub struct ImportOptions {
+    /// Import only this bar (counting from 0), or the whole file.
+    pub bar: Option<usize>,
+    /// Snap onsets to a grid with this many steps per quarter note.
+    pub grid: Option<u32>,
+    /// Onsets closer than this (in quarter notes) to the previous onset are merged into it.
+    pub tolerance: f64,
+    /// Louder onsets start larger groups, instead of the onsets on the beats of the time signature.
+    pub velocities_as_weights: bool,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            bar: None,
+            grid: None,
+            tolerance: 0.0,
+            velocities_as_weights: false,
+        }
+    }
+}
+
+/// A NoteOn in ticks from the start of the file.
+#[derive(Clone, Copy, Debug)]
+struct Onset {
+    tick: u64,
+    velocity: u8,
+}
+
+/// Everything import_midi needs to know about a MIDI file.
+struct MidiClip {
+    ticks_per_quarter: u64,
+    bar_ticks: u64,
+    beat_ticks: u64,
+    onsets: Vec<Onset>,
+    end: u64,
+}
+
+impl MidiClip {
+    fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let smf = Smf::parse(bytes).map_err(|err| format!("Failed to parse MIDI file: {}", err))?;
+        let ticks_per_quarter = match smf.header.timing {
+            Timing::Metrical(ticks) if ticks.as_int() > 0 => ticks.as_int() as u64,
+            _ => return Err("Only MIDI files with metrical timing can be imported".to_string()),
+        };
+
+        // The first time signature of the file is used for all bars, 4/4 if there is none.
+        let mut time_signature = None;
+        let mut onsets = Vec::new();
+        let mut end = 0;
+        // All tracks are treated as if they were played in parallel.
+        for track in &smf.tracks {
+            let mut tick: u64 = 0;
+            for event in track {
+                tick += event.delta.as_int() as u64;
+                match event.kind {
+                    TrackEventKind::Midi { message: MidiMessage::NoteOn { vel, .. }, .. } if vel.as_int() > 0 => {
+                        onsets.push(Onset { tick, velocity: vel.as_int() });
+                    },
+                    TrackEventKind::Meta(MetaMessage::TimeSignature(numerator, denominator_pow, _, _)) => {
+                        time_signature.get_or_insert((numerator as u64, denominator_pow as u32));
+                    },
+                    _ => (),
+                }
+            }
+            end = end.max(tick);
+        }
+        onsets.sort_by_key(|onset| onset.tick);
+
+        let (numerator, denominator_pow) = time_signature.unwrap_or((4, 2));
+        let denominator = 2u64.checked_pow(denominator_pow)
+            .filter(|&denominator| numerator > 0 && denominator <= 64)
+            .ok_or_else(|| format!("Invalid time signature {}/2^{}", numerator, denominator_pow))?;
+        let note_ticks = ticks_per_quarter * 4 / denominator;
+        // In compound metres like 6/8, the beats are dotted.
+        let beat_ticks = if numerator > 3 && numerator % 3 == 0 && denominator >= 8 {
+            note_ticks * 3
+        } else {
+            note_ticks
+        };
+
+        Ok(Self {
+            ticks_per_quarter,
+            bar_ticks: (note_ticks * numerator).max(1),
+            beat_ticks: beat_ticks.max(1),
+            onsets,
+            end,
+        })
+    }
+}
+
+/// Read a MIDI file and turn its onsets into a metre, see import_midi.
+pub fn import_midi_file(path: &Path, options: &ImportOptions) -> Result<MetreData, String> {
+    let bytes = std::fs::read(path)
+        .map_err(|err| format!("Failed to read '{}': {}", path.display(), err))?;
+    import_midi(&bytes, options)
+}
+
+/// Turn the NoteOns of a Standard MIDI File (or of a single bar) into a metre. Each onset
+/// starts a beat that lasts until the next onset. The first beat always starts at the
+/// beginning of the bar, even if there is no note.
+///
+/// The hierarchy is inferred from the weight of each beat: beats on the beats of the time
+/// signature, or with velocities_as_weights the loudest beats, start the largest groups.
+pub fn import_midi(bytes: &[u8], options: &ImportOptions) -> Result<MetreData, String> {
+    let clip = MidiClip::parse(bytes)?;
+
+    let (start, length) = match options.bar {
+        Some(bar) => (bar as u64 * clip.bar_ticks, clip.bar_ticks),
+        None => {
+            let end = clip.onsets.last().map_or(clip.end, |onset| clip.end.max(onset.tick + 1));
+            (0, end.div_ceil(clip.bar_ticks).max(1) * clip.bar_ticks)
+        },
+    };
+
+    let snap = |tick: u64| match options.grid {
+        Some(steps) if steps > 0 => {
+            let step = clip.ticks_per_quarter as f64 / steps as f64;
+            ((tick as f64 / step).round() * step).round() as u64
+        },
+        _ => tick,
+    };
+    let tolerance = options.tolerance.max(0.0) * clip.ticks_per_quarter as f64;
+
+    let mut beats = vec![Onset { tick: 0, velocity: 0 }];
+    for onset in &clip.onsets {
+        if onset.tick < start || onset.tick >= start + length {
+            continue;
+        }
+        let tick = snap(onset.tick - start);
+        // Snapped to the start of the next bar
+        if tick >= length {
+            continue;
+        }
+        let last = beats.last_mut().expect("beats start with the beginning of the bar");
+        if tick == last.tick || ((tick - last.tick) as f64) < tolerance {
+            last.velocity = last.velocity.max(onset.velocity);
+        } else {
+            beats.push(Onset { tick, velocity: onset.velocity });
+        }
+    }
+
+    if beats.len() == 1 && beats[0].velocity == 0 {
+        return Err(match options.bar {
+            Some(bar) => format!("No notes found in bar {}", bar),
+            None => "No notes found".to_string(),
+        });
+    }
+
+    let mut durations: Vec<u64> = beats.windows(2)
+        .map(|pair| pair[1].tick - pair[0].tick)
+        .chain(once(length - beats[beats.len() - 1].tick))
+        .collect();
+    let divisor = durations.iter().fold(0, |acc: u64, duration| acc.gcd(duration));
+    durations.iter_mut().for_each(|duration| *duration /= divisor.max(1));
+
+    let weights: Vec<u32> = beats.iter()
+        .map(|beat| {
+            let on_bar = beat.tick % clip.bar_ticks == 0;
+            if options.velocities_as_weights {
+                beat.velocity as u32 + if on_bar { 128 } else { 0 }
+            } else if on_bar {
+                2
+            } else if beat.tick % clip.beat_ticks == 0 {
+                1
+            } else {
+                0
+            }
+        })
+        .collect();
+
+    MetreData::try_from(weights_to_rqq(&durations, &weights).as_str())
+}
+
+/// Build an RQQ string from the durations and weights of beats. Each list is divided at its
+/// heaviest beats (ignoring the first, which starts the list anyway), so that heavier beats
+/// start larger groups.
+/// ```
+/// use metrefiddler::import::weights_to_rqq;
+///
+/// assert_eq!(weights_to_rqq(&[1, 1, 1, 1], &[2, 0, 1, 0]), "(4 ((2 (1 1)) (2 (1 1))))");
+/// assert_eq!(weights_to_rqq(&[3, 3, 2], &[0, 0, 0]), "(8 (3 3 2))");
+/// ```
+pub fn weights_to_rqq(durations: &[u64], weights: &[u32]) -> String {
+    format!("({} ({}))", durations.iter().sum::<u64>(), rqq_list(durations, weights, 1))
+}
+
+fn rqq_list(durations: &[u64], weights: &[u32], depth: usize) -> String {
+    let len = durations.len().min(weights.len());
+    let max_weight = weights[..len].iter().skip(1).max();
+
+    let group_starts: Vec<usize> = match max_weight {
+        Some(&max_weight) if depth < MAX_IMPORT_DEPTH => {
+            once(0).chain((1..len).filter(|&idx| weights[idx] == max_weight)).collect()
+        },
+        _ => (0..len).collect(),
+    };
+
+    group_starts.iter()
+        .zip(group_starts.iter().skip(1).chain(once(&len)))
+        .map(|(&from, &to)| {
+            if to - from == 1 {
+                durations[from].to_string()
+            } else {
+                format!("({} ({}))",
+                        durations[from..to].iter().sum::<u64>(),
+                        rqq_list(&durations[from..to], &weights[from..to], depth + 1))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
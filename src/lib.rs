@@ -1,17 +1,41 @@
 use nih_plug::prelude::*;
-use std::sync::{Arc};
+use nih_plug::nih_log;
+use nih_plug::wrapper::state::PluginState;
+use std::sync::{Arc, Mutex};
 use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use rtrb::{Consumer, Producer};
+use crate::beat_event::{beat_event_queue, BeatEvent};
 use crate::metre::beat_origin::BeatOrigin;
 use crate::metre::beat_origin::BeatOrigin::*;
+use crate::metre::combined_metre_data::CombinedMetreData;
 use crate::metre::metric_phase::MetricPhase;
-use crate::params::{MetreFiddlerParams, ParamsSnapShot};
-use crate::util::{dry_wet, rescale};
+use crate::micro_timing::{DelayedEvents, NoteOffsets, DELAYED_EVENTS_CAPACITY};
+use crate::midi_clock::{MidiClock, MidiSystemMessage, CLOCKS_PER_QUARTER};
+use crate::params::{BarMarker, ChordMode, MetreFiddlerParams, ParamsSnapShot, RatchetMode, TransposeMode};
+use crate::pitch_transform::TransposedNotes;
+use crate::ratchet::{Ratchet, RatchetedNote, MAX_RATCHETED_NOTES};
+use crate::render::{export_dir, export_midi, new_export_path, MidiExport, RENDER_SAMPLE_RATE};
+use crate::state::migrate_state;
+use crate::util::dry_wet;
 
 mod editor;
-mod metre;
+pub mod metre;
 mod gui;
-mod util;
-mod params;
+pub mod util;
+pub mod params;
+pub mod render;
+pub mod headless;
+pub mod preset;
+pub mod import;
+pub mod history;
+pub mod beat_event;
+pub mod state;
+pub mod midi_clock;
+pub mod chord;
+pub mod pitch_transform;
+pub mod micro_timing;
+pub mod ratchet;
+pub mod controller_scaling;
 
 
 struct MetreFiddler {
@@ -19,29 +43,245 @@ struct MetreFiddler {
     params_snapshot: ParamsSnapShot,
     sample_rate: f32,
     metric_phase: MetricPhase,
+    // When rendering offline, all parameter values come from params_snapshot without smoothing.
+    offline: bool,
 
     last_reset_phase_value: bool,
     last_sent_beat_idx: i32,
+    last_reported_beat_idx: i32,
+    last_group_beat_idx: i32,
     note_off_buffer: Vec<Option<(u8, i32, i64)>>,
     was_playing: bool,
+    // The metre slots that were last prepared, None until the first buffer.
+    requested_slots: Option<(usize, usize)>,
+    // Set at the start of a bar, until the pending metre data could be switched to.
+    metre_switch_due: bool,
+    // The metre data that was switched away from, until process() hands it to the background to drop.
+    retired_metre_data: Option<Box<CombinedMetreData>>,
+    last_position_in_bar: f32,
+    midi_clock: MidiClock,
+    // Set when the metric phase wrapped to a new bar, until the bar start marker is sent.
+    bar_started: bool,
+    // The last sent MIDI clock tick, counted from the start of the bar.
+    last_sent_clock: Option<u64>,
+    transposed_notes: TransposedNotes,
+    // Incoming notes whose repeats are sent until they are released.
+    ratcheted_notes: Vec<RatchetedNote>,
+    // Every event is sent latency_samples later, incoming notes are moved from there.
+    latency_samples: u32,
+    delayed_events: DelayedEvents<MidiSystemMessage>,
+    note_offsets: NoteOffsets,
+
+    // Triggered beats are reported to the Gui, which keeps the consumer between openings.
+    beat_event_producer: Producer<BeatEvent>,
+    beat_event_consumer: Arc<Mutex<Consumer<BeatEvent>>>,
 }
 
 impl Default for MetreFiddler {
     fn default() -> Self {
         let default_params = Arc::new(MetreFiddlerParams::default());
+        let (beat_event_producer, beat_event_consumer) = beat_event_queue();
         Self {
             params: default_params.clone(),
             params_snapshot: ParamsSnapShot::default(),
             sample_rate: 1.0,
             metric_phase: MetricPhase::default(),
+            offline: false,
             last_reset_phase_value: false,
             last_sent_beat_idx: -1,
-            note_off_buffer: vec![None; 8],
+            last_reported_beat_idx: -1,
+            last_group_beat_idx: -1,
+            note_off_buffer: vec![None; 256],
             was_playing: false,
+            requested_slots: None,
+            metre_switch_due: false,
+            retired_metre_data: None,
+            last_position_in_bar: 0.0,
+            midi_clock: MidiClock::default(),
+            bar_started: false,
+            last_sent_clock: None,
+            transposed_notes: TransposedNotes::default(),
+            ratcheted_notes: Vec::with_capacity(MAX_RATCHETED_NOTES),
+            latency_samples: 0,
+            delayed_events: DelayedEvents::with_capacity(DELAYED_EVENTS_CAPACITY),
+            note_offsets: NoteOffsets::default(),
+            beat_event_producer,
+            beat_event_consumer: Arc::new(Mutex::new(beat_event_consumer)),
         }
     }
 }
 
+/// Work that is done off the audio thread.
+pub enum MetreFiddlerTask {
+    /// Prepare the metre data for a pair of metre slots.
+    PrepareSlots(usize, usize),
+    /// Drop the metre data that the audio thread switched away from, freeing its memory.
+    DropMetreData(Box<CombinedMetreData>),
+    /// Render the pattern into a new MIDI file, started from the Gui.
+    ExportMidi(Box<MidiExport>),
+}
+
+/// Build the metre data for a pair of metre slots and leave it for the audio thread, which
+/// switches to it at the next bar.
+pub(crate) fn prepare_metre_slots(params: &MetreFiddlerParams, slot_a: usize, slot_b: usize) {
+    let (current_a, current_b) = {
+        let metric_data = params.combined_metre_data.lock().unwrap();
+        (metric_data.metre_a().clone(), metric_data.metre_b().clone())
+    };
+    let result = params.metre_slots.lock().unwrap().combine(slot_a, slot_b, &current_a, &current_b);
+
+    match result {
+        Ok(metric_data) => *params.pending_metre_data.lock().unwrap() = Some(Box::new(metric_data)),
+        Err(err_string) => nih_log!("Failed to prepare metre slots {} and {}: {}", slot_a + 1, slot_b + 1, err_string),
+    }
+}
+
+/// Render an export into a new file in the export directory and leave a message for the Gui.
+pub(crate) fn export_midi_file(params: &MetreFiddlerParams, export: MidiExport) {
+    let chosen_dir = params.export_dir.lock().unwrap().clone();
+    let exported = export_dir(&chosen_dir).and_then(|dir| {
+        let path = new_export_path(&dir);
+        export_midi(
+            export.combined_metre_data,
+            export.snapshot,
+            export.nr_of_bars,
+            export.tempo,
+            RENDER_SAMPLE_RATE,
+            &path,
+        ).map(|nr_of_notes| (nr_of_notes, path))
+    });
+    let message = match exported {
+        Ok((nr_of_notes, path)) => format!("Exported {} notes to {}", nr_of_notes, path.display()),
+        Err(err_string) => {
+            nih_log!("Failed to export MIDI file: {}", err_string);
+            err_string
+        },
+    };
+    *params.export_message.lock().unwrap() = Some(message);
+}
+
+/// Do the work of a MetreFiddlerTask. Called from the background thread, or right away when
+/// running headless.
+pub(crate) fn execute_task(params: &MetreFiddlerParams, task: MetreFiddlerTask) {
+    match task {
+        MetreFiddlerTask::PrepareSlots(slot_a, slot_b) => prepare_metre_slots(params, slot_a, slot_b),
+        MetreFiddlerTask::DropMetreData(metric_data) => drop(metric_data),
+        MetreFiddlerTask::ExportMidi(export) => export_midi_file(params, *export),
+    }
+}
+
+/// Where the processing gets its incoming note events from and sends its outgoing events to.
+/// This is the host's ProcessContext during playback, or a recorder when rendering offline, so
+/// both run through the same code.
+trait NoteEventIO {
+    fn next_event(&mut self) -> Option<PluginNoteEvent<MetreFiddler>>;
+    fn send_event(&mut self, event: PluginNoteEvent<MetreFiddler>);
+}
+
+/// The rest of the host's ProcessContext that process_buffer() uses, so that the headless
+/// processor can stand in for the host.
+trait HostContext: NoteEventIO {
+    fn playing(&self) -> bool;
+    fn tempo(&self) -> Option<f64>;
+    /// The plain values of all parameters for this buffer.
+    fn snapshot(&self, params: &MetreFiddlerParams, last: &ParamsSnapShot) -> ParamsSnapShot;
+    fn set_latency_samples(&self, samples: u32);
+    fn execute_background(&self, task: MetreFiddlerTask);
+}
+
+struct HostEventIO<'a, C: ProcessContext<MetreFiddler>>(&'a mut C);
+
+impl<C: ProcessContext<MetreFiddler>> NoteEventIO for HostEventIO<'_, C> {
+    fn next_event(&mut self) -> Option<PluginNoteEvent<MetreFiddler>> {
+        self.0.next_event()
+    }
+
+    fn send_event(&mut self, event: PluginNoteEvent<MetreFiddler>) {
+        self.0.send_event(event)
+    }
+}
+
+impl<C: ProcessContext<MetreFiddler>> HostContext for HostEventIO<'_, C> {
+    fn playing(&self) -> bool {
+        self.0.transport().playing
+    }
+
+    fn tempo(&self) -> Option<f64> {
+        self.0.transport().tempo
+    }
+
+    fn snapshot(&self, params: &MetreFiddlerParams, last: &ParamsSnapShot) -> ParamsSnapShot {
+        params.snapshot_since(last)
+    }
+
+    fn set_latency_samples(&self, samples: u32) {
+        self.0.set_latency_samples(samples)
+    }
+
+    fn execute_background(&self, task: MetreFiddlerTask) {
+        self.0.execute_background(task)
+    }
+}
+
+/// Sends every event through the delayed events, latency samples later than its timing. The host
+/// takes the reported latency off again, so generated notes and MIDI clock stay on its grid while
+/// incoming notes can be moved up to latency samples earlier.
+struct DelayingEventIO<'a, I: NoteEventIO> {
+    io: &'a mut I,
+    delayed_events: &'a mut DelayedEvents<MidiSystemMessage>,
+    latency: u32,
+}
+
+impl<I: NoteEventIO> DelayingEventIO<'_, I> {
+    /// Send an event offset samples later (or earlier) than the others. Events that end up at their
+    /// own timing are sent right away, and so are events that don't fit in the delayed events.
+    fn send_moved_event(&mut self, event: PluginNoteEvent<MetreFiddler>, offset: i64) {
+        if self.latency as i64 + offset == 0 {
+            return self.send_now(event);
+        }
+        let timing = (event.timing() as i64 + self.latency as i64 + offset).max(0);
+        if let Err(event) = self.delayed_events.push(timing as u64, event) {
+            self.send_now(event);
+        }
+    }
+
+    /// Send an event at its timing, after the delayed events that are due before it.
+    fn send_now(&mut self, event: PluginNoteEvent<MetreFiddler>) {
+        self.delayed_events.send_until(event.timing() as u64, |event| self.io.send_event(event));
+        self.io.send_event(event);
+    }
+
+    fn send_due_events(&mut self, nr_samples: usize) {
+        self.delayed_events.send_due(nr_samples, |event| self.io.send_event(event));
+    }
+}
+
+impl<I: NoteEventIO> NoteEventIO for DelayingEventIO<'_, I> {
+    fn next_event(&mut self) -> Option<PluginNoteEvent<MetreFiddler>> {
+        self.io.next_event()
+    }
+
+    fn send_event(&mut self, event: PluginNoteEvent<MetreFiddler>) {
+        self.send_moved_event(event, 0)
+    }
+}
+
+/// Everything about the beat at the current position that is read from the metre data.
+#[derive(Debug, Clone, Copy)]
+struct IndispData {
+    beat_idx: usize,
+    // The normalized duration up until the beat.
+    duration_sum: f32,
+    indisp_val: usize,
+    // Whether the thresholds would currently let a note through.
+    let_through: bool,
+    origin: BeatOrigin,
+    max_indisp_val: usize,
+    // The stratum of the group that starts with the beat, 0 when it doesn't start one.
+    stratum: usize,
+    ratchet: Option<Ratchet>,
+}
+
 impl MetreFiddler {
 
     fn hande_playback_start_stop(&mut self, is_playing: bool) {
@@ -50,20 +290,235 @@ impl MetreFiddler {
         } else if is_playing && !self.was_playing {
             self.metric_phase.reset();
             self.was_playing = true;
-            self.last_sent_beat_idx = -1;
+            self.restart_bar();
         }
     }
 
-    fn is_indisp_val_within_thresholds(&self, indisp_val: usize, max_indisp_val: usize) -> bool {
-        indisp_val >= (self.params_snapshot.lower_threshold.min(self.params_snapshot.upper_threshold) * max_indisp_val as f32) as usize
-            && indisp_val <= (self.params_snapshot.upper_threshold * max_indisp_val as f32) as usize
+    /// Forget what was sent in the last bar, so the first beat and marker are sent again.
+    fn restart_bar(&mut self) {
+        self.last_sent_beat_idx = -1;
+        self.last_reported_beat_idx = -1;
+        self.last_group_beat_idx = -1;
+        self.last_sent_clock = None;
+        self.bar_started = true;
+    }
+
+    /// Whether the metric phase moves on. When syncing to MIDI clock, the clock decides instead
+    /// of the host.
+    fn is_playing(&self, transport_playing: bool) -> bool {
+        if self.params_snapshot.midi_clock_sync {
+            self.midi_clock.is_running()
+        } else {
+            transport_playing
+        }
+    }
+
+    /// The tempo measured from MIDI clock when syncing to it, otherwise the host's tempo.
+    fn current_tempo(&self, host_tempo: Option<f64>) -> Option<f64> {
+        if self.params_snapshot.midi_clock_sync {
+            self.midi_clock.tempo(self.sample_rate).or(host_tempo)
+        } else {
+            host_tempo
+        }
+    }
+
+    /// Follow MIDI clock and Song Position Pointer messages. When syncing to them, every tick
+    /// sets the metric phase to the clock's position.
+    fn receive_system_message(&mut self, message: MidiSystemMessage, tempo: Option<f64>) {
+        let position = self.midi_clock.receive(message);
+        if !self.params_snapshot.midi_clock_sync {
+            return;
+        }
+
+        // Starting and stopping is handled here, so the next buffer doesn't restart the bar.
+        match message {
+            MidiSystemMessage::Start => {
+                self.metric_phase.reset();
+                self.restart_bar();
+                self.was_playing = true;
+            },
+            MidiSystemMessage::Continue => self.was_playing = true,
+            MidiSystemMessage::Stop => self.was_playing = false,
+            _ => (),
+        }
+
+        if let Some(quarters) = position {
+            let quarters_per_bar = self.quarters_per_bar(tempo);
+            if self.metric_phase.lock_to_quarters(quarters, quarters_per_bar) {
+                self.bar_started = true;
+            }
+        }
+    }
+
+    /// The length of a bar in quarter notes.
+    fn quarters_per_bar(&self, tempo: Option<f64>) -> f64 {
+        let metric_dur = self.params_snapshot.metric_dur as f64;
+        // Without BPM, the metric duration is in seconds.
+        if self.params_snapshot.use_bpm {
+            metric_dur
+        } else {
+            metric_dur * tempo.unwrap_or(60.0) / 60.0
+        }
+    }
+
+    /// Send MIDI clock ticks that follow the metric phase, and mark the start of a bar.
+    fn send_clock_and_bar_marker(&mut self, sample_id: usize, tempo: Option<f64>, io: &mut impl NoteEventIO) {
+        let timing = sample_id as u32;
+
+        if self.params_snapshot.send_midi_clock {
+            let clocks_per_bar = self.quarters_per_bar(tempo) * CLOCKS_PER_QUARTER as f64;
+            let clock = (self.metric_phase.progress_in_samples() as f64 * clocks_per_bar
+                / self.metric_phase.metric_duration_samples() as f64).floor() as u64;
+            if self.last_sent_clock != Some(clock) {
+                io.send_event(NoteEvent::MidiSysEx { timing, message: MidiSystemMessage::Clock });
+                self.last_sent_clock = Some(clock);
+            }
+        }
+
+        if std::mem::take(&mut self.bar_started) {
+            let number = self.params_snapshot.bar_marker_number;
+            match self.params_snapshot.bar_marker {
+                BarMarker::Off => (),
+                BarMarker::Note => self.send_note(io, sample_id, number, 1.0, self.note_length()),
+                BarMarker::ControlChange => io.send_event(
+                    NoteEvent::MidiCC { timing, channel: 0, cc: number, value: 1.0 }
+                ),
+            }
+        }
+    }
+
+    /// How many samples a sent note lasts, unless a ratchet repeat comes sooner.
+    fn note_length(&self) -> u64 {
+        (0.1 * self.sample_rate).floor() as u64
+    }
+
+    /// Send a note at the sample_id and schedule its NoteOff length samples later.
+    fn send_note(&mut self, io: &mut impl NoteEventIO, sample_id: usize, note: u8, velocity: f32, length: u64) {
+        // Notes that start together, like the voices of a chord, need their own voice ids.
+        let voice_id = sample_id as i32 * 128 + note as i32;
+        io.send_event(
+            NoteEvent::NoteOn {
+                timing: sample_id as u32,
+                velocity,
+                channel: 0,
+                note,
+                voice_id: Some(voice_id),
+            });
+
+        // send a Note Off into self.note_off_buffer
+        let release_timing = (sample_id as u64 + length) as i64;
+        if let Some(slot) = self.note_off_buffer.iter_mut().find(|e| e.is_none()) {
+            *slot = Some((note, voice_id, release_timing));
+        }
+    }
+
+    /// Send the note or the notes of the chord for a beat.
+    fn send_beat(&mut self, io: &mut impl NoteEventIO, sample_id: usize, beat: &IndispData, velocity: f32, length: u64) {
+        let indisp_val = beat.indisp_val;
+        let chord = self.params_snapshot.chord;
+        let root = self.params_snapshot.chord_root;
+
+        match self.params_snapshot.chord_mode {
+            ChordMode::Off => {
+                let note = 60
+                    + if self.params_snapshot.output_one_pitch {
+                    0
+                } else if self.params_snapshot.many_velocities {
+                    indisp_val as u8
+                } else if self.indisp_is_accent(indisp_val) {
+                    0
+                } else {
+                    1
+                };
+                self.send_note(io, sample_id, note, velocity, length);
+            },
+            ChordMode::Chords => {
+                for note in chord.voicing(root, indisp_val, beat.max_indisp_val) {
+                    self.send_note(io, sample_id, note, velocity, length);
+                }
+            },
+            ChordMode::Arpeggio => self.send_note(io, sample_id, chord.arpeggio_note(root, indisp_val), velocity, length),
+        }
+    }
+
+    /// Send a beat as the repeats of its ratchet. Each repeat ends before the next one starts.
+    fn send_ratchet(&mut self, io: &mut impl NoteEventIO, sample_id: usize, beat: &IndispData, velocity: f32, ratchet: Ratchet) {
+        for (idx, &offset) in ratchet.offsets().iter().enumerate() {
+            let length = self.note_length().min(ratchet.gap(idx).saturating_sub(1)).max(1);
+            let velocity = ratchet.velocity(idx, velocity, self.params_snapshot.ratchet_ramp);
+            self.send_beat(io, sample_id + offset as usize, beat, velocity, length);
+        }
+    }
+
+    /// The ratchet for the beat at position, when its indispensability value passes the ratchet
+    /// threshold.
+    fn ratchet_at(&self, metric_data: &CombinedMetreData, position: f32, indisp_val: usize) -> Option<Ratchet> {
+        let snapshot = &self.params_snapshot;
+        if !snapshot.is_ratcheted(indisp_val, metric_data.max_indisp_val()) {
+            return None;
+        }
+
+        let (beat_dur, subdivisions) = metric_data.get_subdivisions_at(
+            position,
+            snapshot.interpolate,
+            snapshot.interpolate_durs,
+        );
+        let beat_samples = (beat_dur * self.metric_phase.metric_duration_samples() as f32).round() as u64;
+
+        match snapshot.ratchet_mode {
+            RatchetMode::Off => None,
+            RatchetMode::Even => Some(Ratchet::even(beat_samples, snapshot.ratchet_repeats)),
+            RatchetMode::Metric => Some(Ratchet::metric(beat_samples, subdivisions)),
+        }
+    }
+
+    /// Send the first repeat of a passed incoming NoteOn, the others are sent by
+    /// send_ratchet_repeats() for as long as the note is held.
+    fn send_incoming_ratchet<I: NoteEventIO>(&mut self, io: &mut DelayingEventIO<I>, event: PluginNoteEvent<Self>, offset: i64, ratchet: Ratchet) {
+        let NoteEvent::NoteOn { timing, voice_id, channel, note, velocity } = event else {
+            return io.send_moved_event(event, offset);
+        };
+        let ratcheted = RatchetedNote::new(channel, note, velocity, offset, voice_id, ratchet);
+        if self.ratcheted_notes.len() < MAX_RATCHETED_NOTES {
+            self.ratcheted_notes.push(ratcheted);
+        }
+        let velocity = ratcheted.repeat_velocity(0, self.params_snapshot.ratchet_ramp);
+        io.send_moved_event(NoteEvent::NoteOn { timing, voice_id, channel, note, velocity }, offset);
+    }
+
+    /// Send the repeats of the ratcheted incoming notes that start at this sample. Every repeat
+    /// ends the one before, the last one sounds until the note is released.
+    fn send_ratchet_repeats<I: NoteEventIO>(&mut self, io: &mut DelayingEventIO<I>, sample_id: usize) {
+        let ramp = self.params_snapshot.ratchet_ramp;
+        let timing = sample_id as u32;
+
+        for ratcheted in self.ratcheted_notes.iter_mut() {
+            let (channel, note) = (ratcheted.channel, ratcheted.note);
+            while let Some(repeat) = ratcheted.next_repeat() {
+                let voice_id = ratcheted.voice_id(repeat - 1);
+                io.send_moved_event(NoteEvent::NoteOff { timing, voice_id, channel, note, velocity: 0.0 }, ratcheted.offset);
+                let (voice_id, velocity) = (ratcheted.voice_id(repeat), ratcheted.repeat_velocity(repeat, ramp));
+                io.send_moved_event(NoteEvent::NoteOn { timing, voice_id, channel, note, velocity }, ratcheted.offset);
+            }
+            ratcheted.advance();
+        }
+    }
+
+    /// Stop repeating the note, when it is ratcheted. Returns the repeat that is sounding.
+    fn stop_ratchet(&mut self, channel: u8, note: u8) -> Option<RatchetedNote> {
+        let idx = self.ratcheted_notes.iter().position(|r| r.channel == channel && r.note == note)?;
+        Some(self.ratcheted_notes.swap_remove(idx))
+    }
+
+    fn is_indisp_val_within_thresholds(&self, metric_data: &CombinedMetreData, beat_idx: usize, indisp_val: usize, nr_beats: usize) -> bool {
+        self.params_snapshot.is_within_thresholds(metric_data, beat_idx, indisp_val, nr_beats)
     }
 
     // Get the normalized time within a measure (between 0.0 and 1.0) depending on the current
     // progress_in_samples or the bar_pos.
     fn get_normalized_position_in_bar(&self) -> f32 {
         // Get the normalized time within a measure (between 0.0 and 1.0)
-        if self.params.use_position.value() {
+        if self.params_snapshot.use_position {
             self.params_snapshot.bar_pos
         } else {
             let pos = self.metric_phase.metric_phase();
@@ -73,134 +528,98 @@ impl MetreFiddler {
     }
 
     fn indisp_is_accent(&self, indisp_value: usize) -> bool {
-        let skew = self.params_snapshot.vel_skew;
-        let nr_beats = self.params.current_nr_of_beats.load(Acquire) as f32;
-        let nr_of_accents = (skew * nr_beats).round() as usize;
-        indisp_value >= nr_of_accents
+        self.params_snapshot.is_accent(indisp_value, self.params.current_nr_of_beats.load(Acquire))
     }
 
     fn calculate_current_velocity(&self, indisp_value: usize) -> f32 {
-        // The current velocity Parameters
-        let v_min: f32 = self.params_snapshot.vel_min.min(self.params_snapshot.vel_max) / 127.0;
-        let v_max: f32 = self.params_snapshot.vel_min.max(self.params_snapshot.vel_max) / 127.0;
-        let skew = self.params_snapshot.vel_skew;
-        let many_velocities = self.params_snapshot.many_velocities;
-        // Velocity in range 0.0 - 1.0,
-        let normalized_vel =
-            if many_velocities {
-                (1.0 / (indisp_value + 1) as f32).powf(2.0*(1.0 - skew))
-            } else if self.indisp_is_accent(indisp_value) {
-                v_min
-            } else {
-                v_max
-            };
-        // rescaled by vel_min and vel_max parameters
-        if v_min == v_max {
-            v_min
-        } else {
-            rescale(normalized_vel, 0.0, 1.0, v_min, v_max, true)
-                .unwrap_or(0.8)
-        }
+        self.params_snapshot.velocity(indisp_value, self.params.current_nr_of_beats.load(Acquire))
     }
 
-    fn get_beat_idx_from_durations(&self, mut durations: impl Iterator<Item=f32>) -> (usize, f32, usize) {
-        let position = self.get_normalized_position_in_bar();
-        let mut current_beat_idx: usize = 0;
-        let mut current_beat_duration_sum: f32 = 0.0;
-        let mut nr_beats = 0;
-
-        while let Some(dur) = durations.next() {
-            nr_beats += 1;
+    /// The velocity of a sent note, faded in or out by the interpolation when the beat only exists
+    /// in one metre and indispensability values are not interpolated.
+    fn calculate_beat_velocity(&self, indisp_value: usize, origin: BeatOrigin) -> f32 {
+        let vel = self.calculate_current_velocity(indisp_value);
 
-            if current_beat_duration_sum + dur >= position {
-                nr_beats += durations.count();
-                break;
+        if self.params_snapshot.interpolate_indisp {
+            vel
+        } else {
+            match origin {
+                Both => vel,
+                MetreA => dry_wet(vel, 0.0, self.params_snapshot.interpolate),
+                MetreB => dry_wet(0.0, vel, self.params_snapshot.interpolate),
             }
+        }
+    }
+
+    /// The selected metre slots, when they changed since they were last prepared. The slots that
+    /// are selected when the plugin starts belong to the restored metres and are not prepared.
+    fn changed_metre_slots(&mut self) -> Option<(usize, usize)> {
+        let slots = (self.params_snapshot.slot_a, self.params_snapshot.slot_b);
+        let requested = self.requested_slots.replace(slots);
+        requested.filter(|requested| *requested != slots).map(|_| slots)
+    }
 
-            current_beat_duration_sum += dur;
-            current_beat_idx += 1;
+    /// Switch to the metre data of newly selected slots, if it has been prepared. This never
+    /// waits for another thread: while the metre data is locked, the switch is tried again with
+    /// the next sample. The old metre data is kept in retired_metre_data, so that it isn't
+    /// dropped on the audio thread.
+    fn switch_to_pending_metre(&mut self) {
+        if self.retired_metre_data.is_some() {
+            return;
         }
+        let Ok(mut pending) = self.params.pending_metre_data.try_lock() else {
+            return;
+        };
+        let Ok(mut metric_data) = self.params.combined_metre_data.try_lock() else {
+            return;
+        };
+        if let Some(new_metric_data) = pending.as_mut() {
+            std::mem::swap(&mut *metric_data, &mut **new_metric_data);
+            self.retired_metre_data = pending.take();
+            self.params.metre_switches.fetch_add(1, Release);
+        }
+        self.metre_switch_due = false;
+    }
 
-        (current_beat_idx, current_beat_duration_sum, nr_beats)
+    /// Tell the Gui that a beat was triggered. When the Gui doesn't keep up, the beat is dropped.
+    fn report_beat(&mut self, beat_idx: usize, indisp_val: usize, velocity: f32, passed: bool) {
+        let _ = self.beat_event_producer.push(BeatEvent { beat_idx, indisp_val, velocity, passed });
     }
 
-    /// return a tuple with the index of the current beat, the normalized duration up until that beat,
-    /// the indispensability value for that beat, whether the thresholds would currently let
-    /// a note through and the Origin of the current Beat.
-    fn get_current_indisp_data(&self) -> (usize, f32, usize, bool, BeatOrigin) {
+    /// Read everything about the current beat from the metre data. The metre data is only locked
+    /// once, so this is done once per sample or event and handed to whatever needs it.
+    fn get_current_indisp_data(&self) -> IndispData {
         // TODO ideally we never want to lock in the audio thread, can this be replaced with rtrb?
         let metric_data = &self.params.combined_metre_data.lock().unwrap();
-        let metric_data_a = metric_data.metre_a();
-        let metric_data_b = metric_data.metre_b();
-        let interpolation_data = metric_data.interpolation_data();
-        let max_len = metric_data_a.durations.len().max(metric_data_b.durations.len());
-
-        let current_beat_idx_a;
-        let current_beat_idx_b;
-        let current_beat_idx;
-        let current_beat_duration_sum;
-        let current_beat_origin: BeatOrigin;
+        let snapshot = &self.params_snapshot;
+        let position = self.get_normalized_position_in_bar();
 
         // TODO no_many_velocities + don't_interpolate is a bit confusing for the user
 
-        if self.params_snapshot.interpolate_durs {
-            let durations = interpolation_data.get_interpolated_durations(self.params_snapshot.interpolate);
-            let (idx, sum, total_nr_beats) = self.get_beat_idx_from_durations(durations);
+        let (beat_idx, duration_sum, indisp_val, total_nr_beats, origin) =
+            metric_data.get_indisp_data_at(
+                position,
+                snapshot.interpolate,
+                snapshot.interpolate_durs,
+                snapshot.interpolate_indisp,
+            );
+        self.params.current_nr_of_beats.store(total_nr_beats, Release);
 
-            current_beat_idx_a = idx;
-            current_beat_idx_b = idx;
-            current_beat_idx = idx;
-            current_beat_duration_sum = sum;
-            current_beat_origin = Both;
-            self.params.current_nr_of_beats.store(total_nr_beats, Release);
-        } else {
-            let durations = metric_data.get_interleaved_durations(self.params_snapshot.interpolate);
-            let (idx, sum, total_nr_beats) = self.get_beat_idx_from_durations(durations);
-            (current_beat_idx_a, _, _) = self.get_beat_idx_from_durations(metric_data_a.durations.iter().copied());
-            (current_beat_idx_b, _, _) = self.get_beat_idx_from_durations(metric_data_b.durations.iter().copied());
-
-            current_beat_idx = idx;
-            current_beat_duration_sum = sum;
-            current_beat_origin = match self.params_snapshot.interpolate {
-                x if x <= 0.0 => MetreA,
-                x if x >= 1.0 => MetreB,
-                _ => interpolation_data.unique_start_time_origins()[idx],
-            };
-            self.params.current_nr_of_beats.store(total_nr_beats, Release);
-        }
-
-        let indisp_val_temp: f32 =
-            if self.params_snapshot.interpolate_indisp|| current_beat_origin == Both {
-                dry_wet(
-                    *metric_data_a.value.get(current_beat_idx_a).unwrap_or(&0),
-                    *metric_data_b.value.get(current_beat_idx_b).unwrap_or(&0),
-                    self.params_snapshot.interpolate)
-            } else {
-                match current_beat_origin {
-                    MetreA => *metric_data_a.value.get(current_beat_idx_a).unwrap_or(&0) as f32,
-                    MetreB => *metric_data_b.value.get(current_beat_idx_b).unwrap_or(&0) as f32,
-                    // this should never occur:
-                    _ => 0.0,
-                }
-            };
-
-        // Not yet sure whether this is the way to go but seems fine for now...
-        let indisp_val: usize = if metric_data_a.durations.len() == metric_data_b.durations.len() {
-            indisp_val_temp.round() as usize
-        } else {
-            indisp_val_temp.ceil() as usize
-        };
-
-        (current_beat_idx,
-         current_beat_duration_sum,
-         indisp_val,
-         self.is_indisp_val_within_thresholds(indisp_val, max_len - 1),
-         current_beat_origin)
+        IndispData {
+            beat_idx,
+            duration_sum,
+            indisp_val,
+            let_through: self.is_indisp_val_within_thresholds(metric_data, beat_idx, indisp_val, total_nr_beats),
+            origin,
+            max_indisp_val: metric_data.max_indisp_val(),
+            stratum: metric_data.get_gnsm_at(beat_idx, snapshot.interpolate, snapshot.interpolate_durs),
+            ratchet: self.ratchet_at(metric_data, position, indisp_val),
+        }
     }
 
     /// Get a MIDI event and either return none (filter it) or return it with a new velocity
     /// value (according to the current metric position).
-    fn process_note_event<S: SysExMessage>(&mut self, event: NoteEvent<S>) -> Option<NoteEvent<S>> {
+    fn process_note_event<S: SysExMessage>(&mut self, event: NoteEvent<S>, beat: &IndispData) -> Option<NoteEvent<S>> {
         match event {
             NoteEvent::NoteOn {
                 timing,
@@ -209,10 +628,10 @@ impl MetreFiddler {
                 note,
                 ..
             } => {
-                let (_,_, indisp_val, let_through, _) = self.get_current_indisp_data();
-                let vel: f32 = self.calculate_current_velocity(indisp_val);
+                let vel: f32 = self.calculate_current_velocity(beat.indisp_val);
+                self.report_beat(beat.beat_idx, beat.indisp_val, vel, beat.let_through);
 
-                if let_through {
+                if beat.let_through {
                 Some(NoteEvent::NoteOn {
                     timing,
                     voice_id,
@@ -226,62 +645,117 @@ impl MetreFiddler {
             _ => None,
         }
     }
-}
 
-impl Plugin for MetreFiddler {
-    const NAME: &'static str = "MetreFiddler";
-    const VENDOR: &'static str = "Leon Focker";
-    const URL: &'static str = "https://youtu.be/dQw4w9WgXcQ";
-    const EMAIL: &'static str = "contact@leonfocker.de";
-
-    const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+    /// Transpose a passed NoteOn by the metric weight of its beat and quantize it to the chord.
+    fn transform_pitch<S: SysExMessage>(&mut self, event: NoteEvent<S>, beat: &IndispData) -> NoteEvent<S> {
+        match event {
+            NoteEvent::NoteOn { timing, voice_id, channel, note, velocity } => {
+                let sent_note = self.transformed_pitch(note, beat);
+                self.transposed_notes.note_on(channel, note, sent_note);
+                NoteEvent::NoteOn { timing, voice_id, channel, note: sent_note, velocity }
+            },
+            _ => event,
+        }
+    }
 
-    const AUDIO_IO_LAYOUTS: &'static [AudioIOLayout] = &[
-    ];
-    
-    const MIDI_INPUT: MidiConfig = MidiConfig::MidiCCs;
-    const MIDI_OUTPUT: MidiConfig = MidiConfig::MidiCCs;
-    const SAMPLE_ACCURATE_AUTOMATION: bool = true;
+    /// Give a NoteOff the pitch that its NoteOn was sent with.
+    fn release_pitch<S: SysExMessage>(&mut self, event: NoteEvent<S>) -> NoteEvent<S> {
+        match event {
+            NoteEvent::NoteOff { timing, voice_id, channel, note, velocity } => {
+                let sent_note = self.transposed_notes.note_off(channel, note);
+                NoteEvent::NoteOff { timing, voice_id, channel, note: sent_note, velocity }
+            },
+            _ => event,
+        }
+    }
 
-    type SysExMessage = ();
-    type BackgroundTask = ();
+    /// How many samples an incoming NoteOn is moved by the metric weight of its beat.
+    fn note_on_offset<S: SysExMessage>(&mut self, event: &NoteEvent<S>, beat: &IndispData) -> i64 {
+        match *event {
+            NoteEvent::NoteOn { channel, note, .. } => {
+                let offset_ms = self.params_snapshot.timing_offset(beat.indisp_val, beat.max_indisp_val);
+                let offset = (offset_ms / 1000.0 * self.sample_rate).round() as i64;
+                self.note_offsets.note_on(channel, note, offset);
+                offset
+            },
+            _ => 0,
+        }
+    }
 
-    fn params(&self) -> Arc<dyn Params> {
-        self.params.clone()
+    /// How many samples an incoming NoteOff is moved, the same as its NoteOn.
+    fn note_off_offset<S: SysExMessage>(&mut self, event: &NoteEvent<S>) -> i64 {
+        match *event {
+            NoteEvent::NoteOff { channel, note, .. } => self.note_offsets.note_off(channel, note),
+            _ => 0,
+        }
     }
 
-    fn editor(&mut self, _async_executor: AsyncExecutor<Self>) -> Option<Box<dyn Editor>> {
-        editor::create(
-            self.params.clone(),
-            self.params.editor_state.clone(),
-        )
+    /// Scale the value of a chosen CC, or aftertouch, by the metric weight of its position. All
+    /// other events are returned unchanged.
+    fn scale_controller<S: SysExMessage>(&self, event: NoteEvent<S>) -> NoteEvent<S> {
+        let snapshot = &self.params_snapshot;
+        if snapshot.metric_cc_depth <= 0.0 {
+            return event;
+        }
+
+        match event {
+            NoteEvent::MidiCC { timing, channel, cc, value } if snapshot.metric_ccs.contains(cc) => {
+                NoteEvent::MidiCC { timing, channel, cc, value: value * self.current_controller_scale() }
+            },
+            NoteEvent::PolyPressure { timing, voice_id, channel, note, pressure } if snapshot.scale_aftertouch => {
+                NoteEvent::PolyPressure { timing, voice_id, channel, note, pressure: pressure * self.current_controller_scale() }
+            },
+            NoteEvent::MidiChannelPressure { timing, channel, pressure } if snapshot.scale_aftertouch => {
+                NoteEvent::MidiChannelPressure { timing, channel, pressure: pressure * self.current_controller_scale() }
+            },
+            _ => event,
+        }
     }
 
-    fn initialize(
-        &mut self,
-        _audio_io_layout: &AudioIOLayout,
-        buffer_config: &BufferConfig,
-        _context: &mut impl InitContext<Self>,
-    ) -> bool {
-        self.sample_rate = buffer_config.sample_rate;
-        true
+    fn current_controller_scale(&self) -> f32 {
+        let beat = self.get_current_indisp_data();
+        self.params_snapshot.controller_scale(beat.indisp_val, beat.max_indisp_val)
     }
 
-    fn process(
-        &mut self,
-        buffer: &mut Buffer,
-        _aux: &mut AuxiliaryBuffers,
-        context: &mut impl ProcessContext<Self>,
-    ) -> ProcessStatus {
-        let nr_samples_for_start_of_beat: u64 = (self.sample_rate / 500.0).ceil() as u64;
-        let mut next_event = context.next_event();
-        let buffer_len = buffer.samples();
+    fn transformed_pitch(&self, note: u8, beat: &IndispData) -> u8 {
+        let snapshot = &self.params_snapshot;
+        let transposed = match snapshot.transpose_mode {
+            TransposeMode::Off => note,
+            TransposeMode::ByRank => snapshot.transpose_table.transpose(beat.indisp_val, note),
+            TransposeMode::ByStratum => snapshot.transpose_table.transpose(beat.stratum, note),
+        };
 
+        if snapshot.quantize_to_chord {
+            snapshot.chord.quantize(snapshot.chord_root, transposed)
+        } else {
+            transposed
+        }
+    }
+}
+
+impl MetreFiddler {
+    /// Process one buffer of nr_samples samples: read the parameters, hand work to the background
+    /// and run process_block() with the host's transport. Used by process() and by the headless
+    /// processor.
+    fn process_buffer(&mut self, nr_samples: usize, context: &mut impl HostContext) {
         // Get all plain parameter values once here
-        self.params_snapshot = self.params.snapshot();
+        self.params_snapshot = context.snapshot(&self.params, &self.params_snapshot);
 
-        // reset metric phase when playback stops and more
-        self.hande_playback_start_stop(context.transport().playing);
+        if let Some((slot_a, slot_b)) = self.changed_metre_slots() {
+            context.execute_background(MetreFiddlerTask::PrepareSlots(slot_a, slot_b));
+        }
+
+        let latency_samples = self.params_snapshot.latency_samples(self.sample_rate);
+        if latency_samples != self.latency_samples {
+            context.set_latency_samples(latency_samples);
+        }
+
+        let playing = context.playing();
+        let tempo = context.tempo();
+        if let Some(tempo) = self.current_tempo(tempo) {
+            // message to gui, used when exporting midi
+            self.params.host_tempo.store(tempo as f32, Relaxed);
+        }
 
         // TODO this is still dodgy and only happens once per buffer
         // Handle the reset_phase button:
@@ -296,20 +770,57 @@ impl Plugin for MetreFiddler {
         }
         self.last_reset_phase_value = self.params.reset_phase.value();
 
-        for (sample_id, _) in buffer.iter_samples().enumerate() {
+        self.process_block(nr_samples, playing, tempo, context);
+
+        if let Some(metric_data) = self.retired_metre_data.take() {
+            context.execute_background(MetreFiddlerTask::DropMetreData(metric_data));
+        }
+    }
+
+    /// Process nr_samples samples: filter and modify incoming notes and send notes when Send Midi is
+    /// on. Used by process_buffer().
+    fn process_block(&mut self, nr_samples: usize, playing: bool, tempo: Option<f64>, io: &mut impl NoteEventIO) {
+        let nr_samples_for_start_of_beat: u64 = (self.sample_rate / 500.0).ceil() as u64;
+        self.latency_samples = self.params_snapshot.latency_samples(self.sample_rate);
+        let mut delayed_events = std::mem::take(&mut self.delayed_events);
+        let io = &mut DelayingEventIO { io, delayed_events: &mut delayed_events, latency: self.latency_samples };
+        let mut next_event = io.next_event();
+
+        // reset metric phase when playback stops and more
+        let was_playing = self.was_playing;
+        self.hande_playback_start_stop(self.is_playing(playing));
+        if self.params_snapshot.send_midi_clock && self.was_playing != was_playing {
+            let message = if self.was_playing { MidiSystemMessage::Start } else { MidiSystemMessage::Stop };
+            io.send_event(NoteEvent::MidiSysEx { timing: 0, message });
+        }
+
+        for sample_id in 0..nr_samples {
             // update Parameters with smoothing
-            let metric_duration = self.params.metric_dur_selector.smoothed.next();
-            self.params_snapshot.bar_pos = self.params.bar_position.smoothed.next();
-            self.params_snapshot.interpolate = self.params.interpolate_a_b.smoothed.next();
+            if !self.offline {
+                self.params_snapshot.metric_dur = self.params.metric_dur_selector.smoothed.next();
+                self.params_snapshot.bar_pos = self.params.bar_position.smoothed.next();
+                self.params_snapshot.interpolate = self.params.interpolate_a_b.smoothed.next();
+            }
+            let tempo = self.current_tempo(tempo);
             self.metric_phase
                 .set_metric_duration(
-                    metric_duration,
+                    self.params_snapshot.metric_dur,
                     self.sample_rate,
                     self.params_snapshot.use_bpm,
-                    context.transport().tempo,
-                    self.params.retain_metric_phase.load(Relaxed)
+                    tempo,
+                    self.params_snapshot.retain_metric_phase
                 );
 
+            // Metre slots switch when a new bar starts, or right away when nothing is playing.
+            let position_in_bar = self.get_normalized_position_in_bar();
+            if !self.is_playing(playing) || position_in_bar < self.last_position_in_bar {
+                self.metre_switch_due = true;
+            }
+            if self.metre_switch_due {
+                self.switch_to_pending_metre();
+            }
+            self.last_position_in_bar = position_in_bar;
+
             // loop through events at this time
             while let Some(event) = next_event {
                 if event.timing() > sample_id as u32 {
@@ -318,27 +829,56 @@ impl Plugin for MetreFiddler {
 
                 match event {
                     NoteEvent::NoteOn { .. } => {
-                        if let Some(event) = self.process_note_event(event) {
-                            context.send_event(event);
+                        let beat = self.get_current_indisp_data();
+                        if let Some(event) = self.process_note_event(event, &beat) {
+                            let event = self.transform_pitch(event, &beat);
+                            let offset = self.note_on_offset(&event, &beat);
+                            if let NoteEvent::NoteOn { channel, note, .. } = event {
+                                self.stop_ratchet(channel, note);
+                            }
+                            match beat.ratchet {
+                                Some(ratchet) => self.send_incoming_ratchet(io, event, offset, ratchet),
+                                None => io.send_moved_event(event, offset),
+                            }
                         }
                     },
                     // it's safest to just let all NoteOffs through, right?
                     NoteEvent::NoteOff {..} => {
-                        context.send_event(event)
+                        let mut event = self.release_pitch(event);
+                        let offset = self.note_off_offset(&event);
+                        // A ratcheted note stops repeating, and its NoteOff ends the sounding repeat.
+                        if let NoteEvent::NoteOff { channel, note, ref mut voice_id, .. } = event {
+                            if let Some(ratcheted) = self.stop_ratchet(channel, note) {
+                                *voice_id = ratcheted.voice_id(ratcheted.repeat());
+                            }
+                        }
+                        io.send_moved_event(event, offset)
+                    },
+                    NoteEvent::MidiSysEx { message, .. } => {
+                        self.receive_system_message(message, tempo);
+                        // When sending MIDI clock, only that clock is passed on.
+                        if !(self.params_snapshot.send_midi_clock && message == MidiSystemMessage::Clock) {
+                            io.send_event(event)
+                        }
                     },
-                    _ => context.send_event(event),
+                    _ => io.send_event(self.scale_controller(event)),
                 }
 
-                next_event = context.next_event();
+                next_event = io.next_event();
             }
+            self.send_ratchet_repeats(io, sample_id);
 
-            // Send Midi
-            if self.params.send_midi.value() {
-                let (current_beat_idx, current_beat_duration_sum, indisp_val, let_through, origin) =
-                    self.get_current_indisp_data();
+            if self.is_playing(playing) {
+                self.send_clock_and_bar_marker(sample_id, tempo, io);
+            }
+
+            // Send Midi and notes at group starts
+            if self.params_snapshot.send_midi || self.params_snapshot.send_group_notes {
+                let beat = self.get_current_indisp_data();
+                let current_beat_idx = beat.beat_idx;
 
                 let beat_first_sample: u64 =
-                    (current_beat_duration_sum * self.metric_phase.metric_duration_samples() as f32)
+                    (beat.duration_sum * self.metric_phase.metric_duration_samples() as f32)
                         .floor() as u64;
 
                 let nth_sample_in_bar: u64 =
@@ -349,67 +889,54 @@ impl Plugin for MetreFiddler {
 
                 // Are we at the beginning of a beat?
                 if nth_sample_of_beat < nr_samples_for_start_of_beat {
-                    // Send midi when we haven't already sent a note for this idx
-                    if self.last_sent_beat_idx != current_beat_idx as i32 && let_through {
-                        let vel = {
-                            let tmp_vel = self.calculate_current_velocity(indisp_val);
-
-                            if self.params_snapshot.interpolate_indisp {
-                                tmp_vel
-                            } else {
-                                match origin {
-                                    Both => tmp_vel,
-                                    MetreA => dry_wet(tmp_vel, 0.0, self.params_snapshot.interpolate),
-                                    MetreB => dry_wet(0.0, tmp_vel, self.params_snapshot.interpolate),
-                                }
+                    if self.params_snapshot.send_midi {
+                        // Report every beat once, whether it is sent or filtered
+                        if self.last_reported_beat_idx != current_beat_idx as i32 {
+                            let vel = self.calculate_beat_velocity(beat.indisp_val, beat.origin);
+                            self.report_beat(current_beat_idx, beat.indisp_val, vel, beat.let_through);
+                            self.last_reported_beat_idx = current_beat_idx as i32;
+                        }
+
+                        // Send midi when we haven't already sent a note for this idx
+                        if self.last_sent_beat_idx != current_beat_idx as i32 && beat.let_through {
+                            let vel = self.calculate_beat_velocity(beat.indisp_val, beat.origin);
+                            match beat.ratchet {
+                                Some(ratchet) => self.send_ratchet(io, sample_id, &beat, vel, ratchet),
+                                None => self.send_beat(io, sample_id, &beat, vel, self.note_length()),
                             }
-                        };
-                        let note = 60
-                            + if self.params_snapshot.output_one_pitch {
-                            0
-                        } else if self.params_snapshot.many_velocities {
-                            indisp_val as u8
-                        } else if self.indisp_is_accent(indisp_val) {
-                            0
-                        } else {
-                            1
-                        };
-
-                        context.send_event(
-                            NoteEvent::NoteOn {
-                                timing: sample_id as u32,
-                                velocity: vel,
-                                channel: 0,
-                                note,
-                                voice_id: Some(sample_id as i32),
-                            });
-
-                        self.last_sent_beat_idx = current_beat_idx as i32;
-
-                        // send a Note Off into self.note_off_buffer
-                        let release_timing = sample_id as i64 + (0.1 * self.sample_rate).floor() as i64;
-                        if let Some(slot) = self.note_off_buffer.iter_mut().find(|e| e.is_none()) {
-                            *slot = Some((note, sample_id as i32, release_timing));
+                            self.last_sent_beat_idx = current_beat_idx as i32;
                         }
                     }
+
+                    // One note per group start, higher strata get higher pitches
+                    if self.params_snapshot.send_group_notes && self.last_group_beat_idx != current_beat_idx as i32 {
+                        if beat.stratum > 0 {
+                            let note = (self.params_snapshot.group_note_base as usize + beat.stratum - 1).min(127) as u8;
+                            self.send_note(io, sample_id, note, 1.0, self.note_length());
+                        }
+                        self.last_group_beat_idx = current_beat_idx as i32;
+                    }
                 } else {
-                    self.last_sent_beat_idx = -1
+                    self.last_sent_beat_idx = -1;
+                    self.last_reported_beat_idx = -1;
+                    self.last_group_beat_idx = -1;
                 }
             }
 
             // update progress
-            if context.transport().playing {
-                self.metric_phase.increment();
+            if self.is_playing(playing) && self.metric_phase.increment() {
+                self.bar_started = true;
             }
+            self.midi_clock.increment();
         }
 
         // Handle Note Offs
         for event in self.note_off_buffer.iter_mut() {
             if let Some((note, id, release_timing)) = event {
-                if *release_timing >= buffer_len as i64 {
-                    *release_timing -= buffer_len as i64;
+                if *release_timing >= nr_samples as i64 {
+                    *release_timing -= nr_samples as i64;
                 } else {
-                    context.send_event(
+                    io.send_event(
                         NoteEvent::NoteOff {
                             timing: *release_timing as u32,
                             voice_id: Some(*id),
@@ -423,6 +950,71 @@ impl Plugin for MetreFiddler {
             }
         }
 
+        io.send_due_events(nr_samples);
+        self.delayed_events = delayed_events;
+    }
+}
+
+impl Plugin for MetreFiddler {
+    const NAME: &'static str = "MetreFiddler";
+    const VENDOR: &'static str = "Leon Focker";
+    const URL: &'static str = "https://youtu.be/dQw4w9WgXcQ";
+    const EMAIL: &'static str = "contact@leonfocker.de";
+
+    const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+
+    const AUDIO_IO_LAYOUTS: &'static [AudioIOLayout] = &[
+    ];
+    
+    const MIDI_INPUT: MidiConfig = MidiConfig::MidiCCs;
+    const MIDI_OUTPUT: MidiConfig = MidiConfig::MidiCCs;
+    const SAMPLE_ACCURATE_AUTOMATION: bool = true;
+
+    type SysExMessage = MidiSystemMessage;
+    type BackgroundTask = MetreFiddlerTask;
+
+    fn params(&self) -> Arc<dyn Params> {
+        self.params.clone()
+    }
+
+    fn filter_state(state: &mut PluginState) {
+        migrate_state(state);
+    }
+
+    fn task_executor(&mut self) -> TaskExecutor<Self> {
+        let params = self.params.clone();
+        Box::new(move |task| execute_task(&params, task))
+    }
+
+    fn editor(&mut self, async_executor: AsyncExecutor<Self>) -> Option<Box<dyn Editor>> {
+        editor::create(
+            self.params.clone(),
+            self.params.editor_state.clone(),
+            self.beat_event_consumer.clone(),
+            async_executor,
+        )
+    }
+
+    fn initialize(
+        &mut self,
+        _audio_io_layout: &AudioIOLayout,
+        buffer_config: &BufferConfig,
+        context: &mut impl InitContext<Self>,
+    ) -> bool {
+        self.sample_rate = buffer_config.sample_rate;
+        self.latency_samples = self.params.snapshot().latency_samples(self.sample_rate);
+        context.set_latency_samples(self.latency_samples);
+        true
+    }
+
+    fn process(
+        &mut self,
+        buffer: &mut Buffer,
+        _aux: &mut AuxiliaryBuffers,
+        context: &mut impl ProcessContext<Self>,
+    ) -> ProcessStatus {
+        self.process_buffer(buffer.samples(), &mut HostEventIO(context));
+
         ProcessStatus::Normal
     }
 }
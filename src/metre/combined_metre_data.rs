@@ -1,10 +1,16 @@
 use serde::{Deserialize, Serialize};
 use vizia_plug::vizia::prelude::Data;
+use crate::metre::beat_origin::BeatOrigin;
+use crate::metre::beat_origin::BeatOrigin::*;
 use crate::metre::interpolation::interpolation_data::InterpolationData;
 use crate::metre::metre_data::MetreData;
+use crate::state::MetreState;
+use crate::util::dry_wet;
 
 /// Holds metric data for A and B and information used for interpolating between the two.
+/// It is persisted as a MetreState, so only the RQQ strings are stored.
 #[derive(Debug, Serialize, Deserialize, Clone, Data)]
+#[serde(into = "MetreState", try_from = "MetreState")]
 pub struct CombinedMetreData {
     metre_a: MetreData,
     metre_b: MetreData,
@@ -13,23 +19,14 @@ pub struct CombinedMetreData {
 
 impl Default for CombinedMetreData {
     fn default() -> Self {
-       let mut result =
-           Self {
-               metre_a: MetreData::default(),
-               metre_b: MetreData::default(),
-               interpolation_data: InterpolationData::default(),
-           };
-
-        result.update_interpolation_data();
-
-        result
+        Self::new(MetreData::default(), MetreData::default())
+            .expect("Interpolating between the Default metres should never fail")
     }
 }
 
 impl CombinedMetreData {
 
-    #[allow(dead_code)]
-    pub fn new(metre_a: MetreData, metre_b: MetreData) -> Self {
+    pub fn new(metre_a: MetreData, metre_b: MetreData) -> Result<Self, String> {
         let mut result =
             Self {
                 metre_a,
@@ -37,9 +34,9 @@ impl CombinedMetreData {
                 interpolation_data: InterpolationData::default(),
             };
 
-        result.update_interpolation_data();
+        result.update_interpolation_data()?;
 
-        result
+        Ok(result)
     }
 
     pub fn metre_a(&self) -> &MetreData {
@@ -50,14 +47,26 @@ impl CombinedMetreData {
         &self.metre_b
     }
 
-    pub fn set_metre_a(&mut self, metre_a: MetreData) {
-        self.metre_a = metre_a;
-        self.update_interpolation_data();
+    /// Replace metre A. When the new metre can't be interpolated with metre B, nothing changes.
+    pub fn set_metre_a(&mut self, metre_a: MetreData) -> Result<(), String> {
+        let old_metre_a = std::mem::replace(&mut self.metre_a, metre_a);
+        if let Err(err_string) = self.update_interpolation_data() {
+            self.metre_a = old_metre_a;
+            return Err(err_string);
+        }
+
+        Ok(())
     }
 
-    pub fn set_metre_b(&mut self, metre_b: MetreData) {
-        self.metre_b = metre_b;
-        self.update_interpolation_data();
+    /// Replace metre B. When the new metre can't be interpolated with metre A, nothing changes.
+    pub fn set_metre_b(&mut self, metre_b: MetreData) -> Result<(), String> {
+        let old_metre_b = std::mem::replace(&mut self.metre_b, metre_b);
+        if let Err(err_string) = self.update_interpolation_data() {
+            self.metre_b = old_metre_b;
+            return Err(err_string);
+        }
+
+        Ok(())
     }
 
     pub fn interpolation_data(&self) -> &InterpolationData {
@@ -78,8 +87,180 @@ impl CombinedMetreData {
         }
     }
 
-    fn update_interpolation_data(&mut self) {
+    /// The highest indispensability value of either metre, which the thresholds are scaled to.
+    pub fn max_indisp_val(&self) -> usize {
+        self.metre_a.durations.len().max(self.metre_b.durations.len()).saturating_sub(1)
+    }
+
+    /// The highest stratum of either metre, the one of the whole bar.
+    pub fn max_stratum(&self) -> usize {
+        let max_a = self.metre_a.gnsm.iter().max().copied().unwrap_or(0);
+        let max_b = self.metre_b.gnsm.iter().max().copied().unwrap_or(0);
+        max_a.max(max_b)
+    }
+
+    /// Return the normalized duration, the indispensability value and the Origin of every beat
+    /// that is currently played, in the same order as get_indisp_data_at would find them.
+    pub fn get_beat_weights(&self, interpolate: f32, interpolate_durs: bool, interpolate_indisp: bool) -> Vec<(f32, usize, BeatOrigin)> {
+        let durations: Vec<f32> = if interpolate_durs {
+            self.get_interpolated_durations(interpolate).collect()
+        } else {
+            self.get_interleaved_durations(interpolate).collect()
+        };
+
+        let mut start: f32 = 0.0;
+        durations.into_iter()
+            .map(|dur| {
+                // Look in the middle of the beat, so rounding can't land on a neighbour.
+                let (_, _, indisp_val, _, origin) =
+                    self.get_indisp_data_at(start + dur / 2.0, interpolate, interpolate_durs, interpolate_indisp);
+                start += dur;
+                (dur, indisp_val, origin)
+            })
+            .collect()
+    }
+
+    /// The stratum of the group that starts with the beat at beat_idx, as returned by
+    /// get_indisp_data_at, or 0 when the beat doesn't start a group.
+    pub fn get_gnsm_at(&self, beat_idx: usize, interpolate: f32, interpolate_durs: bool) -> usize {
+        // With interpolated durations, beat_idx counts the duration pairs, not the beats of A or B.
+        let (idx_a, idx_b) = if interpolate_durs {
+            self.interpolation_data.get_interpolated_beat_indices(beat_idx, interpolate)
+        } else {
+            (Some(beat_idx), Some(beat_idx))
+        };
+        let gnsm_a = idx_a.and_then(|idx| self.metre_a.gnsm.get(idx)).copied().unwrap_or(0);
+        let gnsm_b = idx_b.and_then(|idx| self.metre_b.gnsm.get(idx)).copied().unwrap_or(0);
+
+        match interpolate {
+            x if x <= 0.0 => gnsm_a,
+            x if x >= 1.0 => gnsm_b,
+            _ if interpolate_durs => dry_wet(gnsm_a, gnsm_b, interpolate).round() as usize,
+            _ => *self.interpolation_data.interleaved_gnsm().get(beat_idx).unwrap_or(&0),
+        }
+    }
+
+    /// Return the normalized duration of the beat at position and the proportions of the group it
+    /// belongs to. These come from metre A below an interpolation of 0.5, otherwise from metre B.
+    pub fn get_subdivisions_at(&self, position: f32, interpolate: f32, interpolate_durs: bool) -> (f32, &[f32]) {
+        let duration = if interpolate_durs {
+            get_beat_duration_from_durations(position, self.get_interpolated_durations(interpolate))
+        } else {
+            get_beat_duration_from_durations(position, self.get_interleaved_durations(interpolate))
+        };
+
+        let metre = if interpolate < 0.5 { &self.metre_a } else { &self.metre_b };
+        let (idx, _, _) = get_beat_idx_from_durations(position, metre.durations.iter().copied());
+        (duration, metre.subdivisions.get(idx).map_or(&[], |group| group.as_slice()))
+    }
+
+    /// Return a tuple with the index of the beat at position (between 0.0 and 1.0), the normalized
+    /// duration up until that beat, the indispensability value for that beat, the total number of
+    /// beats and the Origin of the beat.
+    pub fn get_indisp_data_at(&self, position: f32, interpolate: f32, interpolate_durs: bool, interpolate_indisp: bool) -> (usize, f32, usize, usize, BeatOrigin) {
+        let current_beat_idx_a;
+        let current_beat_idx_b;
+        let current_beat_idx;
+        let current_beat_duration_sum;
+        let current_beat_origin: BeatOrigin;
+        let total_nr_beats;
+
+        if interpolate_durs {
+            let durations = self.interpolation_data.get_interpolated_durations(interpolate);
+            let (idx, sum, nr_beats) = get_beat_idx_from_durations(position, durations);
+
+            current_beat_idx_a = idx;
+            current_beat_idx_b = idx;
+            current_beat_idx = idx;
+            current_beat_duration_sum = sum;
+            current_beat_origin = Both;
+            total_nr_beats = nr_beats;
+        } else {
+            let durations = self.get_interleaved_durations(interpolate);
+            let (idx, sum, nr_beats) = get_beat_idx_from_durations(position, durations);
+            (current_beat_idx_a, _, _) = get_beat_idx_from_durations(position, self.metre_a.durations.iter().copied());
+            (current_beat_idx_b, _, _) = get_beat_idx_from_durations(position, self.metre_b.durations.iter().copied());
+
+            current_beat_idx = idx;
+            current_beat_duration_sum = sum;
+            current_beat_origin = match interpolate {
+                x if x <= 0.0 => MetreA,
+                x if x >= 1.0 => MetreB,
+                _ => self.interpolation_data.unique_start_time_origins()[idx],
+            };
+            total_nr_beats = nr_beats;
+        }
+
+        let indisp_val_temp: f32 =
+            if interpolate_indisp || current_beat_origin == Both {
+                dry_wet(
+                    *self.metre_a.value.get(current_beat_idx_a).unwrap_or(&0),
+                    *self.metre_b.value.get(current_beat_idx_b).unwrap_or(&0),
+                    interpolate)
+            } else {
+                match current_beat_origin {
+                    MetreA => *self.metre_a.value.get(current_beat_idx_a).unwrap_or(&0) as f32,
+                    MetreB => *self.metre_b.value.get(current_beat_idx_b).unwrap_or(&0) as f32,
+                    // this should never occur:
+                    _ => 0.0,
+                }
+            };
+
+        // Not yet sure whether this is the way to go but seems fine for now...
+        let indisp_val: usize = if self.metre_a.durations.len() == self.metre_b.durations.len() {
+            indisp_val_temp.round() as usize
+        } else {
+            indisp_val_temp.ceil() as usize
+        };
+
+        (current_beat_idx,
+         current_beat_duration_sum,
+         indisp_val,
+         total_nr_beats,
+         current_beat_origin)
+    }
+
+    fn update_interpolation_data(&mut self) -> Result<(), String> {
         self.interpolation_data =
-            InterpolationData::new_from_durs_and_gnsm(&self.metre_a.durations, &self.metre_b.durations, &self.metre_a.gnsm, &self.metre_b.gnsm);
+            InterpolationData::new_from_durs_and_gnsm(&self.metre_a.exact_durations, &self.metre_b.exact_durations, &self.metre_a.gnsm, &self.metre_b.gnsm)?;
+
+        Ok(())
+    }
+}
+
+/// Return the index of the beat at position, the sum of all durations before it and the number of beats.
+/// A position exactly on the start time of a beat belongs to that beat, not to the one before, so a
+/// beat is found in the sample it starts at.
+fn get_beat_idx_from_durations(position: f32, mut durations: impl Iterator<Item=f32>) -> (usize, f32, usize) {
+    let mut current_beat_idx: usize = 0;
+    let mut current_beat_duration_sum: f32 = 0.0;
+    let mut nr_beats = 0;
+
+    while let Some(dur) = durations.next() {
+        nr_beats += 1;
+
+        if current_beat_duration_sum + dur > position {
+            nr_beats += durations.count();
+            break;
+        }
+
+        current_beat_duration_sum += dur;
+        current_beat_idx += 1;
     }
-}
\ No newline at end of file
+
+    (current_beat_idx, current_beat_duration_sum, nr_beats)
+}
+
+/// Return the duration of the beat at position, or 0 when position is past the last beat.
+fn get_beat_duration_from_durations(position: f32, durations: impl Iterator<Item=f32>) -> f32 {
+    let mut current_beat_duration_sum: f32 = 0.0;
+
+    for dur in durations {
+        if current_beat_duration_sum + dur > position {
+            return dur;
+        }
+        current_beat_duration_sum += dur;
+    }
+
+    0.0
+}
@@ -19,7 +19,10 @@ pub fn gnsm_to_indispensability_list(gnsm: &[usize]) -> Result<Vec<usize>, Strin
     let mut indices: Vec<usize> = vec![];
     let mut set_indices: Vec<usize> = vec![];
     let mut remaining_indices: Vec<usize> = vec![];
-    let mut layer: isize = *gnsm.iter().max().unwrap() as isize;
+    let mut layer: isize = match gnsm.iter().max() {
+        Some(max) => *max as isize,
+        None => return Err("gnsm must not be empty".to_string()),
+    };
 
     get_indices(layer, gnsm, &mut indices);
     let mut old_indices = indices.clone();
@@ -57,7 +60,7 @@ pub fn gnsm_to_indispensability_list(gnsm: &[usize]) -> Result<Vec<usize>, Strin
     }
 
     // invert values
-    let max = *result.iter().max().unwrap();
+    let max = *result.iter().max().unwrap_or(&0);
     Ok(result.iter().map(|x| (max - *x) as usize).collect())
 }
 
@@ -39,13 +39,23 @@ impl IndexPairs {
     pub(crate) fn all_free(&self) -> bool {
         self.iter().all(|&(x, y)| x.is_none() && y.is_none())
     }
+    /// Sort by the indices of A, or of B where A is None. Placeholders and pairs with only one side set
+    /// can't be compared with everything, so this is no total order. A stable insertion sort
+    /// keeps their position instead of panicking like slice::sort_by might.
     pub(crate) fn sort(&mut self) {
-        self.data.sort_by(|&(a, b), &(x, y)|
+        let cmp = |&(a, b): &(Option<usize>, Option<usize>), &(x, y): &(Option<usize>, Option<usize>)|
             if let (Some(a), Some(x)) = (a, x) {
                 a.cmp(&x)
             } else if let (Some(b), Some(y)) = (b, y) {
                 b.cmp(&y)
-            } else { Ordering::Equal} );
+            } else { Ordering::Equal };
+        for i in 1..self.data.len() {
+            let mut k = i;
+            while k > 0 && cmp(&self.data[k - 1], &self.data[k]) == Ordering::Greater {
+                self.data.swap(k - 1, k);
+                k -= 1;
+            }
+        }
     }
 }
 
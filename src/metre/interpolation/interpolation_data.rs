@@ -1,12 +1,13 @@
-use serde::{Deserialize, Serialize};
+use num_rational::Rational64;
+use num_traits::{One, ToPrimitive};
 use vizia_plug::vizia::prelude::Data;
 use crate::metre::beat_origin::BeatOrigin;
 use crate::metre::interpolation::index_pairs::IndexPairs;
-use crate::util::{approx_eq, dry_wet, get_durations, get_start_times};
+use crate::util::{dry_wet, get_checked_durations, get_checked_start_times, get_start_times};
 
 struct InterpolationDataHelper<'a> {
-    durations: &'a[f32],
-    starts: &'a[f32],
+    durations: &'a[Rational64],
+    starts: &'a[Rational64],
     gnsm: &'a[usize],
     len: usize,
     offset: usize,
@@ -18,9 +19,12 @@ struct InterpolationDataHelper<'a> {
 /// Additionally, a sorted list with all unique start-times is stored, plus a list of BeatOrigin,
 /// telling us in which of the two Metres a start-time came from. These start-times correlate with the
 /// stored interleaved_durations and interleaved_gnsm.
-#[derive(Debug, Serialize, Deserialize, Clone, Data, Default)]
+/// All start-times are compared as exact rationals, the results are stored as floats for the audio thread.
+#[derive(Debug, Clone, Data, Default)]
 pub struct InterpolationData {
     duration_pairs: Vec<(f32, f32)>,
+    // The beat indices in A and B that each of the duration_pairs came from.
+    index_pairs: Vec<(Option<usize>, Option<usize>)>,
     interleaved_durations: Vec<f32>,
     interleaved_gnsm: Vec<usize>,
 
@@ -35,12 +39,29 @@ impl InterpolationData {
         Self::default()
     }
 
-    pub fn new_from_durs_and_gnsm(durations_a: &[f32], durations_b: &[f32], gnsm_a: &[usize], gnsm_b: &[usize]) -> Self {
-        assert_eq!(durations_a.len(), gnsm_a.len());
-        assert_eq!(durations_b.len(), gnsm_b.len());
+    /// Pair up the beats of two metres. Beats with identical start times are always paired.
+    ///
+    /// # Examples
+    /// ```
+    /// use num_rational::Rational64;
+    /// use metrefiddler::metre::interpolation::interpolation_data::InterpolationData;
+    ///
+    /// let halves = [Rational64::new(1, 2); 2];
+    /// let thirds = [Rational64::new(1, 3); 3];
+    /// let data = InterpolationData::new_from_durs_and_gnsm(&halves, &thirds, &[1, 0], &[1, 0, 0]).unwrap();
+    /// // B has one beat more than A, so its last beat is paired with a beat of length 0.
+    /// assert_eq!(data.duration_pairs(), &[(0.5, 0.33333334), (0.5, 0.33333334), (0.0, 0.33333334)]);
+    /// ```
+    pub fn new_from_durs_and_gnsm(durations_a: &[Rational64], durations_b: &[Rational64], gnsm_a: &[usize], gnsm_b: &[usize]) -> Result<Self, String> {
+        if durations_a.len() != gnsm_a.len() || durations_b.len() != gnsm_b.len() {
+            return Err("durations and gnsm must have the same length".to_string());
+        }
+        if durations_a.is_empty() || durations_b.is_empty() {
+            return Err("can't interpolate between metres without beats".to_string());
+        }
 
         Self::default()
-            .set_duration_pairs(durations_a, durations_b, gnsm_a, gnsm_b)
+            .set_duration_pairs(durations_a, durations_b, gnsm_a, gnsm_b)?
             .set_interleaved_and_starts(durations_a, durations_b, gnsm_a, gnsm_b)
     }
 
@@ -53,10 +74,38 @@ impl InterpolationData {
             })
     }
 
+    pub fn duration_pairs(&self) -> &[(f32, f32)] {
+        &self.duration_pairs
+    }
+
+    /// The beat indices in A and in B of the beat at idx of get_interpolated_durations(). A beat
+    /// that only exists in one of the metres has None for the other.
+    pub fn get_interpolated_beat_indices(&self, idx: usize, interpolation: f32) -> (Option<usize>, Option<usize>) {
+        self.duration_pairs
+            .iter()
+            .zip(&self.index_pairs)
+            .filter(|(&(a, b), _)| dry_wet(a, b, interpolation) > 0.0)
+            .nth(idx)
+            .map_or((None, None), |(_, &indices)| indices)
+    }
+
+    /// The start times of both durations of every pair. Interpolating between the two gives the
+    /// start times of the interpolated durations.
+    pub fn start_time_pairs(&self) -> Vec<(f32, f32)> {
+        let (starts_a, starts_b): (Vec<f32>, Vec<f32>) = self.duration_pairs.iter().copied().unzip();
+        get_start_times(&starts_a).into_iter()
+            .zip(get_start_times(&starts_b))
+            .collect()
+    }
+
     pub fn interleaved_durations(&self) -> &[f32] {
         &self.interleaved_durations
     }
 
+    pub fn interleaved_gnsm(&self) -> &[usize] {
+        &self.interleaved_gnsm
+    }
+
     pub fn unique_start_times(&self) -> &[f32] {
         &self.unique_start_times
     }
@@ -65,50 +114,48 @@ impl InterpolationData {
         &self.unique_start_time_origins
     }
 
-    fn set_duration_pairs(mut self, durations_a: &[f32], durations_b: &[f32], gnsm_a: &[usize], gnsm_b: &[usize]) -> Self {
+    fn set_duration_pairs(mut self, durations_a: &[Rational64], durations_b: &[Rational64], gnsm_a: &[usize], gnsm_b: &[usize]) -> Result<Self, String> {
         let data_a = InterpolationDataHelper {
             durations: durations_a,
-            starts: &get_start_times(durations_a),
+            starts: &checked_start_times(durations_a)?,
             gnsm: gnsm_a,
             len: durations_a.len(),
             offset: 0
         };
         let data_b = InterpolationDataHelper {
             durations: durations_b,
-            starts: &get_start_times(durations_b),
+            starts: &checked_start_times(durations_b)?,
             gnsm: gnsm_b,
             len: durations_b.len(),
             offset: 0
         };
 
-        self.duration_pairs = get_duration_pairs(data_a, data_b)
+        let index_pairs = get_duration_pairs(data_a, data_b)?;
+        self.duration_pairs = index_pairs
             .iter()
             .map(|&(idx_a, idx_b)| {
-                (if let Some(idx) = idx_a {
-                    *durations_a.get(idx).unwrap_or(&0.0)
-                } else { 0.0 },
-                 if let Some(idx) = idx_b {
-                     *durations_b.get(idx).unwrap_or(&0.0)
-                 } else { 0.0 })
+                (idx_a.and_then(|idx| durations_a.get(idx)).and_then(|dur| dur.to_f32()).unwrap_or(0.0),
+                 idx_b.and_then(|idx| durations_b.get(idx)).and_then(|dur| dur.to_f32()).unwrap_or(0.0))
             })
             .collect();
+        self.index_pairs = index_pairs.to_vec();
 
-        self
+        Ok(self)
     }
 
-    fn set_interleaved_and_starts(mut self, durs_a: &[f32], durs_b: &[f32], gnsm_a: &[usize], gnsm_b: &[usize]) -> Self {
-        let mut starts_a = get_start_times(durs_a);
-        let mut starts_b = get_start_times(durs_b);
+    fn set_interleaved_and_starts(mut self, durs_a: &[Rational64], durs_b: &[Rational64], gnsm_a: &[usize], gnsm_b: &[usize]) -> Result<Self, String> {
+        let mut starts_a = checked_start_times(durs_a)?;
+        let mut starts_b = checked_start_times(durs_b)?;
         let max_len = durs_a.len() + durs_b.len();
 
-        starts_a.push(1.0);
-        starts_b.push(1.0);
+        starts_a.push(Rational64::one());
+        starts_b.push(Rational64::one());
 
-        starts_a.sort_by(|a, b| a.partial_cmp(b).unwrap());
-        starts_b.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        starts_a.sort();
+        starts_b.sort();
 
-        starts_a.dedup_by(|a, b| approx_eq(*a, *b, 0.001));
-        starts_b.dedup_by(|a, b| approx_eq(*a, *b, 0.001));
+        starts_a.dedup();
+        starts_b.dedup();
 
         let mut unique_start_times = Vec::with_capacity(max_len);
         let mut unique_start_time_origins = Vec::with_capacity(max_len);
@@ -120,7 +167,7 @@ impl InterpolationData {
         while i < starts_a.len() || k < starts_b.len() {
             match (starts_a.get(i), starts_b.get(k)) {
                 (Some(&a), Some(&b)) => {
-                    if approx_eq(a, b, 0.001) {
+                    if a == b {
                         unique_start_times.push(a);
                         unique_start_time_origins.push(BeatOrigin::Both);
                         interleaved_gnsm.push(gnsm_a[i % gnsm_a.len()].max(gnsm_b[k % gnsm_b.len()]));
@@ -154,24 +201,34 @@ impl InterpolationData {
             }
         }
 
-        let interleaved_durations = get_durations(&unique_start_times).collect::<Vec<_>>();
+        // A and B share no denominator, so the difference of their start times can overflow
+        let interleaved_durations = get_checked_durations(&unique_start_times)
+            .ok_or_else(|| "the start times of the two metres can't be compared".to_string())?
+            .iter()
+            .map(|dur| dur.to_f32().unwrap_or(0.0))
+            .collect::<Vec<_>>();
 
-        self.unique_start_times = unique_start_times;
+        self.unique_start_times = unique_start_times.iter().map(|start| start.to_f32().unwrap_or(0.0)).collect();
         self.unique_start_time_origins = unique_start_time_origins;
         self.interleaved_durations = interleaved_durations;
         self.interleaved_gnsm = interleaved_gnsm;
 
-        self
+        Ok(self)
     }
 }
 
+fn checked_start_times(durations: &[Rational64]) -> Result<Vec<Rational64>, String> {
+    get_checked_start_times(durations)
+        .ok_or_else(|| "the start times of a metre are too large".to_string())
+}
+
 
 
 /// Given durations A and B, look for identical start times. For each identical start time in both
 /// sets of durations, get their indices and pair them into result.
 fn pair_identical_start_times(result: &mut IndexPairs, data_a: &InterpolationDataHelper, data_b: &InterpolationDataHelper) {
     for (i, &x) in data_a.starts.iter().enumerate() {
-        if let Some(pos) = data_b.starts.iter().position(|&y| approx_eq(x, y, 0.001)) {
+        if let Some(pos) = data_b.starts.iter().position(|&y| x == y) {
             result.set_first_free((Some(i + data_a.offset), Some(pos + data_b.offset)))
         }
     }
@@ -179,37 +236,46 @@ fn pair_identical_start_times(result: &mut IndexPairs, data_a: &InterpolationDat
 
 /// While durations A does have some metrical hierarchy indicated by gnsm_a, durations B does not.
 /// Find the beat with the highest metrical value in durations A and pair it with the closest beat from B by start-time
-fn pair_higher_stratum_by_time(data_a: &InterpolationDataHelper, data_b: &InterpolationDataHelper)  -> (Option<usize>, Option<usize>) {
+fn pair_higher_stratum_by_time(data_a: &InterpolationDataHelper, data_b: &InterpolationDataHelper)  -> Result<(Option<usize>, Option<usize>), String> {
     let no_strata_left_b = data_b.gnsm.iter().all(|&x| x == *data_b.gnsm.first().unwrap_or(&0));
-    assert!(no_strata_left_b);
+    if !no_strata_left_b {
+        return Err("pair_higher_stratum_by_time: durations B still have strata".to_string());
+    }
     // find the indices which belong to the highest stratum
     let highest_stratum = *data_a.gnsm.iter().max().unwrap_or(&1);
-    let idx_a = data_a.gnsm.iter().rposition(|&x| x == highest_stratum).unwrap_or(data_a.gnsm.len() -1);
-    let start_time_a = data_a.starts[idx_a];
+    let idx_a = data_a.gnsm.iter().rposition(|&x| x == highest_stratum).unwrap_or(0);
+    let start_time_a = *data_a.starts.get(idx_a)
+        .ok_or_else(|| "pair_higher_stratum_by_time: durations A are empty".to_string())?;
     // get index for Start in B that's closest to start_time_a
-    let idx_b = data_b.starts.iter()
-        .map(| &start| (start - start_time_a).abs())
+    let distances = data_b.starts.iter()
+        .map(|&start| if start > start_time_a {
+            num_traits::CheckedSub::checked_sub(&start, &start_time_a)
+        } else {
+            num_traits::CheckedSub::checked_sub(&start_time_a, &start)
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| "pair_higher_stratum_by_time: the start times can't be compared".to_string())?;
+    let idx_b = distances.iter()
         .enumerate()
-        .min_by(| (_, x), (_, y) |x.total_cmp(y))
-        .unwrap_or((0, 0.0))
-        .0;
+        .min_by(| (_, x), (_, y) |x.cmp(y))
+        .map_or(0, |(idx, _)| idx);
 
-    (Some(idx_a + data_a.offset), Some(idx_b + data_b.offset))
+    Ok((Some(idx_a + data_a.offset), Some(idx_b + data_b.offset)))
 }
 
 /// Pair the beats with the highest metrical value from each set of durations.
 fn pair_highest_stratus (data_a: &InterpolationDataHelper, data_b: &InterpolationDataHelper) -> (Option<usize>, Option<usize>){
     // find the indices which belong to the highest stratus
     let highest_stratum_a = *data_a.gnsm.iter().max().unwrap_or(&1);
-    let idx_a = data_a.gnsm.iter().rposition(|&x| x == highest_stratum_a).unwrap_or(data_a.gnsm.len() -1);
+    let idx_a = data_a.gnsm.iter().rposition(|&x| x == highest_stratum_a).unwrap_or(0);
     let highest_stratum_b = *data_b.gnsm.iter().max().unwrap_or(&1);
-    let idx_b = data_b.gnsm.iter().rposition(|&x| x == highest_stratum_b).unwrap_or(data_b.gnsm.len() -1);
+    let idx_b = data_b.gnsm.iter().rposition(|&x| x == highest_stratum_b).unwrap_or(0);
 
     (Some(idx_a + data_a.offset), Some(idx_b + data_b.offset))
 }
 
 /// Return a vector of pairs of indices.
-fn get_duration_pairs(data_a: InterpolationDataHelper, data_b: InterpolationDataHelper) -> IndexPairs {
+fn get_duration_pairs(data_a: InterpolationDataHelper, data_b: InterpolationDataHelper) -> Result<IndexPairs, String> {
     let max_len = data_a.len.max(data_b.len);
     let no_strata_left_a = data_a.gnsm.iter().all(|&x| x == *data_a.gnsm.first().unwrap_or(&0));
     let no_strata_left_b = data_b.gnsm.iter().all(|&x| x == *data_b.gnsm.first().unwrap_or(&0));
@@ -237,9 +303,9 @@ fn get_duration_pairs(data_a: InterpolationDataHelper, data_b: InterpolationData
             // If there is metrical hierarchy left in only one of the sections, find a match from the
             // highest stratum via start-time
             if !no_strata_left_a && no_strata_left_b {
-                result.set_first_free(pair_higher_stratum_by_time(&data_a, &data_b));
+                result.set_first_free(pair_higher_stratum_by_time(&data_a, &data_b)?);
             } else if no_strata_left_a && !no_strata_left_b {
-                let (tmp_b, tmp_a) = pair_higher_stratum_by_time(&data_b, &data_a);
+                let (tmp_b, tmp_a) = pair_higher_stratum_by_time(&data_b, &data_a)?;
                 result.set_first_free((tmp_a, tmp_b));
             }
             // If there is metrical hierarchy left in both sections, match beats from the same stratum
@@ -250,7 +316,9 @@ fn get_duration_pairs(data_a: InterpolationDataHelper, data_b: InterpolationData
     }
 
     // At this point, we should have some pairs in result
-    assert!(result.iter().any(|&(x, y)| x.is_some() || y.is_some()));
+    if !result.iter().any(|&(x, y)| x.is_some() || y.is_some()) {
+        return Err("failed to pair any beats".to_string());
+    }
 
     result.sort();
 
@@ -262,15 +330,18 @@ fn get_duration_pairs(data_a: InterpolationDataHelper, data_b: InterpolationData
     let mut subseqs: IndexPairs = IndexPairs::default();
     for (x, y) in result.iter() {
         if let (Some(a), Some(b)) = (x, y) {
-            let diff_a = a - last_a;
-            let diff_b = b - last_b;
+            let (diff_a, diff_b) = match (a.checked_sub(last_a), b.checked_sub(last_b)) {
+                (Some(diff_a), Some(diff_b)) => (diff_a, diff_b),
+                _ => return Err("beats were paired out of order".to_string()),
+            };
 
             if diff_a.max(diff_b) > if flag { 1 } else { 0 } {
-                subseqs.append(&mut call_with_slices(&data_a, &data_b,
-                                                     if flag { last_a + 1} else { last_a } - data_a.offset,
-                                                     if flag { last_b + 1} else { last_b } - data_b.offset,
-                                                     if flag { diff_a - 1 } else { diff_a },
-                                                     if flag { diff_b - 1 } else { diff_b },));
+                let mut pairs = call_with_slices(&data_a, &data_b,
+                                                 if flag { last_a + 1} else { last_a } - data_a.offset,
+                                                 if flag { last_b + 1} else { last_b } - data_b.offset,
+                                                 if flag { diff_a.saturating_sub(1) } else { diff_a },
+                                                 if flag { diff_b.saturating_sub(1) } else { diff_b },)?;
+                subseqs.append(&mut pairs);
             }
 
             last_a = *a;
@@ -285,15 +356,18 @@ fn get_duration_pairs(data_a: InterpolationDataHelper, data_b: InterpolationData
         }
     }
 
-    let diff_a = data_a.len + data_a.offset - last_a;
-    let diff_b = data_b.len + data_b.offset - last_b;
+    let (diff_a, diff_b) = match ((data_a.len + data_a.offset).checked_sub(last_a), (data_b.len + data_b.offset).checked_sub(last_b)) {
+        (Some(diff_a), Some(diff_b)) => (diff_a, diff_b),
+        _ => return Err("more beats were paired than there are".to_string()),
+    };
 
     if diff_a.max(diff_b) > if flag { 1 } else { 0 } {
-        subseqs.append(&mut call_with_slices(&data_a, &data_b,
-                                             if flag { last_a + 1} else { last_a } - data_a.offset,
-                                             if flag { last_b + 1} else { last_b } - data_b.offset,
-                                             if flag { diff_a - 1 } else { diff_a },
-                                             if flag { diff_b - 1 } else { diff_b },));
+        let mut pairs = call_with_slices(&data_a, &data_b,
+                                         if flag { last_a + 1} else { last_a } - data_a.offset,
+                                         if flag { last_b + 1} else { last_b } - data_b.offset,
+                                         if flag { diff_a.saturating_sub(1) } else { diff_a },
+                                         if flag { diff_b.saturating_sub(1) } else { diff_b },)?;
+        subseqs.append(&mut pairs);
     }
 
     // Add the subsections to result
@@ -303,25 +377,31 @@ fn get_duration_pairs(data_a: InterpolationDataHelper, data_b: InterpolationData
             *elem = subseqs.pop().unwrap_or((None, None));
         }
     }
+    // When beats of both sections stay unpaired, there are more pairs than placeholders.
+    while let Some(pair) = subseqs.pop() {
+        result.push(pair);
+    }
+    result.retain(|&(x, y)| x.is_some() || y.is_some());
 
     result.sort();
-    result
+    Ok(result)
 }
 
-fn call_with_slices(data_a: &InterpolationDataHelper, data_b: &InterpolationDataHelper, start_a: usize, start_b: usize, len_a: usize, len_b: usize) -> IndexPairs {
-    let end_a = start_a + len_a;
+fn call_with_slices(data_a: &InterpolationDataHelper, data_b: &InterpolationDataHelper, start_a: usize, start_b: usize, len_a: usize, len_b: usize) -> Result<IndexPairs, String> {
+    let out_of_bounds = || "tried to pair beats that don't exist".to_string();
+    let range_a = start_a..start_a + len_a;
     let new_data_a = InterpolationDataHelper {
-        durations: &data_a.durations[start_a..end_a],
-        starts: &data_a.starts[start_a..end_a],
-        gnsm: &data_a.gnsm[start_a..end_a],
+        durations: data_a.durations.get(range_a.clone()).ok_or_else(out_of_bounds)?,
+        starts: data_a.starts.get(range_a.clone()).ok_or_else(out_of_bounds)?,
+        gnsm: data_a.gnsm.get(range_a).ok_or_else(out_of_bounds)?,
         len: len_a,
         offset: start_a + data_a.offset,
     };
-    let end_b = start_b + len_b;
+    let range_b = start_b..start_b + len_b;
     let new_data_b = InterpolationDataHelper {
-        durations: &data_b.durations[start_b..end_b],
-        starts: &data_b.starts[start_b..end_b],
-        gnsm: &data_b.gnsm[start_b..end_b],
+        durations: data_b.durations.get(range_b.clone()).ok_or_else(out_of_bounds)?,
+        starts: data_b.starts.get(range_b.clone()).ok_or_else(out_of_bounds)?,
+        gnsm: data_b.gnsm.get(range_b).ok_or_else(out_of_bounds)?,
         len: len_b,
         offset: start_b + data_b.offset,
     };
@@ -1,16 +1,22 @@
-use serde::{Serialize, Deserialize};
+use num_rational::Rational64;
+use num_traits::{CheckedAdd, CheckedDiv, ToPrimitive, Zero};
 use crate::metre::indispensability::{gnsm_to_indispensability_list};
 use crate::metre::rqq::parse_rqq;
 use vizia_plug::vizia::prelude::Data;
 
-// *must* derive Serialize and Deserialize for persistence
 /// Holds all the important information for an RQQ defined metric structure.
-#[derive(Debug, Serialize, Deserialize, Clone, Data)]
+/// The exact_durations are used for all calculations, durations holds the same values as floats
+/// for the audio thread.
+#[derive(Debug, Clone, Data)]
 pub struct MetreData {
     pub string: String,
     pub value: Vec<usize>,
     pub gnsm: Vec<usize>,
     pub durations: Vec<f32>,
+    #[data(eq)]
+    pub exact_durations: Vec<Rational64>,
+    /// For every beat, the proportions of the group it belongs to.
+    pub subdivisions: Vec<Vec<f32>>,
     pub max: usize,
 }
 
@@ -27,9 +33,17 @@ impl TryFrom<&str> for MetreData {
 
     fn try_from(text: &str) -> Result<Self, Self::Error> {
         let rqq = parse_rqq(text)?;
-        let durations = rqq.to_durations(1.0)?;
-        let sum: f32 = durations.iter().sum();
-        let durations = durations.iter().map(|x| x / sum).collect::<Vec<f32>>();
+        let durations = rqq.to_durations(Rational64::from_integer(1))?;
+        let sum = durations.iter().try_fold(Rational64::zero(), |acc, x| acc.checked_add(x))
+            .ok_or_else(|| "rqq durations are too large".to_string())?;
+        let exact_durations = durations.iter()
+            .map(|x| x.checked_div(&sum))
+            .collect::<Option<Vec<Rational64>>>()
+            .ok_or_else(|| "rqq durations add up to 0".to_string())?;
+        let durations = exact_durations.iter().map(|x| x.to_f32().unwrap_or(0.0)).collect::<Vec<f32>>();
+        let subdivisions = rqq.to_subdivisions()?.iter()
+            .map(|group| group.iter().map(|x| x.to_f32().unwrap_or(0.0)).collect())
+            .collect();
         let gnsm = rqq.to_gnsm()?;
         let value = gnsm_to_indispensability_list(&gnsm)?;
 
@@ -37,10 +51,12 @@ impl TryFrom<&str> for MetreData {
             MetreData {
                 string: text.to_string(),
                 durations,
+                exact_durations,
+                subdivisions,
                 max: *value.iter().max().unwrap_or(&1),
                 value,
                 gnsm,
             }
         )
     }
-}
\ No newline at end of file
+}
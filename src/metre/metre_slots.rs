@@ -0,0 +1,79 @@
+use serde::{Deserialize, Serialize};
+use crate::metre::combined_metre_data::CombinedMetreData;
+use crate::metre::metre_data::MetreData;
+
+/// How many metres can be stored for A and for B.
+pub const NR_OF_METRE_SLOTS: usize = 16;
+
+/// RQQ strings for metre A and B, which the metre slot parameters switch between. An empty slot
+/// keeps the metre that is currently used.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct MetreSlots {
+    slots_a: Vec<Option<String>>,
+    slots_b: Vec<Option<String>>,
+}
+
+impl Default for MetreSlots {
+    fn default() -> Self {
+        Self {
+            slots_a: vec![None; NR_OF_METRE_SLOTS],
+            slots_b: vec![None; NR_OF_METRE_SLOTS],
+        }
+    }
+}
+
+impl MetreSlots {
+    pub fn slot_a(&self, idx: usize) -> Option<&str> {
+        self.slots_a.get(idx)?.as_deref()
+    }
+
+    pub fn slot_b(&self, idx: usize) -> Option<&str> {
+        self.slots_b.get(idx)?.as_deref()
+    }
+
+    pub fn set_slot_a(&mut self, idx: usize, text: &str) {
+        set_slot(&mut self.slots_a, idx, text)
+    }
+
+    pub fn set_slot_b(&mut self, idx: usize, text: &str) {
+        set_slot(&mut self.slots_b, idx, text)
+    }
+
+    /// The metre data for a pair of slots. Empty slots and slots that hold the current metre reuse
+    /// it instead of parsing it again.
+    /// ```
+    /// use metrefiddler::metre::metre_data::MetreData;
+    /// use metrefiddler::metre::metre_slots::MetreSlots;
+    ///
+    /// let current = MetreData::default();
+    /// let mut slots = MetreSlots::default();
+    /// slots.set_slot_b(2, "(3 (1 1 1))");
+    ///
+    /// let combined = slots.combine(0, 2, &current, &current).unwrap();
+    /// assert_eq!(combined.metre_a().string, current.string);
+    /// assert_eq!(combined.metre_b().string, "(3 (1 1 1))");
+    /// ```
+    pub fn combine(&self, slot_a: usize, slot_b: usize, current_a: &MetreData, current_b: &MetreData) -> Result<CombinedMetreData, String> {
+        CombinedMetreData::new(
+            slot_metre(self.slot_a(slot_a), current_a)?,
+            slot_metre(self.slot_b(slot_b), current_b)?,
+        )
+    }
+}
+
+fn set_slot(slots: &mut Vec<Option<String>>, idx: usize, text: &str) {
+    if idx >= NR_OF_METRE_SLOTS {
+        return;
+    }
+    if slots.len() <= idx {
+        slots.resize(idx + 1, None);
+    }
+    slots[idx] = Some(text.to_string());
+}
+
+fn slot_metre(slot: Option<&str>, current: &MetreData) -> Result<MetreData, String> {
+    match slot {
+        Some(text) if text != current.string => MetreData::try_from(text),
+        _ => Ok(current.clone()),
+    }
+}
@@ -19,8 +19,11 @@ impl Default for MetricPhase {
 }
 
 impl MetricPhase {
+    /// Go back to the start of the bar. The phase is updated right away, so a beat at the start of
+    /// the bar is found in the first sample after the reset.
     pub fn reset(&mut self) {
         self.progress_in_samples = 0;
+        self.update_phase()
     }
 
     pub fn progress_in_samples(&self) -> u64 {
@@ -35,12 +38,25 @@ impl MetricPhase {
         self.metric_phase
     }
 
-    pub fn increment(&mut self) {
+    /// Move on by one sample. Returns true when this wrapped around to the start of a new bar.
+    pub fn increment(&mut self) -> bool {
         self.progress_in_samples += 1;
-        if self.progress_in_samples >= self.metric_duration_samples {
+        let wrapped = self.progress_in_samples >= self.metric_duration_samples;
+        if wrapped {
             self.progress_in_samples -= self.metric_duration_samples;
         }
-        self.update_phase()
+        self.update_phase();
+        wrapped
+    }
+
+    /// Jump to a position given in quarter notes, e.g. by MIDI clock. Returns true when this jumped
+    /// from the end of a bar to the start of the next one.
+    pub fn lock_to_quarters(&mut self, quarters: f64, quarters_per_bar: f64) -> bool {
+        let last_phase = self.metric_phase;
+        let phase = (quarters / quarters_per_bar).fract();
+        self.progress_in_samples = (phase * self.metric_duration_samples as f64).round() as u64 % self.metric_duration_samples;
+        self.update_phase();
+        last_phase - self.metric_phase > 0.5
     }
 
     fn update_phase(&mut self) {
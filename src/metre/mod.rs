@@ -1,7 +1,10 @@
-mod rqq;
-mod indispensability;
+pub mod rqq;
+pub mod indispensability;
 pub mod beat_origin;
 pub mod interpolation;
 pub mod metre_data;
 pub mod combined_metre_data;
-pub mod metric_phase;
\ No newline at end of file
+pub mod metric_phase;
+pub mod rqq_tree;
+pub mod rqq_syntax;
+pub mod metre_slots;
@@ -1,9 +1,16 @@
+use num_rational::Rational64;
+use num_traits::{CheckedAdd, CheckedDiv, CheckedMul, Zero};
 use crate::metre::rqq::RQQ::{Elem, List};
 
+/// The deepest nesting of lists parse_rqq accepts. Real metres never come close to this, but
+/// deeper inputs would take very long to parse or overflow the stack.
+pub const MAX_RQQ_DEPTH: usize = 100;
+
 /// A nested list representing an RQQ notation.
+/// Proportions are kept as exact rationals, so that nested tuplets don't accumulate rounding errors.
 #[derive(Debug, Clone)]
 pub enum RQQ {
-    Elem(f32),
+    Elem(Rational64),
     List(Vec<RQQ>),
 }
 
@@ -47,6 +54,8 @@ impl RQQ {
     /// 
     /// # Examples
     /// ```
+    /// use metrefiddler::metre::rqq::parse_rqq;
+    ///
     /// let rqq = parse_rqq(&String::from("(4 (1 1 1 1))")).unwrap();
     /// let gnsm = rqq.to_gnsm().unwrap();
     /// 
@@ -80,7 +89,9 @@ impl RQQ {
                 match item {
                     Elem(_) => ls.push(lvl),
                     List(vec) => {
-                        ls.append(&mut vec[1].clone().to_gnsm_aux(lvl + 1)?)
+                        let divisions = vec.get(1)
+                            .ok_or_else(|| "rqq.to_gnsm: list without subdivisions".to_string())?;
+                        ls.append(&mut divisions.to_gnsm_aux(lvl + 1)?)
                     },
                 }
             }
@@ -88,7 +99,10 @@ impl RQQ {
             return Err("rqq.to_gnsm: second Element is not a list".to_string())    
         }
         
-        ls[0] -= 1;
+        match ls.first_mut() {
+            Some(first) => *first -= 1,
+            None => return Err("rqq.to_gnsm: empty list".to_string()),
+        }
         Ok(ls)
     }
     
@@ -103,26 +117,84 @@ impl RQQ {
         }
     }
     
-    fn rqq_num_divisions(&self) -> f32 {
-        let mut result = 0.0;
+    fn rqq_num_divisions(&self) -> Result<Rational64, String> {
+        let mut result = Rational64::zero();
         if let List(vec) = self {
             for divs in vec {
-                match divs {
-                    Elem(val) => result += val,
-                    List(vec) => result += 
-                        match vec[0] {
-                            Elem(val) => val,
-                            _ => 0.0
+                let val = match divs {
+                    Elem(val) => *val,
+                    List(vec) =>
+                        match vec.first() {
+                            Some(Elem(val)) => *val,
+                            _ => Rational64::zero()
                         }
-                }
+                };
+                result = result.checked_add(&val)
+                    .ok_or_else(|| "rqq proportions are too large".to_string())?;
+            }
+        }
+        Ok(result)
+    }
+
+    /// For every beat, the proportions of the group it belongs to, adding up to 1. Ratchets
+    /// subdivide a beat like this, one level below the metre's own hierarchy.
+    ///
+    /// # Examples
+    /// ```
+    /// use metrefiddler::metre::rqq::parse_rqq;
+    /// use num_rational::Rational64;
+    ///
+    /// let rqq = parse_rqq(&String::from("(4 ((2 (1 1 1)) 2))")).unwrap();
+    /// let subdivisions = rqq.to_subdivisions().unwrap();
+    ///
+    /// assert_eq!(subdivisions.len(), 4);
+    /// assert_eq!(subdivisions[0], vec![Rational64::new(1, 3); 3]);
+    /// assert_eq!(subdivisions[3], vec![Rational64::new(1, 2); 2]);
+    /// ```
+    pub fn to_subdivisions(&self) -> Result<Vec<Vec<Rational64>>, String> {
+        match self {
+            List(vec) if vec.len() == 2 => vec[1].to_subdivisions_aux(),
+            _ => Err("rqq.to_subdivisions got malformed rqq list".to_string()),
+        }
+    }
+
+    fn to_subdivisions_aux(&self) -> Result<Vec<Vec<Rational64>>, String> {
+        let List(vec) = self else {
+            return Err("rqq.to_subdivisions: second Element is not a list".to_string())
+        };
+
+        let sum = self.rqq_num_divisions()?;
+        let proportions = vec.iter()
+            .map(|item| match item {
+                Elem(val) => *val,
+                List(vec) => match vec.first() {
+                    Some(Elem(val)) => *val,
+                    _ => Rational64::zero(),
+                },
+            })
+            .map(|val| val.checked_div(&sum))
+            .collect::<Option<Vec<Rational64>>>()
+            .ok_or_else(|| "rqq contains subdivisions that add up to 0".to_string())?;
+
+        let mut result = Vec::new();
+        for item in vec {
+            match item {
+                Elem(_) => result.push(proportions.clone()),
+                List(vec) => {
+                    let divisions = vec.get(1)
+                        .ok_or_else(|| "rqq.to_subdivisions: list without subdivisions".to_string())?;
+                    result.append(&mut divisions.to_subdivisions_aux()?)
+                },
             }
         }
-        result
+        Ok(result)
     }
 
-    pub fn to_durations(&self, parent_dur: f32) -> Result<Vec<f32>, String> {
+    /// Get the exact duration of each beat, relative to parent_dur.
+    pub fn to_durations(&self, parent_dur: Rational64) -> Result<Vec<Rational64>, String> {
         match self {
-            Elem(val) => Ok(vec![*val / parent_dur]),
+            Elem(val) => Ok(vec![val.checked_div(&parent_dur)
+                .ok_or_else(|| "rqq contains subdivisions that add up to 0 or are too large".to_string())?]),
             List(vec) => {
                 if vec.len() < 2 {
                     return Err("List must have at least two elements".to_string());
@@ -134,13 +206,15 @@ impl RQQ {
                     _ => return Err("Expected a List for subdivisions".to_string()),
                 };
 
-                let rqqnd = second_divs.rqq_num_divisions();
+                let rqqnd = second_divs.rqq_num_divisions()?;
                 let this_dur = match &vec[0] {
                     Elem(val) => *val,
                     _ => return Err("Expected Elem as first item in List".to_string()),
                 };
 
-                let pd = (parent_dur * rqqnd) / this_dur;
+                let pd = parent_dur.checked_mul(&rqqnd)
+                    .and_then(|x| x.checked_div(&this_dur))
+                    .ok_or_else(|| "rqq contains a duration of 0 or one that is too large".to_string())?;
 
                 let mut result = Vec::new();
                 for div in second_divs_vec {
@@ -176,13 +250,20 @@ pub fn parse_rqq(input: &str) ->  Result<RQQ, String> {
     // match elements
     for element in elements {
         match element {
-            "(" => {result.push_recur(List(vec![]), lvl); lvl += 1 },
+            "(" => {
+                // lvl is -1 outside of all lists
+                if lvl + 2 > MAX_RQQ_DEPTH as isize {
+                    return Err(format!("rqq must not be nested deeper than {} lists", MAX_RQQ_DEPTH));
+                }
+                result.push_recur(List(vec![]), lvl);
+                lvl += 1
+            },
             ")" => lvl -= 1,
             " " => (),
             _ => {
                 // keep numbers only
-                if let Ok(num) = element.parse::<f32>() {
-                    result.push_recur(Elem(num), lvl)
+                if let Ok(num) = element.parse::<i64>() {
+                    result.push_recur(Elem(Rational64::from_integer(num)), lvl)
                 };
             }
         }
@@ -0,0 +1,248 @@
+use crate::metre::rqq_tree::RqqNode;
+
+/// What a part of an RQQ string is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RqqTokenKind {
+    Open,
+    Close,
+    Number,
+    /// Spaces, line breaks and commas.
+    Separator,
+    /// Anything else, which parse_rqq ignores.
+    Invalid,
+}
+
+/// A part of an RQQ string, used to highlight it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RqqToken {
+    pub kind: RqqTokenKind,
+    /// The byte range in the string.
+    pub start: usize,
+    pub end: usize,
+    /// How many lists enclose the token. Brackets have the depth of the list they open or close.
+    pub depth: usize,
+}
+
+/// A problem in an RQQ string and the byte range where it is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RqqSyntaxError {
+    pub start: usize,
+    pub end: usize,
+    pub message: String,
+}
+
+impl RqqSyntaxError {
+    fn at(token: &RqqToken, message: &str) -> Self {
+        Self { start: token.start, end: token.end, message: message.to_string() }
+    }
+}
+
+/// Split an RQQ string into brackets, numbers, separators and invalid characters.
+/// ```
+/// use metrefiddler::metre::rqq_syntax::{tokenize, RqqTokenKind};
+///
+/// let tokens = tokenize("(2 (1 1))");
+/// assert_eq!(tokens.len(), 9);
+/// assert_eq!(tokens[1].kind, RqqTokenKind::Number);
+/// assert_eq!(tokens.iter().map(|token| token.depth).max(), Some(2));
+/// ```
+pub fn tokenize(text: &str) -> Vec<RqqToken> {
+    let mut tokens: Vec<RqqToken> = Vec::new();
+    let mut depth: usize = 0;
+
+    for (start, c) in text.char_indices() {
+        let end = start + c.len_utf8();
+        let kind = match c {
+            '(' => RqqTokenKind::Open,
+            ')' => RqqTokenKind::Close,
+            '0'..='9' => RqqTokenKind::Number,
+            ',' => RqqTokenKind::Separator,
+            c if c.is_whitespace() => RqqTokenKind::Separator,
+            _ => RqqTokenKind::Invalid,
+        };
+
+        match (kind, tokens.last_mut()) {
+            // Digits belong to the number before them.
+            (RqqTokenKind::Number, Some(last)) if last.kind == RqqTokenKind::Number && last.end == start => {
+                last.end = end;
+            },
+            (RqqTokenKind::Open, _) => {
+                depth += 1;
+                tokens.push(RqqToken { kind, start, end, depth });
+            },
+            (RqqTokenKind::Close, _) => {
+                tokens.push(RqqToken { kind, start, end, depth });
+                depth = depth.saturating_sub(1);
+            },
+            _ => tokens.push(RqqToken { kind, start, end, depth }),
+        }
+    }
+
+    tokens
+}
+
+/// The byte index of the bracket matching the bracket at byte index idx.
+pub fn matching_bracket(text: &str, idx: usize) -> Option<usize> {
+    let mut open: Vec<usize> = Vec::new();
+    for token in tokenize(text) {
+        match token.kind {
+            RqqTokenKind::Open => open.push(token.start),
+            RqqTokenKind::Close => {
+                let Some(start) = open.pop() else {
+                    continue;
+                };
+                if start == idx {
+                    return Some(token.start);
+                } else if token.start == idx {
+                    return Some(start);
+                }
+            },
+            _ => (),
+        }
+    }
+
+    None
+}
+
+/// Find unmatched brackets, ignored characters and lists that are not built like
+/// (duration (subdivisions...)). An empty result doesn't guarantee that the metre is valid, but
+/// every result points at something that parse_rqq would reject or ignore.
+pub fn find_syntax_errors(text: &str) -> Vec<RqqSyntaxError> {
+    let tokens = tokenize(text);
+    let mut errors = Vec::new();
+    let mut open: Vec<&RqqToken> = Vec::new();
+
+    for token in &tokens {
+        match token.kind {
+            RqqTokenKind::Open => open.push(token),
+            RqqTokenKind::Close => if open.pop().is_none() {
+                errors.push(RqqSyntaxError::at(token, "This bracket closes no list"));
+            },
+            RqqTokenKind::Invalid => errors.push(RqqSyntaxError::at(token, "Only numbers, brackets, spaces and commas are allowed")),
+            RqqTokenKind::Number => if text[token.start..token.end].bytes().all(|b| b == b'0') {
+                errors.push(RqqSyntaxError::at(token, "Proportions must be positive"));
+            },
+            RqqTokenKind::Separator => (),
+        }
+    }
+    for token in open {
+        errors.push(RqqSyntaxError::at(token, "This list is never closed"));
+    }
+
+    // The structure can only be checked when all brackets match.
+    if errors.is_empty() {
+        let significant: Vec<&RqqToken> = tokens.iter()
+            .filter(|token| token.kind != RqqTokenKind::Separator)
+            .collect();
+        match significant.first() {
+            None => errors.push(RqqSyntaxError { start: 0, end: text.len(), message: "The metre is empty".to_string() }),
+            Some(first) if first.kind != RqqTokenKind::Open =>
+                errors.push(RqqSyntaxError::at(first, "A metre must start with a list")),
+            Some(_) => {
+                let mut pos = 0;
+                if let Err(error) = check_group(&significant, &mut pos) {
+                    errors.push(error);
+                } else if let Some(token) = significant.get(pos) {
+                    errors.push(RqqSyntaxError::at(token, "Everything after the first list is ignored"));
+                }
+            },
+        }
+    }
+
+    errors.sort_by_key(|error| error.start);
+    errors
+}
+
+/// Check a list like (duration (subdivisions...)) starting at tokens[pos].
+fn check_group(tokens: &[&RqqToken], pos: &mut usize) -> Result<(), RqqSyntaxError> {
+    // The caller made sure that tokens[pos] is an opening bracket and all brackets match.
+    let open = tokens[*pos];
+    *pos += 1;
+    match tokens[*pos] {
+        token if token.kind == RqqTokenKind::Number => *pos += 1,
+        token => return Err(RqqSyntaxError::at(token, "A list must start with its duration")),
+    }
+    match tokens[*pos] {
+        token if token.kind == RqqTokenKind::Open => *pos += 1,
+        token => return Err(RqqSyntaxError::at(token, "The duration must be followed by a list of subdivisions")),
+    }
+
+    // The subdivisions
+    let mut nr_of_subdivisions = 0;
+    loop {
+        let token = tokens[*pos];
+        match token.kind {
+            RqqTokenKind::Number => *pos += 1,
+            RqqTokenKind::Open => check_group(tokens, pos)?,
+            _ => break,
+        }
+        nr_of_subdivisions += 1;
+    }
+    if nr_of_subdivisions == 0 {
+        return Err(RqqSyntaxError::at(tokens[*pos], "A list of subdivisions must not be empty"));
+    }
+    *pos += 1;
+
+    match tokens[*pos] {
+        token if token.kind == RqqTokenKind::Close => {
+            *pos += 1;
+            Ok(())
+        },
+        token => Err(RqqSyntaxError { start: token.start, end: token.end, message: format!(
+            "The list starting at {} must end after its subdivisions", open.start) }),
+    }
+}
+
+/// Write an RQQ string so that every list that doesn't fit into width characters is split over
+/// several lines, with its subdivisions indented by their depth.
+/// ```
+/// use metrefiddler::metre::rqq_syntax::format_rqq;
+///
+/// assert_eq!(format_rqq("(4 ((2 (1 1)) (2 (1 1))))", 12).unwrap(),
+///            "(4 (\n  (2 (1 1))\n  (2 (1 1))))");
+/// ```
+pub fn format_rqq(text: &str, width: usize) -> Result<String, String> {
+    let mut result = String::new();
+    write_wrapped(&RqqNode::parse(text)?, 0, width, &mut result);
+    Ok(result)
+}
+
+fn write_wrapped(node: &RqqNode, indent: usize, width: usize, out: &mut String) {
+    let flat = node.to_string();
+    let RqqNode::Group(proportion, children) = node else {
+        out.push_str(&flat);
+        return;
+    };
+    if indent + flat.len() <= width {
+        out.push_str(&flat);
+        return;
+    }
+
+    out.push_str(&format!("({} (", proportion));
+    let child_indent = indent + 2;
+    // Beats share lines, every group starts a new one.
+    let mut line_len = width;
+    for child in children {
+        let child_flat = child.to_string();
+        match child {
+            RqqNode::Beat(_) if line_len + 1 + child_flat.len() <= width => {
+                out.push(' ');
+                out.push_str(&child_flat);
+                line_len += 1 + child_flat.len();
+            },
+            RqqNode::Beat(_) => {
+                out.push('\n');
+                out.push_str(&" ".repeat(child_indent));
+                out.push_str(&child_flat);
+                line_len = child_indent + child_flat.len();
+            },
+            RqqNode::Group(..) => {
+                out.push('\n');
+                out.push_str(&" ".repeat(child_indent));
+                write_wrapped(child, child_indent, width, out);
+                line_len = width;
+            },
+        }
+    }
+    out.push_str("))");
+}
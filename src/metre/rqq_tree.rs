@@ -0,0 +1,204 @@
+use std::fmt;
+use num_integer::Integer;
+use num_rational::Rational64;
+use num_traits::ToPrimitive;
+use crate::metre::rqq::{parse_rqq, RQQ};
+
+/// An edit of a single node in an RqqNode tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TreeEdit {
+    /// Split a beat into this many equal parts.
+    Split(usize),
+    /// Merge a node and its next sibling into a single beat.
+    MergeWithNext,
+    /// Put a node and its next sibling into a new group.
+    GroupWithNext,
+    /// Replace a group with its children.
+    Ungroup,
+    /// Move this much of the next sibling's proportion to the node.
+    Resize(i64),
+    /// Double the proportions of a node and its siblings, to allow finer resizing.
+    Refine,
+}
+
+/// An RQQ structure as a tree of beats and groups, each with its proportion within its parent.
+/// Nodes are addressed by their path, the indices of the children leading to them from the root.
+/// ```
+/// use metrefiddler::metre::rqq_tree::{RqqNode, TreeEdit};
+///
+/// let mut tree = RqqNode::parse("(4 (1 1 1 1))").unwrap();
+/// tree.edit(&[0], TreeEdit::GroupWithNext).unwrap();
+/// tree.edit(&[1], TreeEdit::GroupWithNext).unwrap();
+///
+/// assert_eq!(tree.to_string(), "(4 ((2 (1 1)) (2 (1 1))))");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RqqNode {
+    Beat(i64),
+    Group(i64, Vec<RqqNode>),
+}
+
+impl RqqNode {
+    /// Parse an RQQ string. The root is always a group.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let node = Self::from_rqq(&parse_rqq(text)?)?;
+        match node {
+            RqqNode::Group(..) => Ok(node),
+            RqqNode::Beat(_) => Err("rqq must be a list".to_string()),
+        }
+    }
+
+    fn from_rqq(rqq: &RQQ) -> Result<Self, String> {
+        match rqq {
+            RQQ::Elem(value) => Ok(RqqNode::Beat(Self::proportion_from(value)?)),
+            RQQ::List(vec) => match vec.as_slice() {
+                [RQQ::Elem(value), RQQ::List(children)] if !children.is_empty() => Ok(RqqNode::Group(
+                    Self::proportion_from(value)?,
+                    children.iter().map(Self::from_rqq).collect::<Result<_, _>>()?,
+                )),
+                _ => Err("rqq lists must consist of a duration and a list of subdivisions".to_string()),
+            },
+        }
+    }
+
+    fn proportion_from(value: &Rational64) -> Result<i64, String> {
+        value.to_integer().to_i64()
+            .filter(|&proportion| proportion > 0)
+            .ok_or_else(|| "rqq proportions must be positive".to_string())
+    }
+
+    pub fn proportion(&self) -> i64 {
+        match self {
+            RqqNode::Beat(proportion) | RqqNode::Group(proportion, _) => *proportion,
+        }
+    }
+
+    fn proportion_mut(&mut self) -> &mut i64 {
+        match self {
+            RqqNode::Beat(proportion) | RqqNode::Group(proportion, _) => proportion,
+        }
+    }
+
+    /// A copy with the proportion multiplied by factor, or None on overflow.
+    fn scaled(&self, factor: i64) -> Option<Self> {
+        let mut node = self.clone();
+        *node.proportion_mut() = self.proportion().checked_mul(factor)?;
+        Some(node)
+    }
+
+    pub fn children(&self) -> &[RqqNode] {
+        match self {
+            RqqNode::Beat(_) => &[],
+            RqqNode::Group(_, children) => children,
+        }
+    }
+
+    pub fn node(&self, path: &[usize]) -> Option<&RqqNode> {
+        match path.split_first() {
+            None => Some(self),
+            Some((&idx, rest)) => self.children().get(idx)?.node(rest),
+        }
+    }
+
+    fn children_mut(&mut self, path: &[usize]) -> Result<&mut Vec<RqqNode>, String> {
+        match path.split_first() {
+            None => match self {
+                RqqNode::Group(_, children) => Ok(children),
+                RqqNode::Beat(_) => Err("A beat has no subdivisions".to_string()),
+            },
+            Some((&idx, rest)) => match self {
+                RqqNode::Group(_, children) => children.get_mut(idx)
+                    .ok_or_else(|| "No such node".to_string())?
+                    .children_mut(rest),
+                RqqNode::Beat(_) => Err("No such node".to_string()),
+            },
+        }
+    }
+
+    /// Apply edit to the node at path. On error, the tree is left unchanged.
+    pub fn edit(&mut self, path: &[usize], edit: TreeEdit) -> Result<(), String> {
+        let (&idx, parent_path) = path.split_last()
+            .ok_or_else(|| "The whole bar can't be edited like this".to_string())?;
+        let siblings = self.children_mut(parent_path)?;
+        if idx >= siblings.len() {
+            return Err("No such node".to_string());
+        }
+        let has_next = idx + 1 < siblings.len();
+
+        match edit {
+            TreeEdit::MergeWithNext | TreeEdit::GroupWithNext | TreeEdit::Resize(_) if !has_next => {
+                return Err("There is no next node".to_string());
+            },
+            TreeEdit::Split(parts) => match siblings[idx] {
+                RqqNode::Beat(proportion) if parts >= 2 => {
+                    siblings[idx] = RqqNode::Group(proportion, vec![RqqNode::Beat(1); parts]);
+                },
+                RqqNode::Beat(_) => return Err("A beat must be split into at least two parts".to_string()),
+                RqqNode::Group(..) => return Err("Only beats can be split".to_string()),
+            },
+            TreeEdit::MergeWithNext => {
+                let next = siblings.remove(idx + 1);
+                siblings[idx] = RqqNode::Beat(siblings[idx].proportion() + next.proportion());
+            },
+            TreeEdit::GroupWithNext => {
+                let grouped: Vec<RqqNode> = siblings.drain(idx..idx + 2).collect();
+                let proportion = grouped.iter().map(RqqNode::proportion).sum();
+                siblings.insert(idx, RqqNode::Group(proportion, grouped));
+            },
+            TreeEdit::Ungroup => {
+                let RqqNode::Group(proportion, children) = siblings[idx].clone() else {
+                    return Err("Only groups can be ungrouped".to_string());
+                };
+                // The children keep their durations: scale everything to a common denominator.
+                let children_sum: i64 = children.iter().map(RqqNode::proportion).sum();
+                let mut ungrouped = siblings[..idx].iter()
+                    .map(|sibling| sibling.scaled(children_sum))
+                    .chain(children.iter().map(|child| child.scaled(proportion)))
+                    .chain(siblings[idx + 1..].iter().map(|sibling| sibling.scaled(children_sum)))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| "rqq proportions are too large".to_string())?;
+                let divisor = ungrouped.iter().fold(0, |acc: i64, sibling| acc.gcd(&sibling.proportion()));
+                for sibling in ungrouped.iter_mut() {
+                    *sibling.proportion_mut() /= divisor.max(1);
+                }
+                *siblings = ungrouped;
+            },
+            TreeEdit::Resize(delta) => {
+                let new_proportion = siblings[idx].proportion().checked_add(delta).unwrap_or(0);
+                let new_next = siblings[idx + 1].proportion().checked_sub(delta).unwrap_or(0);
+                if new_proportion < 1 || new_next < 1 {
+                    return Err("Proportions must stay positive".to_string());
+                }
+                *siblings[idx].proportion_mut() = new_proportion;
+                *siblings[idx + 1].proportion_mut() = new_next;
+            },
+            TreeEdit::Refine => {
+                *siblings = siblings.iter()
+                    .map(|sibling| sibling.scaled(2))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| "rqq proportions are too large".to_string())?;
+            },
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for RqqNode {
+    /// Write the tree in RQQ notation.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RqqNode::Beat(proportion) => write!(f, "{}", proportion),
+            RqqNode::Group(proportion, children) => {
+                write!(f, "({} (", proportion)?;
+                for (idx, child) in children.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", child)?;
+                }
+                write!(f, "))")
+            },
+        }
+    }
+}
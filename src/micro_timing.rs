@@ -0,0 +1,149 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use nih_plug::prelude::{NoteEvent, SysExMessage};
+
+/// How many events can be delayed at once. Delaying more would allocate on the audio thread.
+pub const DELAYED_EVENTS_CAPACITY: usize = 1024;
+
+/// Events that are sent later than they were produced. Their timing is counted in samples from the
+/// start of the current buffer, and they are kept in order. The capacity is fixed, when it is
+/// reached, push() hands the event back.
+pub struct DelayedEvents<S: SysExMessage> {
+    events: BinaryHeap<DelayedEvent<S>>,
+    capacity: usize,
+    // The samples since the first buffer. Events are due at a sample counted from there, so that
+    // they don't need to be moved on for each buffer.
+    now: u64,
+    // Counts the pushed events, events that are due together are sent in that order.
+    nr_pushed: u64,
+}
+
+struct DelayedEvent<S: SysExMessage> {
+    due: u64,
+    nr: u64,
+    event: NoteEvent<S>,
+}
+
+impl<S: SysExMessage> DelayedEvent<S> {
+    fn key(&self) -> (u64, u64) {
+        (self.due, self.nr)
+    }
+}
+
+impl<S: SysExMessage> PartialEq for DelayedEvent<S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl<S: SysExMessage> Eq for DelayedEvent<S> {}
+
+impl<S: SysExMessage> PartialOrd for DelayedEvent<S> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<S: SysExMessage> Ord for DelayedEvent<S> {
+    /// Reversed, so that the BinaryHeap pops the event that is due first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.key().cmp(&self.key())
+    }
+}
+
+impl<S: SysExMessage> Default for DelayedEvents<S> {
+    /// No events and no capacity, so taking the events out of the plugin doesn't allocate.
+    fn default() -> Self {
+        Self::with_capacity(0)
+    }
+}
+
+impl<S: SysExMessage> DelayedEvents<S> {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self { events: BinaryHeap::with_capacity(capacity), capacity, now: 0, nr_pushed: 0 }
+    }
+
+    /// Add an event that is due at timing. Events with the same timing keep the order they were
+    /// pushed in. When there are capacity events already, the event is returned instead.
+    pub fn push(&mut self, timing: u64, event: NoteEvent<S>) -> Result<(), NoteEvent<S>> {
+        if self.events.len() >= self.capacity {
+            return Err(event);
+        }
+        self.events.push(DelayedEvent { due: self.now + timing, nr: self.nr_pushed, event });
+        self.nr_pushed += 1;
+        Ok(())
+    }
+
+    /// Send the events that are due at timing or earlier, so that an event with that timing can
+    /// be sent right after them.
+    pub fn send_until(&mut self, timing: u64, send: impl FnMut(NoteEvent<S>)) {
+        self.send_before(timing + 1, send);
+    }
+
+    /// Send the events that are due within a buffer of nr_samples, and move the others on to the
+    /// next buffer.
+    pub fn send_due(&mut self, nr_samples: usize, send: impl FnMut(NoteEvent<S>)) {
+        self.send_before(nr_samples as u64, send);
+        self.now += nr_samples as u64;
+    }
+
+    fn send_before(&mut self, timing: u64, mut send: impl FnMut(NoteEvent<S>)) {
+        let end = self.now + timing;
+        while self.events.peek().is_some_and(|delayed| delayed.due < end) {
+            let Some(DelayedEvent { due, mut event, .. }) = self.events.pop() else {
+                break;
+            };
+            set_timing(&mut event, (due - self.now) as u32);
+            send(event);
+        }
+    }
+}
+
+/// Move an event to another sample within its buffer.
+fn set_timing<S: SysExMessage>(event: &mut NoteEvent<S>, new_timing: u32) {
+    match event {
+        NoteEvent::NoteOn { timing, .. }
+        | NoteEvent::NoteOff { timing, .. }
+        | NoteEvent::Choke { timing, .. }
+        | NoteEvent::VoiceTerminated { timing, .. }
+        | NoteEvent::PolyModulation { timing, .. }
+        | NoteEvent::MonoAutomation { timing, .. }
+        | NoteEvent::PolyPressure { timing, .. }
+        | NoteEvent::PolyVolume { timing, .. }
+        | NoteEvent::PolyPan { timing, .. }
+        | NoteEvent::PolyTuning { timing, .. }
+        | NoteEvent::PolyVibrato { timing, .. }
+        | NoteEvent::PolyExpression { timing, .. }
+        | NoteEvent::PolyBrightness { timing, .. }
+        | NoteEvent::MidiChannelPressure { timing, .. }
+        | NoteEvent::MidiPitchBend { timing, .. }
+        | NoteEvent::MidiCC { timing, .. }
+        | NoteEvent::MidiProgramChange { timing, .. }
+        | NoteEvent::MidiSysEx { timing, .. } => *timing = new_timing,
+        // Events nih_plug adds later keep their timing.
+        _ => (),
+    }
+}
+
+/// Remembers how far every incoming note was moved, so its NoteOff is moved by the same amount
+/// and can't overtake it.
+pub struct NoteOffsets {
+    offsets: [[i64; 128]; 16],
+}
+
+impl Default for NoteOffsets {
+    fn default() -> Self {
+        Self { offsets: [[0; 128]; 16] }
+    }
+}
+
+impl NoteOffsets {
+    pub fn note_on(&mut self, channel: u8, note: u8, offset: i64) {
+        self.offsets[channel as usize % 16][note as usize % 128] = offset;
+    }
+
+    /// The offset for a NoteOff, which is then forgotten.
+    pub fn note_off(&mut self, channel: u8, note: u8) -> i64 {
+        std::mem::take(&mut self.offsets[channel as usize % 16][note as usize % 128])
+    }
+}
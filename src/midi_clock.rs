@@ -0,0 +1,131 @@
+use nih_plug::prelude::SysExMessage;
+
+/// MIDI clock sends 24 ticks per quarter note.
+pub const CLOCKS_PER_QUARTER: u64 = 24;
+/// A Song Position Pointer counts sixteenth notes, which are 6 clock ticks.
+pub const CLOCKS_PER_SONG_POSITION: u64 = 6;
+/// How many tick intervals are averaged to estimate the tempo.
+const TEMPO_WINDOW: usize = 24;
+
+/// The MIDI system messages MetreFiddler understands. They arrive and are sent as SysEx, since
+/// nih_plug only parses channel messages itself.
+/// ```
+/// use nih_plug::prelude::SysExMessage;
+/// use metrefiddler::midi_clock::MidiSystemMessage;
+///
+/// let position = MidiSystemMessage::from_buffer(&[0xF2, 0x10, 0x01]).unwrap();
+/// assert_eq!(position, MidiSystemMessage::SongPosition(144));
+/// assert_eq!(position.to_buffer(), ([0xF2, 0x10, 0x01], 3));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MidiSystemMessage {
+    Clock,
+    Start,
+    Continue,
+    Stop,
+    /// The position in sixteenth notes since the start of the song.
+    SongPosition(u16),
+}
+
+impl SysExMessage for MidiSystemMessage {
+    type Buffer = [u8; 3];
+
+    fn from_buffer(buffer: &[u8]) -> Option<Self> {
+        match *buffer {
+            [0xF8, ..] => Some(Self::Clock),
+            [0xFA, ..] => Some(Self::Start),
+            [0xFB, ..] => Some(Self::Continue),
+            [0xFC, ..] => Some(Self::Stop),
+            [0xF2, lsb, msb, ..] => Some(Self::SongPosition((lsb & 0x7F) as u16 | (((msb & 0x7F) as u16) << 7))),
+            _ => None,
+        }
+    }
+
+    fn to_buffer(self) -> (Self::Buffer, usize) {
+        match self {
+            Self::Clock => ([0xF8, 0, 0], 1),
+            Self::Start => ([0xFA, 0, 0], 1),
+            Self::Continue => ([0xFB, 0, 0], 1),
+            Self::Stop => ([0xFC, 0, 0], 1),
+            Self::SongPosition(position) => ([0xF2, (position & 0x7F) as u8, ((position >> 7) & 0x7F) as u8], 3),
+        }
+    }
+}
+
+/// Follows incoming MIDI clock: whether it runs, where it is and how fast it ticks.
+pub struct MidiClock {
+    running: bool,
+    /// Ticks since the start of the song. The next tick is at this position.
+    clocks: u64,
+    /// Counts every processed sample, to measure the time between ticks.
+    samples: u64,
+    last_tick: Option<u64>,
+    intervals: [u64; TEMPO_WINDOW],
+    nr_of_intervals: usize,
+}
+
+impl Default for MidiClock {
+    fn default() -> Self {
+        Self {
+            running: false,
+            clocks: 0,
+            samples: 0,
+            last_tick: None,
+            intervals: [0; TEMPO_WINDOW],
+            nr_of_intervals: 0,
+        }
+    }
+}
+
+impl MidiClock {
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Call once for every processed sample.
+    pub fn increment(&mut self) {
+        self.samples += 1;
+    }
+
+    /// Handle a message. Returns the position in quarter notes when it was a tick while the clock
+    /// is running.
+    pub fn receive(&mut self, message: MidiSystemMessage) -> Option<f64> {
+        match message {
+            MidiSystemMessage::Clock => return self.tick(),
+            MidiSystemMessage::Start => {
+                self.clocks = 0;
+                self.running = true;
+            },
+            MidiSystemMessage::Continue => self.running = true,
+            MidiSystemMessage::Stop => self.running = false,
+            MidiSystemMessage::SongPosition(position) => self.clocks = position as u64 * CLOCKS_PER_SONG_POSITION,
+        }
+        None
+    }
+
+    fn tick(&mut self) -> Option<f64> {
+        if let Some(last_tick) = self.last_tick {
+            self.intervals[self.nr_of_intervals % TEMPO_WINDOW] = self.samples - last_tick;
+            self.nr_of_intervals += 1;
+        }
+        self.last_tick = Some(self.samples);
+
+        if !self.running {
+            return None;
+        }
+        let position = self.clocks as f64 / CLOCKS_PER_QUARTER as f64;
+        self.clocks += 1;
+        Some(position)
+    }
+
+    /// The tempo in BPM, averaged over the last ticks. None until two ticks arrived.
+    pub fn tempo(&self, sample_rate: f32) -> Option<f64> {
+        let nr_of_intervals = self.nr_of_intervals.min(TEMPO_WINDOW);
+        let sum: u64 = self.intervals[..nr_of_intervals].iter().sum();
+        if sum == 0 {
+            return None;
+        }
+        let samples_per_tick = sum as f64 / nr_of_intervals as f64;
+        Some(60.0 * sample_rate as f64 / (samples_per_tick * CLOCKS_PER_QUARTER as f64))
+    }
+}
@@ -2,10 +2,141 @@ use nih_plug::prelude::*;
 use vizia_plug::ViziaState;
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicBool, AtomicUsize};
-use std::sync::atomic::Ordering::Relaxed;
 use nih_plug::prelude::SmoothingStyle::Linear;
+use serde::{Deserialize, Serialize};
+use crate::chord::Chord;
+use crate::controller_scaling::CcSelection;
 use crate::editor;
+use crate::history::EditHistory;
+use crate::pitch_transform::TransposeTable;
+use crate::ratchet::MAX_RATCHET_REPEATS;
 use crate::metre::combined_metre_data::CombinedMetreData;
+use crate::metre::metre_slots::{MetreSlots, NR_OF_METRE_SLOTS};
+use crate::util::{dry_wet, rescale};
+
+/// How the velocity of a beat follows its indispensability.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VelocityMode {
+    /// Every indispensability value gets its own velocity.
+    #[id = "graded"]
+    Graded,
+    /// Only two distinct velocities, see the skew parameter.
+    #[id = "accents"]
+    Accents,
+}
+
+/// Which pitches the sent notes have.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoteOutput {
+    /// The pitch rises with the indispensability value, starting at middle C.
+    #[id = "by_indispensability"]
+    #[name = "By Indispensability"]
+    ByIndispensability,
+    /// Every note is a middle C.
+    #[id = "one_pitch"]
+    #[name = "One Pitch"]
+    OnePitch,
+}
+
+/// How the two threshold sliders select the beats that are let through.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThresholdMode {
+    /// Indispensability values between the thresholds.
+    #[default]
+    #[id = "window"]
+    Window,
+    /// The N most important beats, set by the upper threshold.
+    #[id = "top_n"]
+    #[name = "Top N"]
+    TopN,
+    /// Beats that start a group of a stratum between the thresholds.
+    #[id = "stratum"]
+    Stratum,
+    /// Indispensability values outside of the thresholds.
+    #[id = "inverted"]
+    Inverted,
+    /// The most important beats, as a percentage of all beats set by the upper threshold.
+    #[id = "density"]
+    Density,
+}
+
+impl ThresholdMode {
+    /// The labels of the lower and the upper threshold slider.
+    pub fn slider_labels(self) -> (&'static str, &'static str) {
+        match self {
+            ThresholdMode::Window | ThresholdMode::Stratum => ("min", "max"),
+            ThresholdMode::TopN => ("-", "N"),
+            ThresholdMode::Inverted => ("from", "to"),
+            ThresholdMode::Density => ("-", "%"),
+        }
+    }
+
+    /// What the threshold sliders display at their maximum.
+    pub fn slider_scale(self, max_indisp_val: usize, max_stratum: usize) -> usize {
+        match self {
+            ThresholdMode::Window | ThresholdMode::Inverted => max_indisp_val,
+            ThresholdMode::TopN => max_indisp_val + 1,
+            ThresholdMode::Stratum => max_stratum,
+            ThresholdMode::Density => 100,
+        }
+    }
+}
+
+/// Whether Send Midi plays the chord instead of single pitches.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChordMode {
+    #[id = "off"]
+    Off,
+    /// The more important a beat, the more voices of the chord it gets.
+    #[id = "chords"]
+    Chords,
+    /// One tone of the chord per beat, chosen by its importance.
+    #[id = "arpeggio"]
+    Arpeggio,
+}
+
+/// What the transpose table of incoming notes is indexed by.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransposeMode {
+    #[id = "off"]
+    Off,
+    /// The indispensability value, 0 is the most important beat.
+    #[id = "by_rank"]
+    #[name = "By Rank"]
+    ByRank,
+    /// The stratum of the group the beat starts, 0 when it starts none.
+    #[id = "by_stratum"]
+    #[name = "By Stratum"]
+    ByStratum,
+}
+
+/// How a beat that passes the ratchet threshold is subdivided into repeats.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RatchetMode {
+    #[id = "off"]
+    Off,
+    /// The number of ratchet repeats, evenly spaced.
+    #[id = "even"]
+    Even,
+    /// Like the group the beat belongs to, one level below the metre's hierarchy.
+    #[id = "metric"]
+    Metric,
+}
+
+/// What marks the start of every bar.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BarMarker {
+    #[id = "off"]
+    Off,
+    /// A note with the pitch of the bar marker number.
+    #[id = "note"]
+    Note,
+    /// A control change with the bar marker number and the highest value.
+    #[id = "cc"]
+    #[name = "CC"]
+    ControlChange,
+}
 
 #[derive(Params)]
 pub struct MetreFiddlerParams {
@@ -29,6 +160,8 @@ pub struct MetreFiddlerParams {
     pub lower_threshold: FloatParam,
     #[id = "upper_threshold"]
     pub upper_threshold: FloatParam,    
+    #[id = "threshold_mode"]
+    pub threshold_mode: EnumParam<ThresholdMode>,
     
     #[id = "velocity_skew"]
     pub velocity_skew: FloatParam,
@@ -44,6 +177,8 @@ pub struct MetreFiddlerParams {
     pub reset_phase: BoolParam,
     // This informs the Gui, that the phase_reset button needs resetting.
     pub reset_info: Arc<AtomicBool>,
+    // The last tempo reported by the host, so the Gui can export MIDI files in the right tempo.
+    pub host_tempo: Arc<AtomicF32>,
     
     // The `Arc<Mutex<>>` allows to share and modify it
     // between the GUI thread and the audio thread safely.
@@ -54,26 +189,106 @@ pub struct MetreFiddlerParams {
     #[id = "interpolate_a_b"]
     pub interpolate_a_b: FloatParam,
 
-    #[persist = "current_nr_of_beats"]
+    // Stored metres that the metre slot parameters switch between
+    #[persist = "metre_slots"]
+    pub metre_slots: Arc<Mutex<MetreSlots>>,
+
+    #[id = "metre_slot_a"]
+    pub metre_slot_a: IntParam,
+    #[id = "metre_slot_b"]
+    pub metre_slot_b: IntParam,
+
+    // The metre data for newly selected slots, prepared off the audio thread. The audio thread
+    // switches to it at the next bar, and swaps the old metre data into the Box.
+    pub pending_metre_data: Arc<Mutex<Option<Box<CombinedMetreData>>>>,
+    // Counts the switches, so the Gui knows when to update.
+    pub metre_switches: AtomicUsize,
+    // The result of a MIDI export that ran in the background, until the Gui shows it.
+    pub export_message: Arc<Mutex<Option<String>>>,
+    // The folder that MIDI files are exported to. Empty for the home directory.
+    #[persist = "export_dir"]
+    pub export_dir: Arc<Mutex<String>>,
+
+    // The number of beats in the current bar, derived from the metres and not persisted.
     pub current_nr_of_beats: AtomicUsize,
 
     #[id = "send_midi"]
     pub send_midi: BoolParam,
 
-    #[persist = "interpolate_durations"]
-    pub interpolate_durations: AtomicBool,
-
-    #[persist = "many_velocities"]
-    pub many_velocities: AtomicBool,
-
-    #[persist = "midi_out_one_note"]
-    pub midi_out_one_note: AtomicBool,
-    
-    #[persist = "interpolate_indisp"]
-    pub interpolate_indisp: AtomicBool,
-
-    #[persist = "retain_metric_phase"]
-    pub retain_metric_phase: AtomicBool,
+    #[id = "interpolate_durations"]
+    pub interpolate_durations: BoolParam,
+
+    #[id = "velocity_mode"]
+    pub velocity_mode: EnumParam<VelocityMode>,
+
+    #[id = "note_output"]
+    pub note_output: EnumParam<NoteOutput>,
+
+    #[id = "interpolate_indisp"]
+    pub interpolate_indisp: BoolParam,
+
+    #[id = "retain_metric_phase"]
+    pub retain_metric_phase: BoolParam,
+
+    #[id = "midi_clock_sync"]
+    pub midi_clock_sync: BoolParam,
+
+    #[id = "chord_mode"]
+    pub chord_mode: EnumParam<ChordMode>,
+    #[id = "chord_root"]
+    pub chord_root: IntParam,
+    // The intervals of the chord that the chord modes play.
+    #[persist = "chord"]
+    pub chord: Arc<Mutex<Chord>>,
+
+    #[id = "transpose_mode"]
+    pub transpose_mode: EnumParam<TransposeMode>,
+    // Semitones for each rank or stratum that incoming notes are transposed by.
+    #[persist = "transpose_table"]
+    pub transpose_table: Arc<Mutex<TransposeTable>>,
+    #[id = "quantize_to_chord"]
+    pub quantize_to_chord: BoolParam,
+
+    // How many ms incoming notes on the most and the least important beats are moved.
+    #[id = "timing_strong"]
+    pub timing_strong: FloatParam,
+    #[id = "timing_weak"]
+    pub timing_weak: FloatParam,
+
+    // Incoming values of the chosen CCs and aftertouch are lowered on weak beats, by up to depth.
+    #[id = "metric_cc_depth"]
+    pub metric_cc_depth: FloatParam,
+    #[persist = "metric_ccs"]
+    pub metric_ccs: Arc<Mutex<CcSelection>>,
+    #[id = "scale_aftertouch"]
+    pub scale_aftertouch: BoolParam,
+
+    // Beats up to the ratchet threshold are repeated within their duration.
+    #[id = "ratchet_mode"]
+    pub ratchet_mode: EnumParam<RatchetMode>,
+    #[id = "ratchet_threshold"]
+    pub ratchet_threshold: FloatParam,
+    #[id = "ratchet_repeats"]
+    pub ratchet_repeats: IntParam,
+    #[id = "ratchet_ramp"]
+    pub ratchet_ramp: FloatParam,
+
+    #[id = "send_midi_clock"]
+    pub send_midi_clock: BoolParam,
+
+    #[id = "bar_marker"]
+    pub bar_marker: EnumParam<BarMarker>,
+    #[id = "bar_marker_number"]
+    pub bar_marker_number: IntParam,
+
+    #[id = "send_group_notes"]
+    pub send_group_notes: BoolParam,
+    #[id = "group_note_base"]
+    pub group_note_base: IntParam,
+
+    // The undo/redo history of the editor, so it still works after reopening the window.
+    #[persist = "edit_history"]
+    pub edit_history: Arc<Mutex<EditHistory>>,
 }
 
 impl Default for MetreFiddlerParams {
@@ -104,6 +319,26 @@ impl Default for MetreFiddlerParams {
             )
                 .with_smoother(Linear(50.0)),
             
+            metre_slots: Arc::new(Mutex::new(MetreSlots::default())),
+
+            metre_slot_a: IntParam::new(
+                "Metre Slot A",
+                1,
+                IntRange::Linear { min: 1, max: NR_OF_METRE_SLOTS as i32 },
+            ),
+
+            metre_slot_b: IntParam::new(
+                "Metre Slot B",
+                1,
+                IntRange::Linear { min: 1, max: NR_OF_METRE_SLOTS as i32 },
+            ),
+
+            pending_metre_data: Arc::new(Mutex::new(None)),
+
+            metre_switches: AtomicUsize::new(0),
+            export_message: Arc::new(Mutex::new(None)),
+            export_dir: Arc::new(Mutex::new(String::new())),
+
             current_nr_of_beats: AtomicUsize::new(0),
 
             send_midi: BoolParam::new(
@@ -135,6 +370,11 @@ impl Default for MetreFiddlerParams {
                 FloatRange::Linear { min: 0.0, max: 1.0},
             ),
 
+            threshold_mode: EnumParam::new(
+                "Threshold Mode",
+                ThresholdMode::Window
+            ),
+
             velocity_skew: FloatParam::new(
                 "Skew value for Velocity Range",
                 0.5,
@@ -162,15 +402,141 @@ impl Default for MetreFiddlerParams {
 
             reset_info: Arc::new(AtomicBool::new(false)),
 
-            interpolate_durations: AtomicBool::from(true),
+            host_tempo: Arc::new(AtomicF32::new(120.0)),
+
+            interpolate_durations: BoolParam::new(
+                "Interpolate Durations",
+                true
+            ),
 
-            many_velocities: AtomicBool::from(true),
+            velocity_mode: EnumParam::new(
+                "Velocity Mode",
+                VelocityMode::Graded
+            ),
 
-            midi_out_one_note: AtomicBool::from(false),
-            
-            interpolate_indisp: AtomicBool::from(true),
+            note_output: EnumParam::new(
+                "Pitch Output",
+                NoteOutput::ByIndispensability
+            ),
+
+            interpolate_indisp: BoolParam::new(
+                "Interpolate Indispensability Values",
+                true
+            ),
+
+            retain_metric_phase: BoolParam::new(
+                "Retain Metric Phase",
+                true
+            ),
+
+            midi_clock_sync: BoolParam::new(
+                "Sync to MIDI Clock (CLAP only)",
+                false
+            ),
+
+            chord_mode: EnumParam::new(
+                "Chord Mode",
+                ChordMode::Off
+            ),
+
+            chord_root: IntParam::new(
+                "Chord Root",
+                48,
+                IntRange::Linear { min: 0, max: 127 },
+            ),
+
+            chord: Arc::new(Mutex::new(Chord::default())),
+
+            transpose_mode: EnumParam::new(
+                "Transpose Incoming Notes",
+                TransposeMode::Off
+            ),
+
+            transpose_table: Arc::new(Mutex::new(TransposeTable::default())),
+
+            quantize_to_chord: BoolParam::new(
+                "Quantize Incoming Notes to the Chord",
+                false
+            ),
+
+            timing_strong: FloatParam::new(
+                "Timing of Strong Beats",
+                0.0,
+                FloatRange::Linear { min: -50.0, max: 50.0 },
+            )
+                .with_unit(" ms"),
 
-            retain_metric_phase: AtomicBool::from(true),
+            timing_weak: FloatParam::new(
+                "Timing of Weak Beats",
+                0.0,
+                FloatRange::Linear { min: -50.0, max: 50.0 },
+            )
+                .with_unit(" ms"),
+
+            metric_cc_depth: FloatParam::new(
+                "Metric CC Depth",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            ),
+
+            metric_ccs: Arc::new(Mutex::new(CcSelection::default())),
+
+            scale_aftertouch: BoolParam::new(
+                "Scale Aftertouch by Metric Weight",
+                false
+            ),
+
+            ratchet_mode: EnumParam::new(
+                "Ratchet Mode",
+                RatchetMode::Off
+            ),
+
+            ratchet_threshold: FloatParam::new(
+                "Ratchet Threshold",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            ),
+
+            ratchet_repeats: IntParam::new(
+                "Ratchet Repeats",
+                4,
+                IntRange::Linear { min: 2, max: MAX_RATCHET_REPEATS as i32 },
+            ),
+
+            ratchet_ramp: FloatParam::new(
+                "Ratchet Velocity Ramp",
+                0.0,
+                FloatRange::Linear { min: -1.0, max: 1.0 },
+            ),
+
+            send_midi_clock: BoolParam::new(
+                "Send MIDI Clock (CLAP only)",
+                false
+            ),
+
+            bar_marker: EnumParam::new(
+                "Bar Start Marker",
+                BarMarker::Off
+            ),
+
+            bar_marker_number: IntParam::new(
+                "Bar Start Note or CC",
+                24,
+                IntRange::Linear { min: 0, max: 127 },
+            ),
+
+            send_group_notes: BoolParam::new(
+                "Send Notes at Group Starts",
+                false
+            ),
+
+            group_note_base: IntParam::new(
+                "Lowest Group Note",
+                36,
+                IntRange::Linear { min: 0, max: 127 },
+            ),
+
+            edit_history: Arc::new(Mutex::new(EditHistory::default())),
 
             // TODO new potential settings: Note Out Duration (how would you input this duration? or just different options like 'short', 'fill_beat'...?),
             // TODO         -"-             Note Out base pitch and channel (dropdown menus? :))
@@ -182,38 +548,209 @@ impl MetreFiddlerParams {
     /// Return all plain values of Parameters in a ParamsSnapShot,
     /// Parameters that need smoothing will get that somewhere else.
     pub fn snapshot(&self) -> ParamsSnapShot {
+        self.snapshot_with_fallback(None)
+    }
+
+    /// Like snapshot(), but never waits for the Gui: values that the Gui is editing right now
+    /// are kept from the last snapshot. This is what the audio thread uses.
+    pub fn snapshot_since(&self, last: &ParamsSnapShot) -> ParamsSnapShot {
+        self.snapshot_with_fallback(Some(last))
+    }
+
+    fn snapshot_with_fallback(&self, last: Option<&ParamsSnapShot>) -> ParamsSnapShot {
         ParamsSnapShot {
             vel_min: self.velocity_min.value(),
             vel_max: self.velocity_max.value(),
             vel_skew: self.velocity_skew.value(),
             lower_threshold: self.lower_threshold.value(),
             upper_threshold: self.upper_threshold.value(),
+            threshold_mode: self.threshold_mode.value(),
+            metric_dur: self.metric_dur_selector.value(),
             bar_pos: self.bar_position.value(),
             interpolate: self.interpolate_a_b.value(),
+            slot_a: (self.metre_slot_a.value() - 1) as usize,
+            slot_b: (self.metre_slot_b.value() - 1) as usize,
             use_bpm: self.use_bpm.value(),
-            output_one_pitch: self.midi_out_one_note.load(Relaxed),
-            many_velocities: self.many_velocities.load(Relaxed),
-            interpolate_durs: self.interpolate_durations.load(Relaxed),
-            interpolate_indisp: self.interpolate_indisp.load(Relaxed),
+            use_position: self.use_position.value(),
+            send_midi: self.send_midi.value(),
+            retain_metric_phase: self.retain_metric_phase.value(),
+            midi_clock_sync: self.midi_clock_sync.value(),
+            chord_mode: self.chord_mode.value(),
+            chord_root: self.chord_root.value() as u8,
+            chord: read_locked(&self.chord, last.map(|last| last.chord)),
+            transpose_mode: self.transpose_mode.value(),
+            transpose_table: read_locked(&self.transpose_table, last.map(|last| last.transpose_table)),
+            quantize_to_chord: self.quantize_to_chord.value(),
+            timing_strong: self.timing_strong.value(),
+            timing_weak: self.timing_weak.value(),
+            metric_cc_depth: self.metric_cc_depth.value(),
+            metric_ccs: read_locked(&self.metric_ccs, last.map(|last| last.metric_ccs)),
+            scale_aftertouch: self.scale_aftertouch.value(),
+            ratchet_mode: self.ratchet_mode.value(),
+            ratchet_threshold: self.ratchet_threshold.value(),
+            ratchet_repeats: self.ratchet_repeats.value() as usize,
+            ratchet_ramp: self.ratchet_ramp.value(),
+            send_midi_clock: self.send_midi_clock.value(),
+            bar_marker: self.bar_marker.value(),
+            bar_marker_number: self.bar_marker_number.value() as u8,
+            send_group_notes: self.send_group_notes.value(),
+            group_note_base: self.group_note_base.value() as u8,
+            output_one_pitch: self.note_output.value() == NoteOutput::OnePitch,
+            many_velocities: self.velocity_mode.value() == VelocityMode::Graded,
+            interpolate_durs: self.interpolate_durations.value(),
+            interpolate_indisp: self.interpolate_indisp.value(),
         }
     }
 }
 
+/// The value behind the lock. With a fallback, this doesn't wait while the lock is held elsewhere.
+fn read_locked<T: Copy>(mutex: &Mutex<T>, fallback: Option<T>) -> T {
+    match fallback {
+        Some(fallback) => mutex.try_lock().map_or(fallback, |value| *value),
+        None => *mutex.lock().unwrap(),
+    }
+}
+
+#[derive(Clone, Copy)]
 pub struct ParamsSnapShot {
     pub vel_min: f32,
     pub vel_max: f32,
     pub vel_skew: f32,
     pub lower_threshold: f32,
     pub upper_threshold: f32,
+    pub threshold_mode: ThresholdMode,
+    pub metric_dur: f32,
     pub bar_pos: f32,
     pub interpolate: f32,
+    /// The selected metre slots, counted from 0.
+    pub slot_a: usize,
+    pub slot_b: usize,
     pub use_bpm: bool,
+    pub use_position: bool,
+    pub send_midi: bool,
+    pub retain_metric_phase: bool,
+    /// Follow incoming MIDI clock and Song Position Pointer instead of the host's transport.
+    pub midi_clock_sync: bool,
+    pub chord_mode: ChordMode,
+    pub chord_root: u8,
+    pub chord: Chord,
+    pub transpose_mode: TransposeMode,
+    pub transpose_table: TransposeTable,
+    /// Move transposed incoming notes to the nearest tone of the chord, played as a scale.
+    pub quantize_to_chord: bool,
+    /// In ms, negative values move notes earlier.
+    pub timing_strong: f32,
+    pub timing_weak: f32,
+    /// How much incoming controller values are lowered on the least important beat.
+    pub metric_cc_depth: f32,
+    pub metric_ccs: CcSelection,
+    /// Scale poly and channel pressure like the chosen CCs.
+    pub scale_aftertouch: bool,
+    pub ratchet_mode: RatchetMode,
+    /// Beats whose indispensability value is at most this share of the highest one are ratcheted.
+    pub ratchet_threshold: f32,
+    pub ratchet_repeats: usize,
+    pub ratchet_ramp: f32,
+    /// Send MIDI clock that follows the metric phase, so a bar has metric_dur quarter notes.
+    pub send_midi_clock: bool,
+    pub bar_marker: BarMarker,
+    pub bar_marker_number: u8,
+    /// Send a note whenever a beat starts a group, one pitch per stratum above group_note_base.
+    pub send_group_notes: bool,
+    pub group_note_base: u8,
     pub output_one_pitch: bool,
     pub many_velocities: bool,
     pub interpolate_durs: bool,
     pub interpolate_indisp: bool,
 }
 
+impl ParamsSnapShot {
+    /// Whether a beat passes the thresholds in the current threshold mode. beat_idx, indisp_val
+    /// and nr_beats are as returned by get_indisp_data_at.
+    pub fn is_within_thresholds(&self, metric_data: &CombinedMetreData, beat_idx: usize, indisp_val: usize, nr_beats: usize) -> bool {
+        let max_indisp_val = metric_data.max_indisp_val();
+        let within_window = |value: usize, max: usize| {
+            value >= (self.lower_threshold.min(self.upper_threshold) * max as f32) as usize
+                && value <= (self.upper_threshold * max as f32) as usize
+        };
+
+        match self.threshold_mode {
+            ThresholdMode::Window => within_window(indisp_val, max_indisp_val),
+            ThresholdMode::TopN => indisp_val < (self.upper_threshold * (max_indisp_val + 1) as f32) as usize,
+            ThresholdMode::Stratum => within_window(
+                metric_data.get_gnsm_at(beat_idx, self.interpolate, self.interpolate_durs),
+                metric_data.max_stratum(),
+            ),
+            ThresholdMode::Inverted => !within_window(indisp_val, max_indisp_val),
+            ThresholdMode::Density => indisp_val < (self.upper_threshold * nr_beats as f32).round() as usize,
+        }
+    }
+
+    /// Whether a beat with this indispensability value is subdivided into ratchet repeats.
+    pub fn is_ratcheted(&self, indisp_val: usize, max_indisp_val: usize) -> bool {
+        self.ratchet_mode != RatchetMode::Off
+            && indisp_val <= (self.ratchet_threshold * max_indisp_val as f32) as usize
+    }
+
+    /// In Accent-Mode, whether a beat gets the lower velocity.
+    pub fn is_accent(&self, indisp_value: usize, nr_beats: usize) -> bool {
+        let nr_of_accents = (self.vel_skew * nr_beats as f32).round() as usize;
+        indisp_value >= nr_of_accents
+    }
+
+    /// How many ms a note with this indispensability value is moved, from timing_strong for the
+    /// most important beat to timing_weak for the least important one.
+    pub fn timing_offset(&self, indisp_value: usize, max_indisp_val: usize) -> f32 {
+        let weight = if max_indisp_val == 0 {
+            0.0
+        } else {
+            indisp_value.min(max_indisp_val) as f32 / max_indisp_val as f32
+        };
+        dry_wet(self.timing_strong, self.timing_weak, weight)
+    }
+
+    /// The factor for incoming controller values at a beat with this indispensability value, 1.0
+    /// on the most important beat down to 1.0 - metric_cc_depth on the least important one.
+    pub fn controller_scale(&self, indisp_value: usize, max_indisp_val: usize) -> f32 {
+        let weight = if max_indisp_val == 0 {
+            1.0
+        } else {
+            1.0 - indisp_value.min(max_indisp_val) as f32 / max_indisp_val as f32
+        };
+        dry_wet(1.0, weight, self.metric_cc_depth)
+    }
+
+    /// The latency to report to the host. Every event is delayed by it, so notes can be moved
+    /// earlier by as much.
+    pub fn latency_samples(&self, sample_rate: f32) -> u32 {
+        let earliest = self.timing_strong.min(self.timing_weak).min(0.0);
+        (-earliest / 1000.0 * sample_rate).round() as u32
+    }
+
+    /// The velocity (between 0.0 and 1.0) for a beat with this indispensability value.
+    pub fn velocity(&self, indisp_value: usize, nr_beats: usize) -> f32 {
+        // The current velocity Parameters
+        let v_min: f32 = self.vel_min.min(self.vel_max) / 127.0;
+        let v_max: f32 = self.vel_min.max(self.vel_max) / 127.0;
+        // Velocity in range 0.0 - 1.0,
+        let normalized_vel =
+            if self.many_velocities {
+                (1.0 / (indisp_value + 1) as f32).powf(2.0*(1.0 - self.vel_skew))
+            } else if self.is_accent(indisp_value, nr_beats) {
+                v_min
+            } else {
+                v_max
+            };
+        // rescaled by vel_min and vel_max parameters
+        if v_min == v_max {
+            v_min
+        } else {
+            rescale(normalized_vel, 0.0, 1.0, v_min, v_max, true)
+                .unwrap_or(0.8)
+        }
+    }
+}
+
 impl Default for ParamsSnapShot {
     fn default() -> Self {
         Self {
@@ -222,9 +759,37 @@ impl Default for ParamsSnapShot {
             vel_skew: 0.5,
             lower_threshold: 0.0,
             upper_threshold: 1.0,
+            threshold_mode: ThresholdMode::Window,
+            metric_dur: 1.0,
             bar_pos: 0.0,
             interpolate: 0.0,
+            slot_a: 0,
+            slot_b: 0,
             use_bpm: false,
+            use_position: false,
+            send_midi: false,
+            retain_metric_phase: true,
+            midi_clock_sync: false,
+            chord_mode: ChordMode::Off,
+            chord_root: 48,
+            chord: Chord::default(),
+            transpose_mode: TransposeMode::Off,
+            transpose_table: TransposeTable::default(),
+            quantize_to_chord: false,
+            timing_strong: 0.0,
+            timing_weak: 0.0,
+            metric_cc_depth: 0.0,
+            metric_ccs: CcSelection::default(),
+            scale_aftertouch: false,
+            ratchet_mode: RatchetMode::Off,
+            ratchet_threshold: 0.0,
+            ratchet_repeats: 4,
+            ratchet_ramp: 0.0,
+            send_midi_clock: false,
+            bar_marker: BarMarker::Off,
+            bar_marker_number: 24,
+            send_group_notes: false,
+            group_note_base: 36,
             output_one_pitch: false,
             many_velocities: true,
             interpolate_durs: true,
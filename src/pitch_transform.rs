@@ -0,0 +1,116 @@
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use crate::chord::{transpose, Chord};
+
+/// The most entries a transpose table can have.
+pub const MAX_TRANSPOSE_ENTRIES: usize = 16;
+/// The largest transposition up or down, four octaves.
+const MAX_TRANSPOSITION: i8 = 48;
+
+/// Semitones to transpose incoming notes by, looked up by the indispensability value or the
+/// stratum of their position. Positions beyond the end of the table are not transposed.
+/// Persisted as its string.
+/// ```
+/// use metrefiddler::pitch_transform::TransposeTable;
+///
+/// let table = TransposeTable::try_from("12 0 -5").unwrap();
+/// assert_eq!(table.offset(0), 12);
+/// assert_eq!(table.offset(2), -5);
+/// assert_eq!(table.offset(3), 0);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct TransposeTable {
+    offsets: [i8; MAX_TRANSPOSE_ENTRIES],
+    len: usize,
+}
+
+impl Default for TransposeTable {
+    /// The most important beat an octave up.
+    fn default() -> Self {
+        Self::try_from("12").expect("The default transpose table should always be valid")
+    }
+}
+
+impl TransposeTable {
+    pub fn offsets(&self) -> &[i8] {
+        &self.offsets[..self.len]
+    }
+
+    /// The transposition in semitones for a rank or stratum.
+    pub fn offset(&self, idx: usize) -> i8 {
+        self.offsets().get(idx).copied().unwrap_or(0)
+    }
+
+    pub fn transpose(&self, idx: usize, note: u8) -> u8 {
+        transpose(note, self.offset(idx) as i32)
+    }
+}
+
+/// Remembers the pitch every incoming note was sent with, so its NoteOff gets the same pitch even
+/// when the transposition changed in the meantime.
+pub struct TransposedNotes {
+    notes: [[u8; 128]; 16],
+}
+
+impl Default for TransposedNotes {
+    fn default() -> Self {
+        Self { notes: [std::array::from_fn(|note| note as u8); 16] }
+    }
+}
+
+impl TransposedNotes {
+    pub fn note_on(&mut self, channel: u8, note: u8, sent_note: u8) {
+        self.notes[channel as usize % 16][note as usize % 128] = sent_note;
+    }
+
+    /// The pitch for a NoteOff, which is then forgotten.
+    pub fn note_off(&mut self, channel: u8, note: u8) -> u8 {
+        std::mem::replace(&mut self.notes[channel as usize % 16][note as usize % 128], note)
+    }
+}
+
+impl TryFrom<&str> for TransposeTable {
+    type Error = String;
+
+    /// Parse semitones, separated by spaces or commas.
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let offsets = value
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|s| !s.is_empty())
+            .map(|s| match s.parse::<i8>() {
+                Ok(offset) if (-MAX_TRANSPOSITION..=MAX_TRANSPOSITION).contains(&offset) => Ok(offset),
+                _ => Err(format!("'{}' is not a transposition between -{} and {}", s, MAX_TRANSPOSITION, MAX_TRANSPOSITION)),
+            })
+            .collect::<Result<Vec<i8>, String>>()?;
+
+        if offsets.len() > MAX_TRANSPOSE_ENTRIES {
+            return Err(format!("a transpose table can have at most {} entries", MAX_TRANSPOSE_ENTRIES));
+        }
+
+        let mut table = Self { offsets: [0; MAX_TRANSPOSE_ENTRIES], len: offsets.len() };
+        table.offsets[..offsets.len()].copy_from_slice(&offsets);
+        Ok(table)
+    }
+}
+
+impl TryFrom<String> for TransposeTable {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::try_from(value.as_str())
+    }
+}
+
+impl fmt::Display for TransposeTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let offsets: Vec<String> = self.offsets().iter().map(|offset| offset.to_string()).collect();
+        write!(f, "{}", offsets.join(" "))
+    }
+}
+
+impl From<TransposeTable> for String {
+    fn from(table: TransposeTable) -> Self {
+        table.to_string()
+    }
+}
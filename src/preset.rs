@@ -0,0 +1,286 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use nih_plug::nih_log;
+use serde::{Deserialize, Serialize};
+use vizia_plug::vizia::prelude::Data;
+use crate::metre::combined_metre_data::CombinedMetreData;
+use crate::metre::metre_data::MetreData;
+use crate::params::{ParamsSnapShot, ThresholdMode};
+
+/// The category of all presets saved by the user.
+pub const USER_CATEGORY: &str = "User";
+
+/// (category, name, rqq) of the factory library.
+const FACTORY_METRES: &[(&str, &str, &str)] = &[
+    // Common and odd time signatures
+    ("Time Signature", "2/4", "(2 (1 1))"),
+    ("Time Signature", "3/4", "(3 (1 1 1))"),
+    ("Time Signature", "4/4", "(4 ((2 (1 1)) (2 (1 1))))"),
+    ("Time Signature", "5/4 (3+2)", "(5 ((3 (1 1 1)) (2 (1 1))))"),
+    ("Time Signature", "5/4 (2+3)", "(5 ((2 (1 1)) (3 (1 1 1))))"),
+    ("Time Signature", "6/8", "(6 ((3 (1 1 1)) (3 (1 1 1))))"),
+    ("Time Signature", "7/8 (2+2+3)", "(7 ((2 (1 1)) (2 (1 1)) (3 (1 1 1))))"),
+    ("Time Signature", "7/8 (3+2+2)", "(7 ((3 (1 1 1)) (2 (1 1)) (2 (1 1))))"),
+    ("Time Signature", "9/8", "(9 ((3 (1 1 1)) (3 (1 1 1)) (3 (1 1 1))))"),
+    ("Time Signature", "12/8", "(12 ((6 ((3 (1 1 1)) (3 (1 1 1)))) (6 ((3 (1 1 1)) (3 (1 1 1))))))"),
+    // Aksak rhythms
+    ("Aksak", "Aksak 5/8 (2+3)", "(5 ((2 (1 1)) (3 (1 1 1))))"),
+    ("Aksak", "Ruchenitsa 7/16 (2+2+3)", "(7 ((2 (1 1)) (2 (1 1)) (3 (1 1 1))))"),
+    ("Aksak", "Daychovo 9/16 (2+2+2+3)", "(9 ((2 (1 1)) (2 (1 1)) (2 (1 1)) (3 (1 1 1))))"),
+    ("Aksak", "Karsilama 9/8 (2+2+2+3)", "(9 ((4 ((2 (1 1)) (2 (1 1)))) (5 ((2 (1 1)) (3 (1 1 1))))))"),
+    ("Aksak", "Kopanitsa 11/16 (2+2+3+2+2)", "(11 ((2 (1 1)) (2 (1 1)) (3 (1 1 1)) (2 (1 1)) (2 (1 1))))"),
+    ("Aksak", "Buchimish 15/16 (2+2+2+2+3+2+2)", "(15 ((2 (1 1)) (2 (1 1)) (2 (1 1)) (2 (1 1)) (3 (1 1 1)) (2 (1 1)) (2 (1 1))))"),
+    // Tala cycles
+    ("Tala", "Adi Tala (4+2+2)", "(8 ((4 (1 1 1 1)) (2 (1 1)) (2 (1 1))))"),
+    ("Tala", "Rupaka Tala (2+4)", "(6 ((2 (1 1)) (4 (1 1 1 1))))"),
+    ("Tala", "Khanda Chapu (2+3)", "(5 ((2 (1 1)) (3 (1 1 1))))"),
+    ("Tala", "Misra Chapu (3+2+2)", "(7 ((3 (1 1 1)) (2 (1 1)) (2 (1 1))))"),
+    ("Tala", "Jhaptaal (2+3+2+3)", "(10 ((2 (1 1)) (3 (1 1 1)) (2 (1 1)) (3 (1 1 1))))"),
+    ("Tala", "Teentaal (4+4+4+4)", "(16 ((8 ((4 (1 1 1 1)) (4 (1 1 1 1)))) (8 ((4 (1 1 1 1)) (4 (1 1 1 1))))))"),
+    // Clave patterns, each beat starts at a stroke of the clave
+    ("Clave", "Tresillo (3+3+2)", "(8 (3 3 2))"),
+    ("Clave", "Son Clave 3-2", "(16 ((10 (3 3 4)) (6 (2 4))))"),
+    ("Clave", "Rumba Clave 3-2", "(16 ((10 (3 4 3)) (6 (2 4))))"),
+    ("Clave", "Bossa Nova Clave", "(16 ((10 (3 3 4)) (6 (3 3))))"),
+    // Barlow's stratified metres, from the slowest to the fastest stratum
+    ("Barlow", "Barlow 2·2·3", "(12 ((6 ((3 (1 1 1)) (3 (1 1 1)))) (6 ((3 (1 1 1)) (3 (1 1 1))))))"),
+    ("Barlow", "Barlow 2·3·2", "(12 ((6 ((2 (1 1)) (2 (1 1)) (2 (1 1)))) (6 ((2 (1 1)) (2 (1 1)) (2 (1 1))))))"),
+    ("Barlow", "Barlow 3·2·2", "(12 ((4 ((2 (1 1)) (2 (1 1)))) (4 ((2 (1 1)) (2 (1 1)))) (4 ((2 (1 1)) (2 (1 1))))))"),
+    ("Barlow", "Barlow 5", "(5 (1 1 1 1 1))"),
+    ("Barlow", "Barlow 7", "(7 (1 1 1 1 1 1 1))"),
+    ("Barlow", "Barlow 2·5", "(10 ((5 (1 1 1 1 1)) (5 (1 1 1 1 1))))"),
+];
+
+/// (category, name, rqq of A, rqq of B) of the factory presets that set both metres.
+const FACTORY_PAIRS: &[(&str, &str, &str, &str)] = &[
+    ("Time Signature", "6/8 to 3/4", "(6 ((3 (1 1 1)) (3 (1 1 1))))", "(6 ((2 (1 1)) (2 (1 1)) (2 (1 1))))"),
+    ("Aksak", "7/8 (2+2+3) to (3+2+2)", "(7 ((2 (1 1)) (2 (1 1)) (3 (1 1 1))))", "(7 ((3 (1 1 1)) (2 (1 1)) (2 (1 1))))"),
+    ("Clave", "4/4 to Son Clave", "(16 ((8 ((4 (1 1 1 1)) (4 (1 1 1 1)))) (8 ((4 (1 1 1 1)) (4 (1 1 1 1))))))", "(16 ((10 (3 3 4)) (6 (2 4))))"),
+];
+
+/// Which part of the plugin state a preset is loaded into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PresetTarget {
+    MetreA,
+    MetreB,
+    /// Both metres, and the settings if the preset has any.
+    Everything,
+}
+
+/// The settings that are stored together with the metres in a preset.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Data)]
+pub struct PresetSettings {
+    pub lower_threshold: f32,
+    pub upper_threshold: f32,
+    // Presets from before the threshold modes use the window.
+    #[serde(default)]
+    #[data(eq)]
+    pub threshold_mode: ThresholdMode,
+    pub velocity_min: f32,
+    pub velocity_max: f32,
+    pub velocity_skew: f32,
+    pub interpolate_durations: bool,
+    pub interpolate_indisp: bool,
+    pub many_velocities: bool,
+    pub midi_out_one_note: bool,
+}
+
+impl PresetSettings {
+    pub fn from_snapshot(snapshot: &ParamsSnapShot) -> Self {
+        Self {
+            lower_threshold: snapshot.lower_threshold,
+            upper_threshold: snapshot.upper_threshold,
+            threshold_mode: snapshot.threshold_mode,
+            velocity_min: snapshot.vel_min,
+            velocity_max: snapshot.vel_max,
+            velocity_skew: snapshot.vel_skew,
+            interpolate_durations: snapshot.interpolate_durs,
+            interpolate_indisp: snapshot.interpolate_indisp,
+            many_velocities: snapshot.many_velocities,
+            midi_out_one_note: snapshot.output_one_pitch,
+        }
+    }
+
+    pub fn apply_to_snapshot(&self, snapshot: &mut ParamsSnapShot) {
+        snapshot.lower_threshold = self.lower_threshold;
+        snapshot.upper_threshold = self.upper_threshold;
+        snapshot.threshold_mode = self.threshold_mode;
+        snapshot.vel_min = self.velocity_min;
+        snapshot.vel_max = self.velocity_max;
+        snapshot.vel_skew = self.velocity_skew;
+        snapshot.interpolate_durs = self.interpolate_durations;
+        snapshot.interpolate_indisp = self.interpolate_indisp;
+        snapshot.many_velocities = self.many_velocities;
+        snapshot.output_one_pitch = self.midi_out_one_note;
+    }
+}
+
+/// A named metre (or pair of metres), optionally with thresholds and velocity settings.
+/// User presets are saved as JSON files in user_preset_dir().
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Data)]
+pub struct Preset {
+    pub name: String,
+    pub category: String,
+    pub metre_a: String,
+    // Without metre B, loading everything sets both metres to metre_a.
+    #[serde(default)]
+    pub metre_b: Option<String>,
+    #[serde(default)]
+    pub settings: Option<PresetSettings>,
+}
+
+impl Preset {
+    /// A preset with a single metre and no settings.
+    pub fn new(name: &str, category: &str, rqq: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            category: category.to_string(),
+            metre_a: rqq.to_string(),
+            metre_b: None,
+            settings: None,
+        }
+    }
+
+    /// The rqq string for metre B, which is metre A if the preset has only one metre.
+    pub fn metre_b_string(&self) -> &str {
+        self.metre_b.as_deref().unwrap_or(&self.metre_a)
+    }
+
+    pub fn metre_a_data(&self) -> Result<MetreData, String> {
+        MetreData::try_from(self.metre_a.as_str())
+    }
+
+    pub fn metre_b_data(&self) -> Result<MetreData, String> {
+        MetreData::try_from(self.metre_b_string())
+    }
+
+    pub fn combined_metre_data(&self) -> Result<CombinedMetreData, String> {
+        CombinedMetreData::new(self.metre_a_data()?, self.metre_b_data()?)
+    }
+
+    /// Load the part of the preset selected by target into the metres and the snapshot.
+    /// Nothing is changed if the preset contains an invalid metre.
+    pub fn apply(
+        &self,
+        target: PresetTarget,
+        combined_metre_data: &mut CombinedMetreData,
+        snapshot: &mut ParamsSnapShot,
+    ) -> Result<(), String> {
+        match target {
+            PresetTarget::MetreA => combined_metre_data.set_metre_a(self.metre_a_data()?),
+            PresetTarget::MetreB => combined_metre_data.set_metre_b(self.metre_b_data()?),
+            PresetTarget::Everything => {
+                *combined_metre_data = self.combined_metre_data()?;
+                if let Some(settings) = &self.settings {
+                    settings.apply_to_snapshot(snapshot);
+                }
+                Ok(())
+            },
+        }
+    }
+
+    /// The file name for this preset within the user preset directory.
+    pub fn file_name(&self) -> String {
+        let name: String = self.name.trim()
+            .chars()
+            .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' || c == ' ' { c } else { '_' })
+            .collect();
+        format!("{}.json", name)
+    }
+
+    pub fn load_file(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read '{}': {}", path.display(), err))?;
+        let preset: Self = serde_json::from_str(&text)
+            .map_err(|err| format!("Failed to parse '{}': {}", path.display(), err))?;
+        preset.combined_metre_data()
+            .map_err(|err| format!("Invalid metre in '{}': {}", path.display(), err))?;
+        Ok(preset)
+    }
+}
+
+/// All factory presets, sorted by category.
+pub fn factory_presets() -> Vec<Preset> {
+    let mut presets: Vec<Preset> = FACTORY_METRES.iter()
+        .map(|(category, name, rqq)| Preset::new(name, category, rqq))
+        .collect();
+
+    for (category, name, rqq_a, rqq_b) in FACTORY_PAIRS {
+        let idx = presets.iter().rposition(|preset| preset.category == *category).map_or(presets.len(), |idx| idx + 1);
+        presets.insert(idx, Preset {
+            metre_b: Some(rqq_b.to_string()),
+            ..Preset::new(name, category, rqq_a)
+        });
+    }
+
+    presets
+}
+
+/// The directory for user presets:
+/// '%APPDATA%\MetreFiddler\Presets' on Windows,
+/// '~/Library/Application Support/MetreFiddler/Presets' on macOS and
+/// '$XDG_CONFIG_HOME/metrefiddler/presets' (or '~/.config/metrefiddler/presets') elsewhere.
+pub fn user_preset_dir() -> Option<PathBuf> {
+    let env_path = |var: &str| std::env::var_os(var).filter(|path| !path.is_empty()).map(PathBuf::from);
+
+    if cfg!(target_os = "windows") {
+        env_path("APPDATA").map(|path| path.join("MetreFiddler").join("Presets"))
+    } else if cfg!(target_os = "macos") {
+        env_path("HOME").map(|path| path.join("Library/Application Support/MetreFiddler/Presets"))
+    } else {
+        env_path("XDG_CONFIG_HOME")
+            .or_else(|| env_path("HOME").map(|path| path.join(".config")))
+            .map(|path| path.join("metrefiddler").join("presets"))
+    }
+}
+
+/// Load all presets in dir, sorted by name. Files that can't be loaded are skipped.
+/// A missing directory just means there are no user presets yet.
+pub fn load_user_presets(dir: &Path) -> Vec<Preset> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+
+    let mut presets: Vec<Preset> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "json"))
+        .filter_map(|path| match Preset::load_file(&path) {
+            Ok(preset) => Some(preset),
+            Err(err_string) => {
+                nih_log!("Skipping preset: {}", err_string);
+                None
+            },
+        })
+        .collect();
+    presets.sort_by_key(|preset| preset.name.to_lowercase());
+
+    presets
+}
+
+/// Save preset as a JSON file in dir, overwriting a preset with the same name.
+/// Returns the path of the new file.
+pub fn save_user_preset(dir: &Path, preset: &Preset) -> Result<PathBuf, String> {
+    if preset.name.trim().is_empty() {
+        return Err("A preset needs a name".to_string());
+    }
+    preset.combined_metre_data()?;
+
+    fs::create_dir_all(dir)
+        .map_err(|err| format!("Failed to create '{}': {}", dir.display(), err))?;
+    let path = dir.join(preset.file_name());
+    let text = serde_json::to_string_pretty(preset)
+        .map_err(|err| format!("Failed to serialize preset: {}", err))?;
+    fs::write(&path, text)
+        .map_err(|err| format!("Failed to write '{}': {}", path.display(), err))?;
+
+    Ok(path)
+}
+
+/// The factory presets followed by the presets in the user directory.
+pub fn all_presets() -> Vec<Preset> {
+    let mut presets = factory_presets();
+    if let Some(dir) = user_preset_dir() {
+        presets.extend(load_user_presets(&dir));
+    }
+    presets
+}
@@ -0,0 +1,128 @@
+use crate::util::dry_wet;
+
+/// The most repeats a beat can be subdivided into.
+pub const MAX_RATCHET_REPEATS: usize = 16;
+
+/// The repeats a ratcheted beat is subdivided into, as offsets in samples from its start.
+/// ```
+/// use metrefiddler::ratchet::Ratchet;
+///
+/// let even = Ratchet::even(1000, 4);
+/// assert_eq!(even.offsets(), &[0, 250, 500, 750]);
+/// // A metric ratchet divides the beat like the group it belongs to, here 2 + 1 + 1.
+/// let metric = Ratchet::metric(1000, &[0.5, 0.25, 0.25]);
+/// assert_eq!(metric.offsets(), &[0, 500, 750]);
+/// assert_eq!(metric.gap(1), 250);
+/// // A positive ramp rises to the velocity of the beat, a negative one falls from it.
+/// assert_eq!(even.velocity(0, 0.8, 0.5), 0.4);
+/// assert_eq!(even.velocity(3, 0.8, 0.5), 0.8);
+/// assert_eq!(even.velocity(3, 0.8, -0.5), 0.4);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Ratchet {
+    offsets: [u64; MAX_RATCHET_REPEATS],
+    len: usize,
+    beat_samples: u64,
+}
+
+impl Ratchet {
+    /// Evenly spaced repeats.
+    pub fn even(beat_samples: u64, nr_of_repeats: usize) -> Self {
+        let len = nr_of_repeats.clamp(1, MAX_RATCHET_REPEATS);
+        let offsets = std::array::from_fn(|idx| beat_samples * idx.min(len) as u64 / len as u64);
+        Self { offsets, len, beat_samples }
+    }
+
+    /// One repeat for each of the proportions, which add up to 1.
+    pub fn metric(beat_samples: u64, proportions: &[f32]) -> Self {
+        let len = proportions.len().clamp(1, MAX_RATCHET_REPEATS);
+        let mut offsets = [0; MAX_RATCHET_REPEATS];
+        let mut start: f32 = 0.0;
+        for (offset, proportion) in offsets.iter_mut().zip(proportions) {
+            *offset = (start * beat_samples as f32).round() as u64;
+            start += proportion;
+        }
+        Self { offsets, len, beat_samples }
+    }
+
+    pub fn offsets(&self) -> &[u64] {
+        &self.offsets[..self.len]
+    }
+
+    pub fn beat_samples(&self) -> u64 {
+        self.beat_samples
+    }
+
+    /// The samples between a repeat and the next one, or the end of the beat.
+    pub fn gap(&self, idx: usize) -> u64 {
+        let next = self.offsets().get(idx + 1).copied().unwrap_or(self.beat_samples);
+        next.saturating_sub(self.offsets[idx])
+    }
+
+    /// The velocity of a repeat. The ramp (between -1.0 and 1.0) sets how much quieter its
+    /// quietest repeat is: the first one for positive ramps, the last one for negative ramps.
+    pub fn velocity(&self, idx: usize, velocity: f32, ramp: f32) -> f32 {
+        let quiet = velocity * (1.0 - ramp.abs().min(1.0));
+        let progress = if self.len > 1 { idx as f32 / (self.len - 1) as f32 } else { 0.0 };
+        if ramp >= 0.0 {
+            dry_wet(quiet, velocity, progress)
+        } else {
+            dry_wet(velocity, quiet, progress)
+        }
+    }
+}
+
+/// How many incoming notes can be ratcheted at the same time. Further notes aren't repeated.
+pub const MAX_RATCHETED_NOTES: usize = 64;
+
+/// An incoming note that is repeated for the ratchet of its beat while it is held.
+#[derive(Debug, Clone, Copy)]
+pub struct RatchetedNote {
+    pub channel: u8,
+    pub note: u8,
+    pub velocity: f32,
+    /// How many samples its repeats are moved, like the note itself.
+    pub offset: i64,
+    /// The voice id of the incoming note, which only its first repeat keeps.
+    voice_id: Option<i32>,
+    ratchet: Ratchet,
+    // Samples since the note started.
+    elapsed: u64,
+    // The repeat that is sounding.
+    repeat: usize,
+}
+
+impl RatchetedNote {
+    /// A note whose first repeat has just been sent.
+    pub fn new(channel: u8, note: u8, velocity: f32, offset: i64, voice_id: Option<i32>, ratchet: Ratchet) -> Self {
+        Self { channel, note, velocity, offset, voice_id, ratchet, elapsed: 0, repeat: 0 }
+    }
+
+    /// The next repeat, if it starts at the current sample. Call this until it returns None, then
+    /// advance() to the next sample.
+    pub fn next_repeat(&mut self) -> Option<usize> {
+        let start = self.ratchet.offsets().get(self.repeat + 1)?;
+        (*start <= self.elapsed).then(|| {
+            self.repeat += 1;
+            self.repeat
+        })
+    }
+
+    pub fn advance(&mut self) {
+        self.elapsed += 1;
+    }
+
+    /// The repeat that is sounding.
+    pub fn repeat(&self) -> usize {
+        self.repeat
+    }
+
+    /// Every repeat is its own voice, so the repeats after the first one have no voice id.
+    pub fn voice_id(&self, repeat: usize) -> Option<i32> {
+        if repeat == 0 { self.voice_id } else { None }
+    }
+
+    pub fn repeat_velocity(&self, repeat: usize, ramp: f32) -> f32 {
+        self.ratchet.velocity(repeat, self.velocity, ramp)
+    }
+}
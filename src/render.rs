@@ -0,0 +1,176 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use midly::{Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind};
+use midly::num::{u15, u24, u28, u4, u7};
+use nih_plug::prelude::*;
+use crate::headless::{HeadlessProcessor, HeadlessTransport};
+use crate::metre::combined_metre_data::CombinedMetreData;
+use crate::metre::metric_phase::MetricPhase;
+use crate::midi_clock::MidiSystemMessage;
+use crate::params::ParamsSnapShot;
+
+/// The sample rate used when rendering from the GUI, where the host's sample rate is not known.
+pub const RENDER_SAMPLE_RATE: f32 = 48000.0;
+
+/// Ticks per quarter note in exported MIDI files.
+const TICKS_PER_QUARTER: u16 = 480;
+
+/// Offline rendering happens in buffers of this size, like a host would call process().
+const BLOCK_SIZE: usize = 512;
+
+/// Render nr_of_bars bars of the pattern that Send Midi would play with the given metres and
+/// settings. The rendering runs through the same processing as the plugin itself. Returns all
+/// NoteOns and NoteOffs, together with their position in samples. Like a host, the rendering takes
+/// the reported latency off every position, so the first bar starts at 0.
+///
+/// The metric duration, bar position and interpolation are taken from the snapshot and are not
+/// smoothed. Send Midi is always on and use_position always off. When snapshot.use_bpm is set,
+/// tempo is used as the host tempo.
+pub fn render_bars(
+    combined_metre_data: CombinedMetreData,
+    snapshot: ParamsSnapShot,
+    nr_of_bars: usize,
+    tempo: f64,
+    sample_rate: f32,
+) -> Vec<(u64, NoteEvent<MidiSystemMessage>)> {
+    let mut processor = HeadlessProcessor::new(
+        combined_metre_data,
+        ParamsSnapShot {
+            send_midi: true,
+            use_position: false,
+            ..snapshot
+        },
+        sample_rate,
+    );
+    let transport = HeadlessTransport { playing: true, tempo: Some(tempo) };
+
+    let bar_samples = {
+        let mut metric_phase = MetricPhase::default();
+        metric_phase.set_metric_duration(snapshot.metric_dur, sample_rate, snapshot.use_bpm, Some(tempo), true);
+        metric_phase.metric_duration_samples()
+    };
+    let total_samples = bar_samples * nr_of_bars as u64;
+    let mut events = processor.process_samples(transport, total_samples, BLOCK_SIZE);
+
+    // Let the last notes end without starting new ones.
+    processor.snapshot_mut().send_midi = false;
+    let tail_samples = (0.1 * sample_rate).ceil() as u64 + 1;
+    events.extend(
+        processor.process_samples(transport, tail_samples, BLOCK_SIZE)
+            .into_iter()
+            .map(|(position, event)| (total_samples + position, event))
+    );
+
+    // NoteOffs are sent at the end of each buffer, after the NoteOns of that buffer.
+    events.sort_by_key(|(position, _)| *position);
+
+    let latency = processor.latency_samples() as u64;
+    events.into_iter()
+        .map(|(position, event)| (position.saturating_sub(latency), event))
+        .collect()
+}
+
+/// Write the events returned by render_bars into a single track Standard MIDI File. An existing
+/// file is never overwritten.
+pub fn write_midi_file(events: &[(u64, NoteEvent<MidiSystemMessage>)], tempo: f64, sample_rate: f32, path: &Path) -> Result<(), String> {
+    if tempo <= 0.0 || sample_rate <= 0.0 {
+        return Err(format!("Invalid tempo ({}) or sample rate ({})", tempo, sample_rate));
+    }
+
+    let ticks_per_sample = tempo / 60.0 * TICKS_PER_QUARTER as f64 / sample_rate as f64;
+    let mut track = vec![TrackEvent {
+        delta: u28::new(0),
+        kind: TrackEventKind::Meta(MetaMessage::Tempo(u24::new((60_000_000.0 / tempo).round() as u32))),
+    }];
+
+    let mut last_tick: u64 = 0;
+    for (position, event) in events {
+        let message = match *event {
+            NoteEvent::NoteOn { note, velocity, .. } => MidiMessage::NoteOn {
+                key: u7::new(note.min(127)),
+                vel: u7::new((velocity * 127.0).round().clamp(1.0, 127.0) as u8),
+            },
+            NoteEvent::NoteOff { note, .. } => MidiMessage::NoteOff {
+                key: u7::new(note.min(127)),
+                vel: u7::new(0),
+            },
+            _ => continue,
+        };
+        let channel = event.channel().unwrap_or(0);
+
+        let tick = (*position as f64 * ticks_per_sample).round() as u64;
+        track.push(TrackEvent {
+            delta: u28::new(tick.saturating_sub(last_tick) as u32),
+            kind: TrackEventKind::Midi { channel: u4::new(channel), message },
+        });
+        last_tick = last_tick.max(tick);
+    }
+
+    track.push(TrackEvent {
+        delta: u28::new(0),
+        kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+    });
+
+    let mut smf = Smf::new(Header::new(Format::SingleTrack, Timing::Metrical(u15::new(TICKS_PER_QUARTER))));
+    smf.tracks.push(track);
+    File::create_new(path)
+        .and_then(|file| smf.write_std(BufWriter::new(file)))
+        .map_err(|err| format!("Failed to write '{}': {}", path.display(), err))
+}
+
+/// Render nr_of_bars bars and write them to a new MIDI file at path.
+/// Returns the number of notes that were written.
+pub fn export_midi(
+    combined_metre_data: CombinedMetreData,
+    snapshot: ParamsSnapShot,
+    nr_of_bars: usize,
+    tempo: f64,
+    sample_rate: f32,
+    path: &Path,
+) -> Result<usize, String> {
+    let events = render_bars(combined_metre_data, snapshot, nr_of_bars, tempo, sample_rate);
+    write_midi_file(&events, tempo, sample_rate, path)?;
+
+    Ok(events.iter().filter(|(_, event)| matches!(event, NoteEvent::NoteOn { .. })).count())
+}
+
+/// Everything the editor needs to export the pattern, so that the rendering can run in the background.
+pub struct MidiExport {
+    pub combined_metre_data: CombinedMetreData,
+    pub snapshot: ParamsSnapShot,
+    pub nr_of_bars: usize,
+    pub tempo: f64,
+}
+
+/// Where the editor saves exported MIDI files: the chosen folder, or the home directory when no
+/// folder was chosen.
+pub fn export_dir(chosen: &str) -> Result<PathBuf, String> {
+    let dir = if chosen.trim().is_empty() {
+        std::env::var_os("HOME")
+            .or_else(|| std::env::var_os("USERPROFILE"))
+            .map(PathBuf::from)
+            .ok_or_else(|| String::from("No home directory found, please choose a folder for exports"))?
+    } else {
+        PathBuf::from(chosen.trim())
+    };
+
+    if dir.is_dir() {
+        Ok(dir)
+    } else {
+        Err(format!("'{}' is not a folder", dir.display()))
+    }
+}
+
+/// The first of 'metrefiddler-export-1.mid', 'metrefiddler-export-2.mid', ... that doesn't exist
+/// in dir yet.
+pub fn new_export_path(dir: &Path) -> PathBuf {
+    let mut nr: usize = 1;
+    loop {
+        let path = dir.join(format!("metrefiddler-export-{}.mid", nr));
+        if !path.exists() {
+            return path;
+        }
+        nr += 1;
+    }
+}
@@ -0,0 +1,98 @@
+use nih_plug::wrapper::state::{ParamValue, PluginState};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use crate::metre::combined_metre_data::CombinedMetreData;
+use crate::metre::metre_data::MetreData;
+
+/// The version of the persisted metres. Version 1 stored CombinedMetreData with everything that is
+/// derived from the RQQ strings, version 2 only stores the strings.
+pub const STATE_VERSION: u32 = 2;
+
+/// How CombinedMetreData is persisted. Only the RQQ strings are stored, everything else is
+/// calculated again when a state is loaded, so the derived data can change between versions.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MetreState {
+    /// The version the state was saved with.
+    pub version: u32,
+    pub metre_a: String,
+    pub metre_b: String,
+}
+
+/// Every format the metres were ever persisted in.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredMetreState {
+    Versioned { version: u32, metre_a: String, metre_b: String },
+    /// Version 1, the whole CombinedMetreData. Only the strings of the metres are used.
+    Unversioned { metre_a: UnversionedMetre, metre_b: UnversionedMetre },
+}
+
+#[derive(Deserialize)]
+struct UnversionedMetre {
+    string: String,
+}
+
+impl<'de> Deserialize<'de> for MetreState {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match StoredMetreState::deserialize(deserializer)? {
+            StoredMetreState::Versioned { version: STATE_VERSION, metre_a, metre_b } =>
+                Ok(Self { version: STATE_VERSION, metre_a, metre_b }),
+            // Version 1 was never versioned, so any other version is from a newer MetreFiddler.
+            StoredMetreState::Versioned { version, .. } =>
+                Err(de::Error::custom(format!("can't load metres saved with state version {}, the latest known version is {}", version, STATE_VERSION))),
+            StoredMetreState::Unversioned { metre_a, metre_b } =>
+                Ok(Self { version: 1, metre_a: metre_a.string, metre_b: metre_b.string }),
+        }
+    }
+}
+
+impl From<CombinedMetreData> for MetreState {
+    fn from(metre_data: CombinedMetreData) -> Self {
+        Self {
+            version: STATE_VERSION,
+            metre_a: metre_data.metre_a().string.clone(),
+            metre_b: metre_data.metre_b().string.clone(),
+        }
+    }
+}
+
+impl TryFrom<MetreState> for CombinedMetreData {
+    type Error = String;
+
+    fn try_from(state: MetreState) -> Result<Self, Self::Error> {
+        CombinedMetreData::new(
+            MetreData::try_from(state.metre_a.as_str())?,
+            MetreData::try_from(state.metre_b.as_str())?,
+        )
+    }
+}
+
+/// Bring a state saved by an older version up to date, before the host loads it. Settings that
+/// were persisted fields became parameters, and derived values are no longer persisted.
+pub fn migrate_state(state: &mut PluginState) {
+    migrate_persisted_settings(state);
+    state.fields.remove("current_nr_of_beats");
+}
+
+/// Older versions stored the settings as persisted fields instead of parameters. Move them to the
+/// parameters that replaced them, unless the state already has those.
+fn migrate_persisted_settings(state: &mut PluginState) {
+    let bool_params = ["interpolate_durations", "interpolate_indisp", "retain_metric_phase"];
+    for id in bool_params {
+        if let Some(value) = take_persisted_bool(state, id) {
+            state.params.entry(id.to_string()).or_insert(ParamValue::Bool(value));
+        }
+    }
+    if let Some(many_velocities) = take_persisted_bool(state, "many_velocities") {
+        let mode = if many_velocities { "graded" } else { "accents" };
+        state.params.entry("velocity_mode".to_string()).or_insert(ParamValue::String(mode.to_string()));
+    }
+    if let Some(one_note) = take_persisted_bool(state, "midi_out_one_note") {
+        let output = if one_note { "one_pitch" } else { "by_indispensability" };
+        state.params.entry("note_output".to_string()).or_insert(ParamValue::String(output.to_string()));
+    }
+}
+
+/// Remove a persisted field and read it as a bool, which is stored as JSON.
+fn take_persisted_bool(state: &mut PluginState, key: &str) -> Option<bool> {
+    state.fields.remove(key)?.trim().parse().ok()
+}
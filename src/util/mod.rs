@@ -1,12 +1,12 @@
 use std::fmt::Debug;
 use std::iter::Sum;
-use num_traits::{Float, Num, NumCast};
+use num_traits::{CheckedAdd, CheckedSub, Num, NumCast};
 
 ///  Given a value within an original range, return its value within a new range.
 ///
 /// # Examples
 /// ```
-/// let rescaled = metrefiddler::rescale(0.5, 0.0, 1.0, 0.0, 100.0, false).unwrap();
+/// let rescaled = metrefiddler::util::rescale(0.5, 0.0, 1.0, 0.0, 100.0, false).unwrap();
 /// 
 /// assert_eq!(rescaled, 50.0);
 /// ```
@@ -52,7 +52,7 @@ pub fn rescale<T: Num + PartialOrd + Copy + Debug>(
 /// ```
 /// let elements = vec!['a', 'b', 'c', 'd', 'e', 'f', 'g'];
 /// let weights = vec![1.0, 1.0, 2.0, 2.0, 3.0, 1.0, 2.0];
-/// let index = metrefiddler::decider(0.1, &weights).unwrap();
+/// let index = metrefiddler::util::decider(0.1, &weights).unwrap();
 /// let element = elements[index as usize];
 ///
 /// assert_eq!(element, 'b');
@@ -103,6 +103,26 @@ pub fn get_start_times<T: Num + Copy>(durations: &[T]) -> Vec<T> {
     result
 }
 
+/// Like get_start_times, but None if a start time overflows.
+pub fn get_checked_start_times<T: Num + CheckedAdd + Copy>(durations: &[T]) -> Option<Vec<T>> {
+    let mut time = T::zero();
+    let mut result = Vec::with_capacity(durations.len());
+
+    for dur in durations {
+        result.push(time);
+        time = time.checked_add(dur)?;
+    }
+
+    Some(result)
+}
+
+/// Like get_durations, but None if a duration overflows.
+pub fn get_checked_durations<T: Num + CheckedSub + Copy>(start_times: &[T]) -> Option<Vec<T>> {
+    start_times.windows(2)
+        .map(|pair| pair[1].checked_sub(&pair[0]))
+        .collect()
+}
+
 pub fn get_durations<T: Num + Copy>(start_times: &[T]) -> impl Iterator<Item = T> + use<'_, T> {
     let mut last = start_times[0];
     start_times[1..]
@@ -113,7 +133,3 @@ pub fn get_durations<T: Num + Copy>(start_times: &[T]) -> impl Iterator<Item = T
             dur
         })
 }
-
-pub fn approx_eq<T: Float>(a: T, b: T, epsilon: T) -> bool {
-    (a - b).abs() <= epsilon
-}
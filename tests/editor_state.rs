@@ -0,0 +1,20 @@
+use std::collections::BTreeMap;
+use nih_plug::prelude::Params;
+use metrefiddler::params::MetreFiddlerParams;
+
+#[test]
+fn the_editor_size_is_restored() {
+    let saved = MetreFiddlerParams::default();
+    assert_eq!(saved.editor_state.user_scale_factor(), 1.0);
+
+    // The state as saved after choosing 150% in the settings or dragging the resize handle.
+    saved.deserialize_fields(&BTreeMap::from([
+        (String::from("editor-state"), String::from(r#"{"scale_factor":1.5}"#)),
+    ]));
+    assert_eq!(saved.editor_state.user_scale_factor(), 1.5);
+
+    let restored = MetreFiddlerParams::default();
+    restored.deserialize_fields(&saved.serialize_fields());
+    assert_eq!(restored.editor_state.user_scale_factor(), 1.5);
+    assert_eq!(restored.editor_state.inner_logical_size(), saved.editor_state.inner_logical_size());
+}
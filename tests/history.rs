@@ -0,0 +1,62 @@
+use metrefiddler::history::{Edit, EditHistory, SettingToggle, MAX_HISTORY_LEN};
+
+fn metre_a(old: &str, new: &str) -> Edit {
+    Edit::MetreA { old: old.to_string(), new: new.to_string() }
+}
+
+fn switched_on(toggle: SettingToggle) -> Edit {
+    Edit::Setting { toggle, old: false, new: true }
+}
+
+#[test]
+fn undo_and_redo() {
+    let mut history = EditHistory::default();
+    assert!(!history.can_undo() && !history.can_redo());
+    assert_eq!(history.undo(), None);
+
+    history.push(metre_a("(1 (1))", "(2 (1 1))"));
+    history.push(switched_on(SettingToggle::ManyVelocities));
+    history.push(Edit::SwitchAB);
+
+    assert_eq!(history.undo(), Some(Edit::SwitchAB));
+    assert_eq!(history.undo(), Some(switched_on(SettingToggle::ManyVelocities)));
+    assert!(history.can_redo());
+    assert_eq!(history.redo(), Some(switched_on(SettingToggle::ManyVelocities)));
+    assert_eq!(history.undo(), Some(switched_on(SettingToggle::ManyVelocities)));
+    assert_eq!(history.undo(), Some(metre_a("(1 (1))", "(2 (1 1))")));
+    assert!(!history.can_undo());
+
+    // A new edit discards the redo stack.
+    history.push(metre_a("(1 (1))", "(3 (1 1 1))"));
+    assert!(!history.can_redo());
+    assert_eq!(history.redo(), None);
+    assert_eq!(history.undo(), Some(metre_a("(1 (1))", "(3 (1 1 1))")));
+}
+
+#[test]
+fn history_is_limited() {
+    let mut history = EditHistory::default();
+    for idx in 0..MAX_HISTORY_LEN + 10 {
+        history.push(metre_a(&idx.to_string(), &(idx + 1).to_string()));
+    }
+
+    let mut undone = Vec::new();
+    while let Some(edit) = history.undo() {
+        undone.push(edit);
+    }
+    assert_eq!(undone.len(), MAX_HISTORY_LEN);
+    assert_eq!(undone.last(), Some(&metre_a("10", "11")));
+}
+
+#[test]
+fn history_survives_saving() {
+    let mut history = EditHistory::default();
+    history.push(metre_a("(1 (1))", "(2 (1 1))"));
+    history.push(switched_on(SettingToggle::RetainMetricPhase));
+    history.undo();
+
+    let json = serde_json::to_string(&history).unwrap();
+    let mut restored: EditHistory = serde_json::from_str(&json).unwrap();
+    assert_eq!(restored, history);
+    assert_eq!(restored.redo(), Some(switched_on(SettingToggle::RetainMetricPhase)));
+}
@@ -0,0 +1,124 @@
+use midly::{Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind};
+use midly::num::{u15, u28, u4, u7};
+use metrefiddler::import::{import_midi, ImportOptions};
+
+const TICKS_PER_QUARTER: u16 = 480;
+const SIXTEENTH: u64 = TICKS_PER_QUARTER as u64 / 4;
+
+/// A single track MIDI file with NoteOns at (tick, velocity), ending at end.
+fn midi_file(time_signature: Option<(u8, u8)>, notes: &[(u64, u8)], end: u64) -> Vec<u8> {
+    let mut track = Vec::new();
+    if let Some((numerator, denominator_pow)) = time_signature {
+        track.push(TrackEvent {
+            delta: u28::new(0),
+            kind: TrackEventKind::Meta(MetaMessage::TimeSignature(numerator, denominator_pow, 24, 8)),
+        });
+    }
+
+    let mut last_tick = 0;
+    for &(tick, velocity) in notes {
+        track.push(TrackEvent {
+            delta: u28::new((tick - last_tick) as u32),
+            kind: TrackEventKind::Midi {
+                channel: u4::new(0),
+                message: MidiMessage::NoteOn { key: u7::new(60), vel: u7::new(velocity) },
+            },
+        });
+        last_tick = tick;
+    }
+    track.push(TrackEvent {
+        delta: u28::new((end - last_tick) as u32),
+        kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+    });
+
+    let mut smf = Smf::new(Header::new(Format::SingleTrack, Timing::Metrical(u15::new(TICKS_PER_QUARTER))));
+    smf.tracks.push(track);
+    let mut bytes = Vec::new();
+    smf.write_std(&mut bytes).unwrap();
+    bytes
+}
+
+fn import_rqq(bytes: &[u8], options: ImportOptions) -> String {
+    import_midi(bytes, &options).unwrap().string
+}
+
+#[test]
+fn straight_eighths_in_four_four() {
+    let notes: Vec<(u64, u8)> = (0..8).map(|idx| (idx * 2 * SIXTEENTH, 100)).collect();
+    let bytes = midi_file(None, &notes, 16 * SIXTEENTH);
+
+    assert_eq!(import_rqq(&bytes, ImportOptions::default()), "(8 ((2 (1 1)) (2 (1 1)) (2 (1 1)) (2 (1 1))))");
+}
+
+#[test]
+fn compound_time_signature() {
+    let notes: Vec<(u64, u8)> = (0..6).map(|idx| (idx * 2 * SIXTEENTH, 100)).collect();
+    let bytes = midi_file(Some((6, 3)), &notes, 12 * SIXTEENTH);
+
+    assert_eq!(import_rqq(&bytes, ImportOptions::default()), "(6 ((3 (1 1 1)) (3 (1 1 1))))");
+}
+
+#[test]
+fn quantized_son_clave() {
+    // Slightly early and late strokes of a son clave
+    let notes = [(0, 100), (3 * SIXTEENTH + 7, 100), (6 * SIXTEENTH - 11, 100), (10 * SIXTEENTH + 3, 100), (12 * SIXTEENTH - 5, 100)];
+    let bytes = midi_file(None, &notes, 16 * SIXTEENTH);
+
+    let options = ImportOptions { grid: Some(4), ..ImportOptions::default() };
+    assert_eq!(import_rqq(&bytes, options), "(16 ((12 (3 3 4 2)) 4))");
+
+    let durations = import_midi(&bytes, &options).unwrap().durations;
+    assert_eq!(durations, vec![3.0 / 16.0, 3.0 / 16.0, 4.0 / 16.0, 2.0 / 16.0, 4.0 / 16.0]);
+}
+
+#[test]
+fn tolerance_merges_flams() {
+    let notes = [(0, 100), (10, 60), (4 * SIXTEENTH, 100), (4 * SIXTEENTH + 20, 100), (8 * SIXTEENTH, 100), (12 * SIXTEENTH, 100)];
+    let bytes = midi_file(None, &notes, 16 * SIXTEENTH);
+
+    let options = ImportOptions { tolerance: 0.1, ..ImportOptions::default() };
+    assert_eq!(import_rqq(&bytes, options), "(4 (1 1 1 1))");
+    // Without tolerance, every onset is a beat.
+    assert_eq!(import_midi(&bytes, &ImportOptions::default()).unwrap().durations.len(), 6);
+}
+
+#[test]
+fn velocities_as_weights() {
+    let notes = [(0, 100), (4 * SIXTEENTH, 40), (8 * SIXTEENTH, 80), (12 * SIXTEENTH, 40)];
+    let bytes = midi_file(None, &notes, 16 * SIXTEENTH);
+
+    let options = ImportOptions { velocities_as_weights: true, ..ImportOptions::default() };
+    assert_eq!(import_rqq(&bytes, options), "(4 ((2 (1 1)) (2 (1 1))))");
+
+    // A late accent starts a group too.
+    let notes = [(0, 100), (4 * SIXTEENTH, 40), (8 * SIXTEENTH, 40), (12 * SIXTEENTH, 90)];
+    let bytes = midi_file(None, &notes, 16 * SIXTEENTH);
+    assert_eq!(import_rqq(&bytes, options), "(4 ((3 (1 1 1)) 1))");
+}
+
+#[test]
+fn single_bars_and_whole_files() {
+    // Bar 0: two half notes, bar 1: a dotted quarter, a dotted quarter and a quarter
+    let notes = [(0, 100), (8 * SIXTEENTH, 100), (16 * SIXTEENTH, 100), (22 * SIXTEENTH, 100), (28 * SIXTEENTH, 100)];
+    let bytes = midi_file(None, &notes, 32 * SIXTEENTH);
+
+    let bar = |bar| ImportOptions { bar: Some(bar), ..ImportOptions::default() };
+    assert_eq!(import_rqq(&bytes, bar(0)), "(2 (1 1))");
+    assert_eq!(import_rqq(&bytes, bar(1)), "(8 ((6 (3 3)) 2))");
+    assert!(import_midi(&bytes, &bar(2)).is_err());
+
+    // The bar line starts the largest groups.
+    assert_eq!(import_rqq(&bytes, ImportOptions::default()), "(16 ((8 (4 4)) (8 ((6 (3 3)) 2))))");
+}
+
+#[test]
+fn first_beat_starts_at_the_bar() {
+    let bytes = midi_file(None, &[(4 * SIXTEENTH, 100)], 16 * SIXTEENTH);
+    assert_eq!(import_rqq(&bytes, ImportOptions::default()), "(4 (1 3))");
+}
+
+#[test]
+fn invalid_files() {
+    assert!(import_midi(b"not a midi file", &ImportOptions::default()).is_err());
+    assert!(import_midi(&midi_file(None, &[], 16 * SIXTEENTH), &ImportOptions::default()).is_err());
+}
@@ -0,0 +1,115 @@
+use metrefiddler::metre::beat_origin::BeatOrigin;
+use metrefiddler::metre::combined_metre_data::CombinedMetreData;
+use metrefiddler::metre::metre_data::MetreData;
+
+/// A bar of 7 beats, divided into `levels` levels of 3 beats each.
+fn nested(levels: usize) -> String {
+    let mut rqq = "1".to_string();
+    for _ in 0..levels {
+        rqq = format!("(1 ({}))", [rqq.as_str(); 3].join(" "));
+    }
+    format!("(7 ({}))", [rqq.as_str(); 7].join(" "))
+}
+
+#[test]
+fn deeply_nested_metres_are_paired_by_start_time() {
+    // 7 * 3^6 = 5103 beats, each beat of B starts a group of 729 in A.
+    let a = MetreData::try_from(nested(6).as_str()).unwrap();
+    let b = MetreData::try_from("(7 (1 1 1 1 1 1 1))").unwrap();
+    let combined = CombinedMetreData::new(a, b).unwrap();
+    let data = combined.interpolation_data();
+
+    assert_eq!(data.unique_start_times().len(), 5103 + 1);
+    for (i, origin) in data.unique_start_time_origins().iter().enumerate() {
+        assert_eq!(*origin == BeatOrigin::Both, i % 729 == 0);
+    }
+    let pairs = data.duration_pairs();
+    assert_eq!(pairs.len(), 5103);
+    for (i, (_, dur_b)) in pairs.iter().enumerate() {
+        assert_eq!(*dur_b > 0.0, i % 729 == 0);
+    }
+
+    // The other way around, the same beats are paired.
+    let switched = CombinedMetreData::new(
+        MetreData::try_from("(7 (1 1 1 1 1 1 1))").unwrap(),
+        MetreData::try_from(nested(6).as_str()).unwrap(),
+    ).unwrap();
+    for (i, (dur_a, _)) in switched.interpolation_data().duration_pairs().iter().enumerate() {
+        assert_eq!(*dur_a > 0.0, i % 729 == 0);
+    }
+}
+
+#[test]
+fn groups_of_thirteen_thirteens_are_paired_by_start_time() {
+    // A bar of 7 beats, each divided into 13 and again into 13 (1183 beats), against 7 beats.
+    let thirteen = format!("(1 ({}))", ["1"; 13].join(" "));
+    let thirteen_thirteens = format!("(1 ({}))", [thirteen.as_str(); 13].join(" "));
+    let a = MetreData::try_from(format!("(7 ({}))", [thirteen_thirteens.as_str(); 7].join(" ")).as_str()).unwrap();
+    let b = MetreData::try_from("(7 (1 1 1 1 1 1 1))").unwrap();
+    let combined = CombinedMetreData::new(a, b).unwrap();
+    let data = combined.interpolation_data();
+
+    // Every start time of B coincides with one of A, the 7th one is the end of the bar.
+    assert_eq!(data.unique_start_times().len(), 1183 + 1);
+    for (i, origin) in data.unique_start_time_origins().iter().enumerate() {
+        assert_eq!(*origin == BeatOrigin::Both, i % 169 == 0);
+    }
+    // Each beat of B is paired with the first beat of a group of 169 in A.
+    let pairs = data.duration_pairs();
+    assert_eq!(pairs.len(), 1183);
+    for (i, (_, dur_b)) in pairs.iter().enumerate() {
+        assert_eq!(*dur_b > 0.0, i % 169 == 0);
+    }
+}
+
+/// Tuplets of each of the primes, each nested in the first beat of the one before.
+fn nested_primes(primes: &[usize]) -> String {
+    let mut rqq = "1".to_string();
+    for &n in primes.iter().rev() {
+        rqq = format!("(1 ({} {}))", rqq, vec!["1"; n - 1].join(" "));
+    }
+    rqq
+}
+
+#[test]
+fn start_times_that_overflow_are_an_error() {
+    // Tuplets of every prime up to 43, ending in a 47-tuplet in A and a 53-tuplet in B. The
+    // start times of their smallest beats have no common denominator within i64.
+    let primes = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43];
+    let a = MetreData::try_from(nested_primes(&[&primes[..], &[47]].concat()).as_str()).unwrap();
+    let b = MetreData::try_from(nested_primes(&[&primes[..], &[53]].concat()).as_str()).unwrap();
+
+    assert!(CombinedMetreData::new(a.clone(), b.clone()).is_err());
+    assert!(CombinedMetreData::new(b, a).is_err());
+}
+
+#[test]
+fn tuplets_of_many_primes_are_paired_with_a_53_tuplet() {
+    let primes = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43];
+    let groups: Vec<String> = primes.iter().map(|&n| format!("(1 ({}))", vec!["1"; n].join(" "))).collect();
+    let a = MetreData::try_from(format!("({} ({}))", primes.len(), groups.join(" ")).as_str()).unwrap();
+    let b = MetreData::try_from(format!("(53 ({}))", vec!["1"; 53].join(" ")).as_str()).unwrap();
+    let combined = CombinedMetreData::new(a, b).unwrap();
+
+    // Every beat of both metres is part of exactly one pair.
+    let pairs = combined.interpolation_data().duration_pairs();
+    assert_eq!(pairs.iter().filter(|(dur_a, _)| *dur_a > 0.0).count(), primes.iter().sum::<usize>());
+    assert_eq!(pairs.iter().filter(|(_, dur_b)| *dur_b > 0.0).count(), 53);
+}
+
+#[test]
+fn strata_of_interpolated_durations_follow_the_paired_beats() {
+    // The half bar of A, its third beat, is paired with the half bar of B, its fourth beat. The
+    // third and sixth beat of B have no partner in A.
+    let a = MetreData::try_from("(4 ((2 (1 1)) (2 (1 1))))").unwrap();
+    let b = MetreData::try_from("(6 ((3 (1 1 1)) (3 (1 1 1))))").unwrap();
+    let combined = CombinedMetreData::new(a, b).unwrap();
+    let data = combined.interpolation_data();
+    assert_eq!(data.get_interpolated_beat_indices(2, 0.5), (None, Some(2)));
+    assert_eq!(data.get_interpolated_beat_indices(3, 0.5), (Some(2), Some(3)));
+
+    let strata = |interpolate| (0..6).map(|idx| combined.get_gnsm_at(idx, interpolate, true)).collect::<Vec<_>>();
+    assert_eq!(strata(0.0), vec![2, 0, 1, 0, 0, 0]);
+    assert_eq!(strata(0.5), vec![2, 0, 0, 1, 0, 0]);
+    assert_eq!(strata(1.0), vec![2, 0, 0, 1, 0, 0]);
+}
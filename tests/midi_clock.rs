@@ -0,0 +1,35 @@
+use nih_plug::prelude::SysExMessage;
+use metrefiddler::midi_clock::MidiSystemMessage;
+
+#[test]
+fn song_positions_round_trip() {
+    for position in 0..1 << 14 {
+        let message = MidiSystemMessage::SongPosition(position);
+        let (buffer, len) = message.to_buffer();
+        assert_eq!(len, 3);
+        assert_eq!(buffer[0], 0xF2);
+        assert!(buffer[1] < 0x80 && buffer[2] < 0x80);
+        assert_eq!(MidiSystemMessage::from_buffer(&buffer[..len]), Some(message));
+    }
+}
+
+#[test]
+fn real_time_messages_round_trip() {
+    for (message, status) in [
+        (MidiSystemMessage::Clock, 0xF8),
+        (MidiSystemMessage::Start, 0xFA),
+        (MidiSystemMessage::Continue, 0xFB),
+        (MidiSystemMessage::Stop, 0xFC),
+    ] {
+        let (buffer, len) = message.to_buffer();
+        assert_eq!(&buffer[..len], &[status]);
+        assert_eq!(MidiSystemMessage::from_buffer(&buffer[..len]), Some(message));
+    }
+}
+
+#[test]
+fn other_system_messages_are_ignored() {
+    assert_eq!(MidiSystemMessage::from_buffer(&[0xF0, 0x7E, 0xF7]), None);
+    assert_eq!(MidiSystemMessage::from_buffer(&[0xF2, 0x10]), None);
+    assert_eq!(MidiSystemMessage::from_buffer(&[]), None);
+}
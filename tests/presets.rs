@@ -0,0 +1,111 @@
+use std::fs;
+use std::path::PathBuf;
+use metrefiddler::metre::combined_metre_data::CombinedMetreData;
+use metrefiddler::params::ParamsSnapShot;
+use metrefiddler::preset::{factory_presets, load_user_presets, save_user_preset, Preset, PresetSettings, PresetTarget};
+
+fn temp_preset_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("metrefiddler-presets-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+#[test]
+fn factory_presets_are_valid() {
+    let presets = factory_presets();
+    assert!(presets.len() > 30);
+
+    for category in ["Time Signature", "Aksak", "Tala", "Clave", "Barlow"] {
+        assert!(presets.iter().any(|preset| preset.category == category), "no presets for {}", category);
+    }
+    for preset in &presets {
+        assert!(preset.combined_metre_data().is_ok(), "invalid factory preset '{}'", preset.name);
+    }
+
+    // Presets of the same category are next to each other.
+    let mut categories: Vec<&str> = presets.iter().map(|preset| preset.category.as_str()).collect();
+    categories.dedup();
+    assert_eq!(categories.len(), 5);
+}
+
+#[test]
+fn clave_beats_start_at_strokes() {
+    let son_clave = factory_presets().into_iter().find(|preset| preset.name == "Son Clave 3-2").unwrap();
+    let starts: Vec<f32> = son_clave.metre_a_data().unwrap().durations.iter()
+        .scan(0.0, |start, duration| {
+            let beat_start = *start;
+            *start += duration;
+            Some(beat_start * 16.0)
+        })
+        .collect();
+
+    for (start, expected) in starts.iter().zip([0.0, 3.0, 6.0, 10.0, 12.0]) {
+        assert!((start - expected).abs() < 1e-4);
+    }
+}
+
+#[test]
+fn loading_presets() {
+    let mut combined = CombinedMetreData::default();
+    let mut snapshot = ParamsSnapShot::default();
+    let settings = PresetSettings {
+        upper_threshold: 0.5,
+        velocity_max: 100.0,
+        many_velocities: false,
+        ..PresetSettings::from_snapshot(&snapshot)
+    };
+    let preset = Preset {
+        metre_b: Some("(3 (1 1 1))".to_string()),
+        settings: Some(settings),
+        ..Preset::new("test", "Test", "(2 (1 1))")
+    };
+
+    preset.apply(PresetTarget::MetreB, &mut combined, &mut snapshot).unwrap();
+    assert_eq!(combined.metre_b().string, "(3 (1 1 1))");
+    assert_eq!(combined.metre_a().string, CombinedMetreData::default().metre_a().string);
+    assert_eq!(snapshot.upper_threshold, 1.0);
+
+    preset.apply(PresetTarget::MetreA, &mut combined, &mut snapshot).unwrap();
+    assert_eq!(combined.metre_a().string, "(2 (1 1))");
+    assert_eq!(snapshot.upper_threshold, 1.0);
+
+    combined = CombinedMetreData::default();
+    preset.apply(PresetTarget::Everything, &mut combined, &mut snapshot).unwrap();
+    assert_eq!(combined.metre_a().string, "(2 (1 1))");
+    assert_eq!(combined.metre_b().string, "(3 (1 1 1))");
+    assert_eq!(PresetSettings::from_snapshot(&snapshot), settings);
+
+    // An invalid metre changes nothing.
+    let invalid = Preset::new("invalid", "Test", "(1 (");
+    assert!(invalid.apply(PresetTarget::Everything, &mut combined, &mut snapshot).is_err());
+    assert!(invalid.apply(PresetTarget::MetreB, &mut combined, &mut snapshot).is_err());
+    assert_eq!(combined.metre_b().string, "(3 (1 1 1))");
+}
+
+#[test]
+fn user_presets_round_trip() {
+    let dir = temp_preset_dir("round-trip");
+    assert!(load_user_presets(&dir).is_empty());
+
+    let preset = Preset {
+        settings: Some(PresetSettings::from_snapshot(&ParamsSnapShot::default())),
+        ..Preset::new("my 7/8", "User", "(7 ((2 (1 1)) (2 (1 1)) (3 (1 1 1))))")
+    };
+    let path = save_user_preset(&dir, &preset).unwrap();
+    assert_eq!(path, dir.join("my 7_8.json"));
+    save_user_preset(&dir, &Preset::new("another", "User", "(3 (1 1 1))")).unwrap();
+
+    // Broken files are skipped.
+    fs::write(dir.join("broken.json"), "{ not json").unwrap();
+    fs::write(dir.join("invalid metre.json"), r#"{"name": "x", "category": "User", "metre_a": "(1 ("}"#).unwrap();
+
+    let loaded = load_user_presets(&dir);
+    assert_eq!(loaded.len(), 2);
+    assert_eq!(loaded[0].name, "another");
+    assert_eq!(loaded[1], preset);
+
+    assert!(save_user_preset(&dir, &Preset::new("  ", "User", "(3 (1 1 1))")).is_err());
+    assert!(save_user_preset(&dir, &Preset::new("bad", "User", "(1 (")).is_err());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,865 @@
+use midly::{MidiMessage, Smf, TrackEventKind};
+use nih_plug::prelude::NoteEvent;
+use metrefiddler::headless::{HeadlessProcessor, HeadlessTransport};
+use metrefiddler::metre::combined_metre_data::CombinedMetreData;
+use metrefiddler::metre::metre_data::MetreData;
+use metrefiddler::metre::metric_phase::MetricPhase;
+use metrefiddler::micro_timing::DELAYED_EVENTS_CAPACITY;
+use metrefiddler::midi_clock::MidiSystemMessage;
+use metrefiddler::chord::Chord;
+use metrefiddler::controller_scaling::CcSelection;
+use metrefiddler::params::{BarMarker, ChordMode, ParamsSnapShot, RatchetMode, ThresholdMode, TransposeMode};
+use metrefiddler::pitch_transform::TransposeTable;
+use metrefiddler::render::{export_dir, export_midi, new_export_path, render_bars};
+
+// With a sample rate of 1000 and a metric duration of 1 second, a bar is 1000 samples long and
+// the beats of FOUR_FOUR start at 0, 250, 500 and 750.
+const SAMPLE_RATE: f32 = 1000.0;
+// Indispensability values: 0, 3, 1, 2
+const FOUR_FOUR: &str = "(4 ((2 (1 1)) (2 (1 1))))";
+
+const PLAYING: HeadlessTransport = HeadlessTransport { playing: true, tempo: Some(120.0) };
+const STOPPED: HeadlessTransport = HeadlessTransport { playing: false, tempo: Some(120.0) };
+
+fn send_midi_snapshot() -> ParamsSnapShot {
+    ParamsSnapShot {
+        vel_max: 127.0,
+        send_midi: true,
+        ..ParamsSnapShot::default()
+    }
+}
+
+fn new_processor(rqq: &str, snapshot: ParamsSnapShot) -> HeadlessProcessor {
+    let metre = MetreData::try_from(rqq).unwrap();
+    HeadlessProcessor::new(CombinedMetreData::new(metre.clone(), metre).unwrap(), snapshot, SAMPLE_RATE)
+}
+
+const SIX_EIGHT: &str = "(6 ((3 (1 1 1)) (3 (1 1 1))))";
+
+/// Halfway between FOUR_FOUR and SIX_EIGHT with interpolated durations. The half bars are paired,
+/// so the beats start at 0, 208, 417, 500, 708 and 917 and have the strata 2, 0, 0, 1, 0, 0.
+fn halfway_processor(snapshot: ParamsSnapShot) -> HeadlessProcessor {
+    let metres = CombinedMetreData::new(MetreData::try_from(FOUR_FOUR).unwrap(), MetreData::try_from(SIX_EIGHT).unwrap()).unwrap();
+    HeadlessProcessor::new(metres, ParamsSnapShot { interpolate: 0.5, interpolate_durs: true, ..snapshot }, SAMPLE_RATE)
+}
+
+fn note_on(timing: u32, note: u8) -> NoteEvent<MidiSystemMessage> {
+    NoteEvent::NoteOn { timing, voice_id: None, channel: 0, note, velocity: 0.8 }
+}
+
+/// (position, note, velocity) of all NoteOns
+fn note_ons(events: &[(u64, NoteEvent<MidiSystemMessage>)]) -> Vec<(u64, u8, f32)> {
+    events.iter()
+        .filter_map(|(position, event)| match *event {
+            NoteEvent::NoteOn { note, velocity, .. } => Some((*position, note, velocity)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// (position, note) of all NoteOffs
+fn note_offs(events: &[(u64, NoteEvent<MidiSystemMessage>)]) -> Vec<(u64, u8)> {
+    events.iter()
+        .filter_map(|(position, event)| match *event {
+            NoteEvent::NoteOff { note, .. } => Some((*position, note)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn assert_velocity(actual: f32, expected: f32) {
+    assert!((actual - expected).abs() < 1e-4, "velocity {} != {}", actual, expected);
+}
+
+#[test]
+fn send_midi_onsets_at_beat_starts() {
+    let mut processor = new_processor(FOUR_FOUR, send_midi_snapshot());
+    let events = processor.process_samples(PLAYING, 2000, 64);
+
+    let onsets: Vec<(u64, u8)> = note_ons(&events).iter().map(|&(position, note, _)| (position, note)).collect();
+    assert_eq!(onsets, vec![
+        (0, 60), (250, 63), (500, 61), (750, 62),
+        (1000, 60), (1250, 63), (1500, 61), (1750, 62),
+    ]);
+}
+
+#[test]
+fn positions_on_a_beat_boundary_belong_to_the_beat_starting_there() {
+    let metre = MetreData::try_from(FOUR_FOUR).unwrap();
+    let combined = CombinedMetreData::new(metre.clone(), metre).unwrap();
+    for interpolate_durs in [false, true] {
+        for (beat_idx, start) in [0.0, 0.25, 0.5, 0.75].into_iter().enumerate() {
+            let (idx, duration_sum, ..) = combined.get_indisp_data_at(start, 0.0, interpolate_durs, false);
+            assert_eq!((idx, duration_sum), (beat_idx, start));
+            if beat_idx > 0 {
+                let (idx, ..) = combined.get_indisp_data_at(start - 0.001, 0.0, interpolate_durs, false);
+                assert_eq!(idx, beat_idx - 1);
+            }
+        }
+    }
+}
+
+#[test]
+fn reset_phase_is_at_the_start_of_the_bar() {
+    let mut metric_phase = MetricPhase::default();
+    metric_phase.set_metric_duration(1.0, SAMPLE_RATE, false, None, true);
+    for _ in 0..300 {
+        metric_phase.increment();
+    }
+    assert_eq!(metric_phase.metric_phase(), 0.3);
+
+    // The phase follows right away, not only after the next sample.
+    metric_phase.reset();
+    assert_eq!(metric_phase.metric_phase(), 0.0);
+}
+
+#[test]
+fn send_midi_pitches() {
+    let mut snapshot = send_midi_snapshot();
+    snapshot.output_one_pitch = true;
+    let mut processor = new_processor(FOUR_FOUR, snapshot);
+    let events = processor.process_samples(PLAYING, 1000, 64);
+    assert!(note_ons(&events).iter().all(|&(_, note, _)| note == 60));
+
+    let mut snapshot = send_midi_snapshot();
+    snapshot.many_velocities = false;
+    snapshot.vel_skew = 0.5;
+    let mut processor = new_processor(FOUR_FOUR, snapshot);
+    let events = processor.process_samples(PLAYING, 1000, 64);
+    let notes: Vec<u8> = note_ons(&events).iter().map(|&(_, note, _)| note).collect();
+    // The two beats with the lowest indispensability values get the higher velocity and pitch.
+    assert_eq!(notes, vec![61, 60, 61, 60]);
+}
+
+#[test]
+fn send_midi_plays_chords_by_importance() {
+    let mut snapshot = send_midi_snapshot();
+    snapshot.chord_mode = ChordMode::Chords;
+    snapshot.chord = Chord::try_from("0 4 7").unwrap();
+    let mut processor = new_processor(FOUR_FOUR, snapshot);
+    let events = processor.process_samples(PLAYING, 1000, 64);
+
+    let onsets: Vec<(u64, u8)> = note_ons(&events).iter().map(|&(position, note, _)| (position, note)).collect();
+    assert_eq!(onsets, vec![
+        (0, 36), (0, 48), (0, 52), (0, 55),
+        (250, 55),
+        (500, 52), (500, 55),
+        (750, 52), (750, 55),
+    ]);
+    assert_eq!(note_offs(&events).len(), 9);
+
+    snapshot.chord_mode = ChordMode::Arpeggio;
+    let mut processor = new_processor(FOUR_FOUR, snapshot);
+    let events = processor.process_samples(PLAYING, 1000, 64);
+    let notes: Vec<u8> = note_ons(&events).iter().map(|&(_, note, _)| note).collect();
+    assert_eq!(notes, vec![36, 55, 48, 52]);
+}
+
+#[test]
+fn send_midi_respects_thresholds() {
+    let mut snapshot = send_midi_snapshot();
+    snapshot.upper_threshold = 0.5;
+    let mut processor = new_processor(FOUR_FOUR, snapshot);
+    let events = processor.process_samples(PLAYING, 1000, 64);
+    let onsets: Vec<u64> = note_ons(&events).iter().map(|&(position, _, _)| position).collect();
+    assert_eq!(onsets, vec![0, 500]);
+
+    let mut snapshot = send_midi_snapshot();
+    snapshot.lower_threshold = 0.7;
+    let mut processor = new_processor(FOUR_FOUR, snapshot);
+    let events = processor.process_samples(PLAYING, 1000, 64);
+    let onsets: Vec<u64> = note_ons(&events).iter().map(|&(position, _, _)| position).collect();
+    assert_eq!(onsets, vec![250, 750]);
+}
+
+#[test]
+fn threshold_modes_select_beats() {
+    // FOUR_FOUR has the strata 2, 0, 1, 0
+    let cases = [
+        (ThresholdMode::TopN, 0.0, 0.5, vec![0, 500]),
+        (ThresholdMode::Stratum, 1.0, 1.0, vec![0]),
+        (ThresholdMode::Stratum, 0.5, 1.0, vec![0, 500]),
+        (ThresholdMode::Inverted, 0.34, 0.67, vec![0, 250]),
+        (ThresholdMode::Density, 0.0, 0.75, vec![0, 500, 750]),
+    ];
+    for (threshold_mode, lower_threshold, upper_threshold, expected) in cases {
+        let snapshot = ParamsSnapShot { threshold_mode, lower_threshold, upper_threshold, ..send_midi_snapshot() };
+        let mut processor = new_processor(FOUR_FOUR, snapshot);
+        let events = processor.process_samples(PLAYING, 1000, 64);
+        let onsets: Vec<u64> = note_ons(&events).iter().map(|&(position, _, _)| position).collect();
+        assert_eq!(onsets, expected, "{:?}", threshold_mode);
+    }
+}
+
+#[test]
+fn stratum_thresholds_follow_interpolated_metres() {
+    let snapshot = ParamsSnapShot {
+        threshold_mode: ThresholdMode::Stratum,
+        lower_threshold: 0.5,
+        upper_threshold: 1.0,
+        ..send_midi_snapshot()
+    };
+    let mut processor = halfway_processor(snapshot);
+    let events = processor.process_samples(PLAYING, 1000, 64);
+    let onsets: Vec<u64> = note_ons(&events).iter().map(|&(position, _, _)| position).collect();
+    assert_eq!(onsets, vec![0, 500]);
+}
+
+#[test]
+fn send_midi_velocities() {
+    let mut processor = new_processor(FOUR_FOUR, send_midi_snapshot());
+    let events = processor.process_samples(PLAYING, 1000, 64);
+    let velocities: Vec<f32> = note_ons(&events).iter().map(|&(_, _, velocity)| velocity).collect();
+
+    assert_eq!(velocities.len(), 4);
+    for (velocity, indisp) in velocities.iter().zip([0, 3, 1, 2]) {
+        assert_velocity(*velocity, 1.0 / (indisp + 1) as f32);
+    }
+
+    // A velocity range of 0 to 63.5 halves all velocities.
+    let mut snapshot = send_midi_snapshot();
+    snapshot.vel_max = 63.5;
+    let mut processor = new_processor(FOUR_FOUR, snapshot);
+    let events = processor.process_samples(PLAYING, 1000, 64);
+    assert_velocity(note_ons(&events)[0].2, 0.5);
+    assert_velocity(note_ons(&events)[2].2, 0.25);
+}
+
+#[test]
+fn incoming_notes_are_filtered_and_get_velocities() {
+    let mut snapshot = send_midi_snapshot();
+    snapshot.send_midi = false;
+    snapshot.upper_threshold = 0.5;
+    let mut processor = new_processor(FOUR_FOUR, snapshot);
+
+    let incoming = [note_on(0, 40), note_on(250, 41), note_on(500, 42), note_on(750, 43)];
+    let events = processor.process(PLAYING, 1000, incoming);
+    let notes: Vec<(u32, u8, f32)> = events.iter()
+        .filter_map(|event| match *event {
+            NoteEvent::NoteOn { timing, note, velocity, .. } => Some((timing, note, velocity)),
+            _ => None,
+        })
+        .collect();
+
+    assert_eq!(notes.len(), 2);
+    assert_eq!((notes[0].0, notes[0].1), (0, 40));
+    assert_velocity(notes[0].2, 1.0);
+    assert_eq!((notes[1].0, notes[1].1), (500, 42));
+    assert_velocity(notes[1].2, 0.5);
+}
+
+#[test]
+fn sent_and_filtered_beats_are_reported() {
+    let mut snapshot = send_midi_snapshot();
+    snapshot.upper_threshold = 0.5;
+    let mut processor = new_processor(FOUR_FOUR, snapshot);
+    processor.process_samples(PLAYING, 1000, 64);
+
+    let beats: Vec<(usize, usize, bool)> = processor.take_beat_events().iter()
+        .map(|beat| (beat.beat_idx, beat.indisp_val, beat.passed))
+        .collect();
+    assert_eq!(beats, vec![(0, 0, true), (1, 3, false), (2, 1, true), (3, 2, false)]);
+    assert!(processor.take_beat_events().is_empty());
+
+    // Without Send Midi, the incoming notes trigger the beats.
+    snapshot.send_midi = false;
+    let mut processor = new_processor(FOUR_FOUR, snapshot);
+    processor.process(PLAYING, 1000, [note_on(0, 40), note_on(250, 41)]);
+    let beats = processor.take_beat_events();
+    assert_eq!(beats.iter().map(|beat| beat.passed).collect::<Vec<_>>(), vec![true, false]);
+    assert_velocity(beats[0].velocity, 1.0);
+}
+
+fn note_off(timing: u32, note: u8) -> NoteEvent<MidiSystemMessage> {
+    NoteEvent::NoteOff { timing, voice_id: None, channel: 0, note, velocity: 0.0 }
+}
+
+/// (timing, note, is NoteOn) of all NoteOns and NoteOffs in a single buffer
+fn buffer_notes(events: &[NoteEvent<MidiSystemMessage>]) -> Vec<(u32, u8, bool)> {
+    events.iter()
+        .filter_map(|event| match *event {
+            NoteEvent::NoteOn { timing, note, .. } => Some((timing, note, true)),
+            NoteEvent::NoteOff { timing, note, .. } => Some((timing, note, false)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn incoming_notes_are_transposed_by_rank() {
+    let mut snapshot = send_midi_snapshot();
+    snapshot.send_midi = false;
+    snapshot.transpose_mode = TransposeMode::ByRank;
+    snapshot.transpose_table = TransposeTable::try_from("12 0 0 7").unwrap();
+    let mut processor = new_processor(FOUR_FOUR, snapshot);
+
+    // The first note ends on a beat with another transposition, its NoteOff follows the NoteOn.
+    let incoming = [note_on(0, 60), note_off(250, 60), note_on(250, 60), note_off(300, 60), note_on(500, 60)];
+    let events = processor.process(PLAYING, 1000, incoming);
+    assert_eq!(buffer_notes(&events), vec![
+        (0, 72, true), (250, 72, false), (250, 67, true), (300, 67, false), (500, 60, true),
+    ]);
+}
+
+#[test]
+fn incoming_notes_are_transposed_by_stratum_and_quantized() {
+    let mut snapshot = send_midi_snapshot();
+    snapshot.send_midi = false;
+    snapshot.transpose_mode = TransposeMode::ByStratum;
+    snapshot.transpose_table = TransposeTable::try_from("0 0 12").unwrap();
+    snapshot.quantize_to_chord = true;
+    snapshot.chord = Chord::try_from("0 4 7").unwrap();
+    let mut processor = new_processor(FOUR_FOUR, snapshot);
+
+    // The bar is the second stratum, the half bar the first and the other beats start no group.
+    let incoming = [note_on(0, 61), note_on(250, 63), note_on(500, 61), note_off(600, 61)];
+    let events = processor.process(PLAYING, 1000, incoming);
+    assert_eq!(buffer_notes(&events), vec![(0, 72, true), (250, 64, true), (500, 60, true), (600, 60, false)]);
+}
+
+#[test]
+fn incoming_notes_are_transposed_by_interpolated_strata() {
+    let mut snapshot = send_midi_snapshot();
+    snapshot.send_midi = false;
+    snapshot.transpose_mode = TransposeMode::ByStratum;
+    snapshot.transpose_table = TransposeTable::try_from("0 7 12").unwrap();
+    let mut processor = halfway_processor(snapshot);
+
+    let incoming = [note_on(0, 60), note_on(420, 60), note_on(500, 60)];
+    let events = processor.process(PLAYING, 1000, incoming);
+    assert_eq!(buffer_notes(&events), vec![(0, 72, true), (420, 60, true), (500, 67, true)]);
+}
+
+#[test]
+fn weak_beats_are_moved_later() {
+    let mut snapshot = send_midi_snapshot();
+    snapshot.send_midi = false;
+    snapshot.timing_weak = 20.0;
+    let mut processor = new_processor(FOUR_FOUR, snapshot);
+
+    // Indispensability 0, 3 and 1 of 3 are moved by 0, 20 and 7 samples. The NoteOff is moved
+    // like its NoteOn, and late notes are sent in the next buffer.
+    let incoming = [note_on(0, 40), note_on(250, 41), note_off(260, 41), note_on(500, 42), note_on(990, 43)];
+    let events = processor.process(PLAYING, 1000, incoming);
+    assert_eq!(buffer_notes(&events), vec![(0, 40, true), (270, 41, true), (280, 41, false), (507, 42, true)]);
+    assert_eq!(processor.latency_samples(), 0);
+
+    let events = processor.process(PLAYING, 1000, []);
+    assert_eq!(buffer_notes(&events), vec![(3, 43, true)]);
+}
+
+#[test]
+fn notes_that_cant_be_delayed_are_sent_in_time() {
+    let mut snapshot = send_midi_snapshot();
+    snapshot.send_midi = false;
+    snapshot.timing_weak = 20.0;
+    let mut processor = new_processor(FOUR_FOUR, snapshot);
+
+    // Only DELAYED_EVENTS_CAPACITY notes can be moved later, the others are sent unmoved.
+    let incoming: Vec<_> = (0..DELAYED_EVENTS_CAPACITY + 10).map(|idx| note_on(250, (idx % 128) as u8)).collect();
+    let events = processor.process(PLAYING, 1000, incoming);
+    let timings: Vec<u32> = buffer_notes(&events).iter().map(|&(timing, _, _)| timing).collect();
+    assert_eq!(timings, [vec![250; 10], vec![270; DELAYED_EVENTS_CAPACITY]].concat());
+}
+
+#[test]
+fn strong_beats_are_moved_earlier_with_latency() {
+    let mut snapshot = send_midi_snapshot();
+    snapshot.send_midi = false;
+    snapshot.timing_strong = -10.0;
+    let mut processor = new_processor(FOUR_FOUR, snapshot);
+
+    // Everything is 10 samples late, except for notes on the most important beat.
+    let cc = NoteEvent::MidiCC { timing: 100, channel: 0, cc: 1, value: 0.5 };
+    let events = processor.process(PLAYING, 1000, [note_on(0, 40), cc, note_on(250, 41)]);
+    assert_eq!(processor.latency_samples(), 10);
+    assert_eq!(buffer_notes(&events), vec![(0, 40, true), (260, 41, true)]);
+    assert!(matches!(events[1], NoteEvent::MidiCC { timing: 110, .. }));
+}
+
+#[test]
+fn latency_changes_are_reported_to_the_host() {
+    let mut snapshot = send_midi_snapshot();
+    snapshot.send_midi = false;
+    snapshot.timing_strong = -10.0;
+    let mut processor = new_processor(FOUR_FOUR, snapshot);
+    processor.process(PLAYING, 1000, []);
+    assert_eq!(processor.latency_samples(), 10);
+
+    processor.snapshot_mut().timing_strong = 0.0;
+    let events = processor.process(PLAYING, 1000, [note_on(0, 40)]);
+    assert_eq!(processor.latency_samples(), 0);
+    assert_eq!(buffer_notes(&events), vec![(0, 40, true)]);
+}
+
+#[test]
+fn generated_events_stay_on_the_grid_with_latency() {
+    // Everything is latency samples late, which the host takes off again.
+    fn on_grid(events: &[(u64, NoteEvent<MidiSystemMessage>)], latency: u64) -> Vec<(u64, u8, bool)> {
+        events.iter()
+            .filter_map(|&(position, event)| match event {
+                NoteEvent::NoteOn { note, .. } => Some((position - latency, note, true)),
+                NoteEvent::MidiSysEx { .. } => Some((position - latency, 0, false)),
+                _ => None,
+            })
+            .filter(|&(position, _, _)| position < 2000)
+            .collect()
+    }
+
+    let snapshot = ParamsSnapShot { send_midi_clock: true, ..send_midi_snapshot() };
+    let mut processor = new_processor(FOUR_FOUR, snapshot);
+    let expected = on_grid(&processor.process_samples(PLAYING, 2000, 64), 0);
+
+    let mut processor = new_processor(FOUR_FOUR, ParamsSnapShot { timing_strong: -10.0, ..snapshot });
+    let events = processor.process_samples(PLAYING, 2010, 64);
+    assert_eq!(processor.latency_samples(), 10);
+    assert_eq!(on_grid(&events, 10), expected);
+    assert_eq!(expected.first(), Some(&(0, 0, false)));
+    assert!(expected.contains(&(1000, 60, true)));
+}
+
+#[test]
+fn send_midi_ratchets_strong_beats() {
+    let mut snapshot = send_midi_snapshot();
+    snapshot.ratchet_mode = RatchetMode::Even;
+    snapshot.ratchet_repeats = 4;
+    snapshot.ratchet_ramp = 0.5;
+    let mut processor = new_processor(FOUR_FOUR, snapshot);
+    let events = processor.process_samples(PLAYING, 1000, 64);
+
+    // Only the most important beat is repeated, rising to its velocity.
+    let onsets: Vec<u64> = note_ons(&events).iter().map(|&(position, _, _)| position).collect();
+    assert_eq!(onsets, vec![0, 62, 125, 187, 250, 500, 750]);
+    for (&(_, _, velocity), expected) in note_ons(&events).iter().zip([0.5, 2.0 / 3.0, 5.0 / 6.0, 1.0]) {
+        assert_velocity(velocity, expected);
+    }
+    // Every repeat ends before the next one, the last one before the end of the beat.
+    let offs: Vec<u64> = note_offs(&events).iter().map(|&(position, _)| position).take(4).collect();
+    assert_eq!(offs, vec![61, 124, 186, 249]);
+}
+
+#[test]
+fn metric_ratchets_follow_the_group() {
+    let mut snapshot = send_midi_snapshot();
+    snapshot.ratchet_mode = RatchetMode::Metric;
+    // The first beat belongs to a group of three, so it becomes a triplet.
+    let mut processor = new_processor("(4 ((3 (1 1 1)) 1))", snapshot);
+    let events = processor.process_samples(PLAYING, 1000, 64);
+    let onsets: Vec<u64> = note_ons(&events).iter().map(|&(position, _, _)| position).collect();
+    assert_eq!(onsets, vec![0, 83, 167, 250, 500, 750]);
+
+    // With a higher threshold, the half bar of FOUR_FOUR is halved as well.
+    snapshot.ratchet_threshold = 0.4;
+    let mut processor = new_processor(FOUR_FOUR, snapshot);
+    let events = processor.process_samples(PLAYING, 1000, 64);
+    let onsets: Vec<u64> = note_ons(&events).iter().map(|&(position, _, _)| position).collect();
+    assert_eq!(onsets, vec![0, 125, 250, 500, 625, 750]);
+}
+
+#[test]
+fn incoming_notes_are_ratcheted() {
+    let mut snapshot = send_midi_snapshot();
+    snapshot.send_midi = false;
+    snapshot.ratchet_mode = RatchetMode::Even;
+    snapshot.ratchet_repeats = 2;
+    snapshot.ratchet_ramp = -0.5;
+    let mut processor = new_processor(FOUR_FOUR, snapshot);
+
+    // The repeat ends the note before it, and the NoteOff ends the repeat. The next note isn't
+    // repeated, and the note released before its repeat isn't repeated either.
+    let held = NoteEvent::NoteOn { timing: 0, voice_id: Some(7), channel: 0, note: 40, velocity: 0.8 };
+    let incoming = [held, note_off(200, 40), note_on(250, 41), note_off(300, 41), note_on(500, 42), note_off(550, 42)];
+    let events = processor.process(PLAYING, 1000, incoming);
+    assert_eq!(buffer_notes(&events), vec![
+        (0, 40, true), (125, 40, false), (125, 40, true), (200, 40, false),
+        (250, 41, true), (300, 41, false), (500, 42, true), (550, 42, false),
+    ]);
+    let voice_ids: Vec<Option<i32>> = events[..4].iter()
+        .filter_map(|event| match *event {
+            NoteEvent::NoteOn { voice_id, .. } | NoteEvent::NoteOff { voice_id, .. } => Some(voice_id),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(voice_ids, vec![Some(7), Some(7), None, None]);
+    let velocities: Vec<f32> = events.iter()
+        .filter_map(|event| match *event {
+            NoteEvent::NoteOn { velocity, .. } => Some(velocity),
+            _ => None,
+        })
+        .collect();
+    assert_velocity(velocities[0], 1.0);
+    assert_velocity(velocities[1], 0.5);
+}
+
+#[test]
+fn ratchets_continue_across_buffers() {
+    let mut snapshot = send_midi_snapshot();
+    snapshot.send_midi = false;
+    snapshot.ratchet_mode = RatchetMode::Even;
+    snapshot.ratchet_repeats = 4;
+    let mut processor = new_processor(FOUR_FOUR, snapshot);
+
+    // Repeats at 0, 62, 125 and 187, the last one sounds until the NoteOff in the third buffer.
+    let mut events = processor.process(PLAYING, 100, [note_on(0, 40)]);
+    events.extend(processor.process(PLAYING, 100, []));
+    assert_eq!(buffer_notes(&events), vec![
+        (0, 40, true), (62, 40, false), (62, 40, true), (25, 40, false), (25, 40, true), (87, 40, false), (87, 40, true),
+    ]);
+    let events = processor.process(PLAYING, 100, [note_off(10, 40)]);
+    assert_eq!(buffer_notes(&events), vec![(10, 40, false)]);
+}
+
+#[test]
+fn chosen_ccs_and_aftertouch_are_scaled_by_metric_weight() {
+    let mut snapshot = send_midi_snapshot();
+    snapshot.send_midi = false;
+    snapshot.metric_cc_depth = 0.5;
+    snapshot.metric_ccs = CcSelection::try_from("74").unwrap();
+    snapshot.scale_aftertouch = true;
+    let mut processor = new_processor(FOUR_FOUR, snapshot);
+
+    let incoming = [
+        NoteEvent::MidiCC { timing: 0, channel: 0, cc: 74, value: 1.0 },
+        NoteEvent::MidiCC { timing: 250, channel: 0, cc: 74, value: 1.0 },
+        NoteEvent::MidiCC { timing: 250, channel: 0, cc: 1, value: 1.0 },
+        NoteEvent::PolyPressure { timing: 500, voice_id: None, channel: 0, note: 60, pressure: 1.0 },
+        NoteEvent::MidiChannelPressure { timing: 750, channel: 0, pressure: 1.0 },
+    ];
+    let events = processor.process(PLAYING, 1000, incoming);
+    let values: Vec<f32> = events.iter()
+        .filter_map(|event| match *event {
+            NoteEvent::MidiCC { value, .. } => Some(value),
+            NoteEvent::PolyPressure { pressure, .. } | NoteEvent::MidiChannelPressure { pressure, .. } => Some(pressure),
+            _ => None,
+        })
+        .collect();
+
+    // Indispensability 0, 3, (CC 1 is not chosen), 1 and 2 of 3
+    for (value, expected) in values.into_iter().zip([1.0, 0.5, 1.0, 5.0 / 6.0, 2.0 / 3.0]) {
+        assert_velocity(value, expected);
+    }
+}
+
+#[test]
+fn incoming_note_offs_pass() {
+    let mut snapshot = send_midi_snapshot();
+    snapshot.send_midi = false;
+    snapshot.upper_threshold = 0.0;
+    let mut processor = new_processor(FOUR_FOUR, snapshot);
+
+    let note_off = NoteEvent::NoteOff { timing: 250, voice_id: None, channel: 0, note: 41, velocity: 0.0 };
+    let events = processor.process(PLAYING, 1000, [note_on(250, 41), note_off]);
+    assert_eq!(events.len(), 1);
+    assert!(matches!(events[0], NoteEvent::NoteOff { timing: 250, note: 41, .. }));
+}
+
+#[test]
+fn note_offs_are_scheduled_across_buffers() {
+    // NoteOffs follow their NoteOns after 0.1 seconds, no matter how the buffers are split.
+    for buffer_size in [1, 37, 64, 100, 1000] {
+        let mut processor = new_processor(FOUR_FOUR, send_midi_snapshot());
+        let events = processor.process_samples(PLAYING, 1000, buffer_size);
+
+        assert_eq!(note_offs(&events), vec![(100, 60), (350, 63), (600, 61), (850, 62)], "buffer size {}", buffer_size);
+    }
+
+    // The NoteOff of the last beat is sent in the next buffer.
+    let mut processor = new_processor(FOUR_FOUR, send_midi_snapshot());
+    let first = processor.process(PLAYING, 800, []);
+    assert_eq!(first.iter().filter(|event| matches!(event, NoteEvent::NoteOff { .. })).count(), 3);
+    let second = processor.process(PLAYING, 100, []);
+    assert!(matches!(second[..], [NoteEvent::NoteOff { timing: 50, note: 62, .. }]));
+}
+
+#[test]
+fn note_offs_are_sent_when_stopped() {
+    let mut processor = new_processor(FOUR_FOUR, send_midi_snapshot());
+    processor.process(PLAYING, 10, []);
+    let events = processor.process_samples(STOPPED, 200, 64);
+    assert_eq!(note_offs(&events), vec![(90, 60)]);
+}
+
+#[test]
+fn playback_start_resets_phase() {
+    let mut processor = new_processor(FOUR_FOUR, send_midi_snapshot());
+    processor.process_samples(PLAYING, 300, 64);
+    assert_eq!(processor.metric_phase().progress_in_samples(), 300);
+
+    // Stopped: the phase stands still and no new notes are sent.
+    let events = processor.process_samples(STOPPED, 500, 64);
+    assert_eq!(processor.metric_phase().progress_in_samples(), 300);
+    assert!(note_ons(&events).is_empty());
+
+    // Starting again begins at the start of the bar.
+    let events = processor.process_samples(PLAYING, 300, 64);
+    let onsets: Vec<(u64, u8)> = note_ons(&events).iter().map(|&(position, note, _)| (position, note)).collect();
+    assert_eq!(onsets, vec![(0, 60), (250, 63)]);
+    assert_eq!(processor.metric_phase().progress_in_samples(), 300);
+}
+
+#[test]
+fn nothing_is_sent_without_send_midi() {
+    let mut snapshot = send_midi_snapshot();
+    snapshot.send_midi = false;
+    let mut processor = new_processor(FOUR_FOUR, snapshot);
+    assert!(processor.process_samples(PLAYING, 2000, 64).is_empty());
+}
+
+#[test]
+fn rendered_bars_match_processing() {
+    let events = render_bars(
+        CombinedMetreData::new(MetreData::try_from(FOUR_FOUR).unwrap(), MetreData::try_from(FOUR_FOUR).unwrap()).unwrap(),
+        send_midi_snapshot(),
+        2,
+        120.0,
+        SAMPLE_RATE,
+    );
+
+    let mut processor = new_processor(FOUR_FOUR, send_midi_snapshot());
+    let mut processed = processor.process_samples(PLAYING, 2000, 64);
+    processed.sort_by_key(|(position, _)| *position);
+
+    assert_eq!(note_ons(&events), note_ons(&processed));
+    assert_eq!(note_offs(&events), note_offs(&processed));
+    assert_eq!(note_offs(&events).len(), 8);
+}
+
+#[test]
+fn exports_never_overwrite_files() {
+    let dir = std::env::temp_dir().join(format!("metrefiddler-export-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let export = |path| {
+        let metre = MetreData::try_from(FOUR_FOUR).unwrap();
+        export_midi(CombinedMetreData::new(metre.clone(), metre).unwrap(), send_midi_snapshot(), 1, 120.0, SAMPLE_RATE, path)
+    };
+
+    let first = new_export_path(&dir);
+    assert_eq!(export(&first), Ok(4));
+    let second = new_export_path(&dir);
+    assert_ne!(first, second);
+    assert_eq!(export(&second), Ok(4));
+    assert!(export(&first).is_err());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn exports_go_to_an_existing_folder() {
+    let dir = std::env::temp_dir();
+    assert_eq!(export_dir(dir.to_str().unwrap()), Ok(dir.clone()));
+
+    let missing = dir.join(format!("metrefiddler-missing-{}", std::process::id()));
+    assert_eq!(export_dir(missing.to_str().unwrap()), Err(format!("'{}' is not a folder", missing.display())));
+}
+
+#[test]
+fn exports_take_off_the_latency() {
+    let dir = std::env::temp_dir().join(format!("metrefiddler-latency-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("latency.mid");
+    let metre = MetreData::try_from(FOUR_FOUR).unwrap();
+    let snapshot = ParamsSnapShot { timing_strong: -10.0, ..send_midi_snapshot() };
+    assert_eq!(export_midi(CombinedMetreData::new(metre.clone(), metre).unwrap(), snapshot, 1, 120.0, SAMPLE_RATE, &path), Ok(4));
+
+    // 250 samples at 120 BPM are 240 ticks.
+    let bytes = std::fs::read(&path).unwrap();
+    let smf = Smf::parse(&bytes).unwrap();
+    let mut tick = 0;
+    let onsets: Vec<u32> = smf.tracks[0].iter()
+        .filter_map(|event| {
+            tick += event.delta.as_int();
+            matches!(event.kind, TrackEventKind::Midi { message: MidiMessage::NoteOn { .. }, .. }).then_some(tick)
+        })
+        .collect();
+    assert_eq!(onsets, vec![0, 240, 480, 720]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn metre_slots_switch_at_the_next_bar() {
+    let mut processor = new_processor(FOUR_FOUR, send_midi_snapshot());
+    processor.metre_slots().set_slot_a(1, "(3 (1 1 1))");
+    let mut onsets: Vec<u64> = note_ons(&processor.process_samples(PLAYING, 500, 50))
+        .iter().map(|&(position, _, _)| position).collect();
+
+    processor.snapshot_mut().slot_a = 1;
+    let events = processor.process_samples(PLAYING, 1500, 50);
+    assert_eq!(processor.combined_metre_data().metre_a().string, "(3 (1 1 1))");
+    assert_eq!(processor.combined_metre_data().metre_b().string, FOUR_FOUR);
+
+    onsets.extend(note_ons(&events).iter().map(|&(position, _, _)| position + 500));
+    assert_eq!(onsets, vec![0, 250, 500, 750, 1000, 1334, 1667]);
+}
+
+#[test]
+fn empty_metre_slots_keep_the_current_metre() {
+    let mut snapshot = send_midi_snapshot();
+    snapshot.slot_b = 3;
+    let mut processor = new_processor(FOUR_FOUR, send_midi_snapshot());
+    processor.process_samples(PLAYING, 500, 50);
+    *processor.snapshot_mut() = snapshot;
+    processor.process_samples(PLAYING, 1500, 50);
+    assert_eq!(processor.combined_metre_data().metre_b().string, FOUR_FOUR);
+}
+
+#[test]
+fn metre_slots_switch_again_after_a_switch() {
+    // The metre data that was switched away from is handed to the background, which makes way for
+    // the next switch.
+    let mut processor = new_processor(FOUR_FOUR, send_midi_snapshot());
+    processor.metre_slots().set_slot_a(1, "(3 (1 1 1))");
+    processor.metre_slots().set_slot_a(2, "(2 (1 1))");
+    processor.process_samples(PLAYING, 500, 50);
+
+    processor.snapshot_mut().slot_a = 1;
+    processor.process_samples(PLAYING, 1500, 50);
+    assert_eq!(processor.combined_metre_data().metre_a().string, "(3 (1 1 1))");
+
+    processor.snapshot_mut().slot_a = 2;
+    processor.process_samples(PLAYING, 1500, 50);
+    assert_eq!(processor.combined_metre_data().metre_a().string, "(2 (1 1))");
+}
+
+const NO_TRANSPORT: HeadlessTransport = HeadlessTransport { playing: false, tempo: None };
+
+fn system_message(timing: u32, message: MidiSystemMessage) -> NoteEvent<MidiSystemMessage> {
+    NoteEvent::MidiSysEx { timing, message }
+}
+
+/// MIDI clock at 125 BPM, where a quarter note lasts 480 samples and a tick 20 samples.
+fn clock_ticks(start: u32, end: u32) -> impl Iterator<Item = NoteEvent<MidiSystemMessage>> {
+    (start..end).step_by(20).map(|timing| system_message(timing, MidiSystemMessage::Clock))
+}
+
+/// (timing, note) of all NoteOns in a single buffer
+fn buffer_note_ons(events: &[NoteEvent<MidiSystemMessage>]) -> Vec<(u32, u8)> {
+    events.iter()
+        .filter_map(|event| match *event {
+            NoteEvent::NoteOn { timing, note, .. } => Some((timing, note)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn clock_sync_snapshot() -> ParamsSnapShot {
+    // One bar is one quarter note.
+    ParamsSnapShot {
+        midi_clock_sync: true,
+        use_bpm: true,
+        ..send_midi_snapshot()
+    }
+}
+
+#[test]
+fn midi_clock_drives_the_metric_phase() {
+    let mut processor = new_processor(FOUR_FOUR, clock_sync_snapshot());
+    let incoming = std::iter::once(system_message(0, MidiSystemMessage::Start))
+        .chain(clock_ticks(0, 960))
+        .chain([system_message(950, MidiSystemMessage::Stop)]);
+    let events = processor.process(NO_TRANSPORT, 960, incoming);
+
+    assert_eq!(buffer_note_ons(&events), vec![
+        (0, 60), (120, 63), (240, 61), (360, 62),
+        (480, 60), (600, 63), (720, 61), (840, 62),
+    ]);
+    // The clock messages are passed on.
+    assert_eq!(events.iter().filter(|event| matches!(event, NoteEvent::MidiSysEx { .. })).count(), 50);
+
+    // After the clock stopped, nothing moves.
+    let events = processor.process(NO_TRANSPORT, 960, []);
+    assert!(buffer_note_ons(&events).is_empty());
+}
+
+#[test]
+fn song_position_moves_the_metric_phase() {
+    let mut processor = new_processor(FOUR_FOUR, clock_sync_snapshot());
+    // Ticks while stopped only measure the tempo.
+    let incoming = clock_ticks(0, 200)
+        .chain([
+            // Two sixteenths are half a quarter note, the third beat of the bar.
+            system_message(200, MidiSystemMessage::SongPosition(2)),
+            system_message(200, MidiSystemMessage::Continue),
+        ])
+        .chain(clock_ticks(200, 480));
+    let events = processor.process(NO_TRANSPORT, 480, incoming);
+
+    // Like with a stopped host, the beat at the current position is sent once before that.
+    assert_eq!(buffer_note_ons(&events), vec![(0, 60), (200, 61), (320, 62), (440, 60)]);
+}
+
+#[test]
+fn the_tempo_follows_the_midi_clock() {
+    let mut processor = new_processor(FOUR_FOUR, clock_sync_snapshot());
+    processor.process(HeadlessTransport { playing: true, tempo: Some(100.0) }, 480, []);
+    assert_eq!(processor.tempo(), 100.0);
+
+    // The tempo is measured from the ticks of the last buffer.
+    processor.process(NO_TRANSPORT, 480, clock_ticks(0, 480));
+    processor.process(NO_TRANSPORT, 480, clock_ticks(0, 480));
+    assert!((processor.tempo() - 125.0).abs() < 0.01);
+}
+
+#[test]
+fn sent_midi_clock_follows_the_bar() {
+    // A bar of four quarter notes at 125 BPM lasts 1920 samples, a tick 20 samples.
+    let snapshot = ParamsSnapShot { send_midi_clock: true, use_bpm: true, metric_dur: 4.0, ..ParamsSnapShot::default() };
+    let mut processor = new_processor(FOUR_FOUR, snapshot);
+    let transport = HeadlessTransport { playing: true, tempo: Some(125.0) };
+    let events = processor.process_samples(transport, 3840, 64);
+
+    let messages: Vec<(u64, MidiSystemMessage)> = events.iter()
+        .filter_map(|(position, event)| match *event {
+            NoteEvent::MidiSysEx { message, .. } => Some((*position, message)),
+            _ => None,
+        })
+        .collect();
+    let expected: Vec<(u64, MidiSystemMessage)> = std::iter::once((0, MidiSystemMessage::Start))
+        .chain((0..3840).step_by(20).map(|position| (position, MidiSystemMessage::Clock)))
+        .collect();
+    assert_eq!(messages, expected);
+
+    let events = processor.process(HeadlessTransport { playing: false, ..transport }, 64, []);
+    assert!(matches!(events[..], [NoteEvent::MidiSysEx { timing: 0, message: MidiSystemMessage::Stop }]));
+}
+
+#[test]
+fn bar_starts_and_group_starts_are_marked() {
+    let snapshot = ParamsSnapShot {
+        bar_marker: BarMarker::Note,
+        bar_marker_number: 24,
+        send_group_notes: true,
+        group_note_base: 36,
+        ..ParamsSnapShot::default()
+    };
+    let mut processor = new_processor(FOUR_FOUR, snapshot);
+    let events = processor.process_samples(PLAYING, 2000, 64);
+
+    // The bar is the second stratum of FOUR_FOUR, the half bars are the first.
+    let onsets: Vec<(u64, u8)> = note_ons(&events).iter().map(|&(position, note, _)| (position, note)).collect();
+    assert_eq!(onsets, vec![(0, 24), (0, 37), (500, 36), (1000, 24), (1000, 37), (1500, 36)]);
+    assert_eq!(note_offs(&events).len(), 6);
+
+    let snapshot = ParamsSnapShot { bar_marker: BarMarker::ControlChange, bar_marker_number: 20, ..ParamsSnapShot::default() };
+    let mut processor = new_processor(FOUR_FOUR, snapshot);
+    let events = processor.process_samples(PLAYING, 2000, 64);
+    let ccs: Vec<(u64, u8)> = events.iter()
+        .filter_map(|(position, event)| match *event {
+            NoteEvent::MidiCC { cc, .. } => Some((*position, cc)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(ccs, vec![(0, 20), (1000, 20)]);
+}
+
+#[test]
+fn group_starts_of_interpolated_metres_are_marked() {
+    let snapshot = ParamsSnapShot { send_group_notes: true, group_note_base: 36, ..ParamsSnapShot::default() };
+    let mut processor = halfway_processor(snapshot);
+    let events = processor.process_samples(PLAYING, 1000, 64);
+
+    let onsets: Vec<(u64, u8)> = note_ons(&events).iter().map(|&(position, note, _)| (position, note)).collect();
+    assert_eq!(onsets, vec![(0, 37), (500, 36)]);
+}
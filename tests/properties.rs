@@ -0,0 +1,149 @@
+use num_rational::Rational64;
+use proptest::prelude::*;
+use metrefiddler::metre::combined_metre_data::CombinedMetreData;
+use metrefiddler::metre::indispensability::gnsm_to_indispensability_list;
+use metrefiddler::metre::interpolation::interpolation_data::InterpolationData;
+use metrefiddler::metre::metre_data::MetreData;
+use metrefiddler::metre::rqq::parse_rqq;
+use metrefiddler::util::dry_wet;
+
+/// A valid RQQ string, nested up to four levels deep.
+fn rqq_strategy() -> impl Strategy<Value = String> {
+    let division = (1u32..10).prop_map(|n| n.to_string());
+    let nested = division.prop_recursive(4, 64, 6, |inner| {
+        (1u32..10, prop::collection::vec(inner, 1..6))
+            .prop_map(|(duration, divisions)| format!("({} ({}))", duration, divisions.join(" ")))
+    });
+
+    (1u32..10, prop::collection::vec(nested, 1..6))
+        .prop_map(|(duration, divisions)| format!("({} ({}))", duration, divisions.join(" ")))
+}
+
+fn ratio_strategy() -> impl Strategy<Value = Rational64> {
+    (0i64..5, 1i64..5).prop_map(|(numer, denom)| Rational64::new(numer, denom))
+}
+
+fn assert_close(actual: f32, expected: f32) {
+    assert!((actual - expected).abs() < 1e-4, "{} != {}", actual, expected);
+}
+
+proptest! {
+    #[test]
+    fn parse_rqq_does_not_panic(input in "\\PC*") {
+        let _ = parse_rqq(&input);
+    }
+
+    #[test]
+    fn metre_data_does_not_panic(input in "[()0-9 ,]{0,64}") {
+        let _ = MetreData::try_from(input.as_str());
+    }
+
+    #[test]
+    fn metre_data_is_consistent(rqq in rqq_strategy()) {
+        let metre = MetreData::try_from(rqq.as_str()).unwrap();
+        let len = metre.durations.len();
+
+        prop_assert_eq!(metre.gnsm.len(), len);
+        prop_assert_eq!(metre.value.len(), len);
+        prop_assert_eq!(metre.exact_durations.len(), len);
+        prop_assert_eq!(metre.exact_durations.iter().sum::<Rational64>(), Rational64::from_integer(1));
+        prop_assert_eq!(metre.max, len - 1);
+
+        // Every beat gets a different indispensability value.
+        let mut values = metre.value.clone();
+        values.sort();
+        prop_assert_eq!(values, (0..len).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn gnsm_to_indispensability_list_does_not_panic(gnsm in prop::collection::vec(0usize..4, 0..12)) {
+        let _ = gnsm_to_indispensability_list(&gnsm);
+    }
+
+    #[test]
+    fn new_from_durs_and_gnsm_does_not_panic(
+        durations_a in prop::collection::vec(ratio_strategy(), 0..8),
+        durations_b in prop::collection::vec(ratio_strategy(), 0..8),
+        gnsm_a in prop::collection::vec(0usize..3, 0..8),
+        gnsm_b in prop::collection::vec(0usize..3, 0..8),
+    ) {
+        let _ = InterpolationData::new_from_durs_and_gnsm(&durations_a, &durations_b, &gnsm_a, &gnsm_b);
+    }
+
+    #[test]
+    fn interpolation_keeps_all_beats(a in rqq_strategy(), b in rqq_strategy(), interpolation in 0.0f32..=1.0) {
+        let metre_a = MetreData::try_from(a.as_str()).unwrap();
+        let metre_b = MetreData::try_from(b.as_str()).unwrap();
+        let combined = CombinedMetreData::new(metre_a.clone(), metre_b.clone()).unwrap();
+
+        // Every beat of A and B appears in exactly one pair.
+        let pairs = combined.interpolation_data().duration_pairs();
+        prop_assert_eq!(pairs.iter().filter(|(a, _)| *a > 0.0).count(), metre_a.durations.len());
+        prop_assert_eq!(pairs.iter().filter(|(_, b)| *b > 0.0).count(), metre_b.durations.len());
+
+        // The ends of the interpolation are the original metres.
+        let at_a: Vec<f32> = combined.get_interpolated_durations(0.0).collect();
+        let at_b: Vec<f32> = combined.get_interpolated_durations(1.0).collect();
+        prop_assert_eq!(at_a.len(), metre_a.durations.len());
+        prop_assert_eq!(at_b.len(), metre_b.durations.len());
+        at_a.iter().zip(&metre_a.durations).for_each(|(x, y)| assert_close(*x, *y));
+        at_b.iter().zip(&metre_b.durations).for_each(|(x, y)| assert_close(*x, *y));
+
+        // Everything in between still fills a whole bar.
+        assert_close(combined.get_interpolated_durations(interpolation).sum(), 1.0);
+        assert_close(combined.get_interleaved_durations(interpolation).sum(), 1.0);
+
+        for position in [0.0, interpolation * 0.999, 0.999] {
+            let _ = combined.get_indisp_data_at(position, interpolation, true, true);
+            let _ = combined.get_indisp_data_at(position, interpolation, false, false);
+        }
+
+        // The pairs' start times morph into the start times of the interpolated beats.
+        let starts: Vec<f32> = combined.interpolation_data().start_time_pairs().iter()
+            .zip(combined.interpolation_data().duration_pairs())
+            .filter(|(_, &(a, b))| dry_wet(a, b, interpolation) > 0.0)
+            .map(|(&(start_a, start_b), _)| dry_wet(start_a, start_b, interpolation))
+            .collect();
+        let mut start = 0.0;
+        for (dur, expected) in combined.get_interpolated_durations(interpolation).zip(&starts) {
+            assert_close(*expected, start);
+            start += dur;
+        }
+
+        // At the ends, the beat weights are those of the original metres.
+        let weights = |interpolation| combined.get_beat_weights(interpolation, false, false)
+            .iter()
+            .map(|&(_, indisp_val, _)| indisp_val)
+            .collect::<Vec<_>>();
+        prop_assert_eq!(weights(0.0), metre_a.value.clone());
+        prop_assert_eq!(weights(1.0), metre_b.value.clone());
+        prop_assert_eq!(combined.get_beat_weights(interpolation, true, true).len(), combined.get_interpolated_durations(interpolation).count());
+    }
+}
+
+#[test]
+fn unpaired_beats_in_both_metres_are_kept() {
+    // A's first beat starts together with B's, A's second with B's third. B's second beat and the
+    // last two beats of A stay unpaired.
+    let metre_a = MetreData::try_from("(1 (7 1 1 5))").unwrap();
+    let metre_b = MetreData::try_from("(2 (7 1 8))").unwrap();
+    let combined = CombinedMetreData::new(metre_a, metre_b).unwrap();
+
+    assert_eq!(combined.interpolation_data().duration_pairs().len(), 5);
+    assert_close(combined.get_interpolated_durations(0.0).sum(), 1.0);
+    assert_close(combined.get_interpolated_durations(1.0).sum(), 1.0);
+}
+
+#[test]
+fn deeply_nested_rqq_is_an_error() {
+    let deep = format!("{}1{}", "(1 (".repeat(1000), "))".repeat(1000));
+    assert!(MetreData::try_from(deep.as_str()).is_err());
+}
+
+#[test]
+fn invalid_interpolation_input_is_an_error() {
+    let one = [Rational64::from_integer(1)];
+    assert!(InterpolationData::new_from_durs_and_gnsm(&one, &one, &[0, 1], &[0]).is_err());
+    assert!(InterpolationData::new_from_durs_and_gnsm(&[], &one, &[], &[0]).is_err());
+    assert!(gnsm_to_indispensability_list(&[]).is_err());
+}
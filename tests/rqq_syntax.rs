@@ -0,0 +1,76 @@
+use metrefiddler::metre::metre_data::MetreData;
+use metrefiddler::metre::rqq_syntax::{find_syntax_errors, format_rqq, matching_bracket, tokenize, RqqTokenKind};
+use metrefiddler::preset::factory_presets;
+
+fn error_positions(text: &str) -> Vec<(usize, usize)> {
+    find_syntax_errors(text).iter().map(|error| (error.start, error.end)).collect()
+}
+
+#[test]
+fn tokens_and_depths() {
+    let tokens = tokenize("(3 (12,x))");
+    let kinds: Vec<RqqTokenKind> = tokens.iter().map(|token| token.kind).collect();
+    assert_eq!(kinds, vec![
+        RqqTokenKind::Open, RqqTokenKind::Number, RqqTokenKind::Separator, RqqTokenKind::Open,
+        RqqTokenKind::Number, RqqTokenKind::Separator, RqqTokenKind::Invalid,
+        RqqTokenKind::Close, RqqTokenKind::Close,
+    ]);
+    assert_eq!((tokens[4].start, tokens[4].end), (4, 6));
+    let depths: Vec<usize> = tokens.iter().map(|token| token.depth).collect();
+    assert_eq!(depths, vec![1, 1, 1, 2, 2, 2, 2, 2, 1]);
+
+    // Unmatched closing brackets don't make the depth negative.
+    assert!(tokenize("))(").iter().all(|token| token.depth <= 1));
+}
+
+#[test]
+fn brackets_match() {
+    let text = "(4 ((2 (1 1)) 2))";
+    assert_eq!(matching_bracket(text, 0), Some(16));
+    assert_eq!(matching_bracket(text, 16), Some(0));
+    assert_eq!(matching_bracket(text, 4), Some(12));
+    assert_eq!(matching_bracket(text, 7), Some(11));
+    assert_eq!(matching_bracket(text, 1), None);
+    assert_eq!(matching_bracket("(1 (1)", 0), None);
+}
+
+#[test]
+fn valid_metres_have_no_errors() {
+    for preset in factory_presets() {
+        assert_eq!(find_syntax_errors(&preset.metre_a), vec![]);
+    }
+    assert_eq!(find_syntax_errors("(3 (\n  1,1\n  (1 (1 1))))"), vec![]);
+}
+
+#[test]
+fn errors_point_at_the_problem() {
+    assert_eq!(error_positions("(3 (1 1 1)"), vec![(0, 1)]);
+    assert_eq!(error_positions("(3 (1 1 1)))"), vec![(11, 12)]);
+    assert_eq!(error_positions("(3 (1 a 1))"), vec![(6, 7)]);
+    assert_eq!(error_positions("(3 (1 0 1))"), vec![(6, 7)]);
+    assert_eq!(error_positions("((1 1))"), vec![(1, 2)]);
+    assert_eq!(error_positions("(2 1 1)"), vec![(3, 4)]);
+    assert_eq!(error_positions("(2 ())"), vec![(4, 5)]);
+    assert_eq!(error_positions("(2 (1 1) 3)"), vec![(9, 10)]);
+    assert_eq!(error_positions("(2 (1 1)) (1 (1))"), vec![(10, 11)]);
+    assert_eq!(error_positions("  "), vec![(0, 2)]);
+    assert_eq!(error_positions("4"), vec![(0, 1)]);
+}
+
+#[test]
+fn formatting_keeps_the_metre() {
+    for preset in factory_presets() {
+        for width in [8, 20, 80] {
+            let formatted = format_rqq(&preset.metre_a, width).unwrap();
+            assert_eq!(find_syntax_errors(&formatted), vec![]);
+            assert_eq!(MetreData::try_from(formatted.as_str()).unwrap().exact_durations,
+                       MetreData::try_from(preset.metre_a.as_str()).unwrap().exact_durations);
+        }
+        // Short enough metres stay on one line.
+        assert_eq!(format_rqq(&preset.metre_a, 1000).unwrap(), preset.metre_a);
+    }
+
+    assert_eq!(format_rqq("(8 (1 1 1 1 1 1 1 1))", 12).unwrap(), "(8 (\n  1 1 1 1 1\n  1 1 1))");
+    assert_eq!(format_rqq("(2 ((3 (1 1 1)) 1 1))", 13).unwrap(), "(2 (\n  (3 (1 1 1))\n  1 1))");
+    assert!(format_rqq("(1 (", 12).is_err());
+}
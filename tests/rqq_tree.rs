@@ -0,0 +1,91 @@
+use metrefiddler::metre::metre_data::MetreData;
+use metrefiddler::metre::rqq_tree::{RqqNode, TreeEdit};
+use metrefiddler::preset::factory_presets;
+
+fn edited(rqq: &str, path: &[usize], edit: TreeEdit) -> Result<String, String> {
+    let mut tree = RqqNode::parse(rqq).unwrap();
+    tree.edit(path, edit).map(|_| tree.to_string())
+}
+
+#[test]
+fn parse_and_print() {
+    for preset in factory_presets() {
+        assert_eq!(RqqNode::parse(&preset.metre_a).unwrap().to_string(), preset.metre_a);
+    }
+    // Separators and whitespace are normalized.
+    assert_eq!(RqqNode::parse("(3 ( 1,1  (1 (1 1))))").unwrap().to_string(), "(3 (1 1 (1 (1 1))))");
+
+    assert!(RqqNode::parse("").is_err());
+    assert!(RqqNode::parse("4").is_err());
+    assert!(RqqNode::parse("(4 (1 0 1))").is_err());
+}
+
+#[test]
+fn nodes_by_path() {
+    let tree = RqqNode::parse("(4 ((2 (1 1)) 2))").unwrap();
+    assert_eq!(tree.node(&[]), Some(&tree));
+    assert_eq!(tree.node(&[0, 1]), Some(&RqqNode::Beat(1)));
+    assert_eq!(tree.node(&[1]), Some(&RqqNode::Beat(2)));
+    assert_eq!(tree.node(&[1, 0]), None);
+    assert_eq!(tree.node(&[2]), None);
+}
+
+#[test]
+fn split_and_merge() {
+    assert_eq!(edited("(4 (1 1 1 1))", &[1], TreeEdit::Split(3)), Ok("(4 (1 (1 (1 1 1)) 1 1))".to_string()));
+    assert!(edited("(4 (1 1 1 1))", &[1], TreeEdit::Split(1)).is_err());
+    assert!(edited("(4 ((2 (1 1)) 2))", &[0], TreeEdit::Split(2)).is_err());
+
+    assert_eq!(edited("(4 (1 1 1 1))", &[2], TreeEdit::MergeWithNext), Ok("(4 (1 1 2))".to_string()));
+    assert_eq!(edited("(4 ((2 (1 1)) 2))", &[0], TreeEdit::MergeWithNext), Ok("(4 (4))".to_string()));
+    assert!(edited("(4 (1 1 1 1))", &[3], TreeEdit::MergeWithNext).is_err());
+}
+
+#[test]
+fn group_and_ungroup() {
+    assert_eq!(edited("(3 (1 2 1))", &[1], TreeEdit::GroupWithNext), Ok("(3 (1 (3 (2 1))))".to_string()));
+    assert_eq!(edited("(6 ((3 (1 1 1)) 3))", &[0], TreeEdit::Ungroup), Ok("(6 (1 1 1 3))".to_string()));
+    // (2 (1 2)) lasts 2/5 of the bar, its children 2/15 and 4/15.
+    assert_eq!(edited("(5 (1 (2 (1 2)) 2))", &[1], TreeEdit::Ungroup), Ok("(5 (3 2 4 6))".to_string()));
+    assert!(edited("(4 (1 1 1 1))", &[0], TreeEdit::Ungroup).is_err());
+
+    // Ungrouping keeps the durations of all beats.
+    let rqq = "(7 ((2 (1 1)) (2 (1 1)) (3 (1 (2 (1 1))))))";
+    for path in [[0], [1], [2]] {
+        let ungrouped = edited(rqq, &path, TreeEdit::Ungroup).unwrap();
+        assert_eq!(MetreData::try_from(ungrouped.as_str()).unwrap().exact_durations,
+                   MetreData::try_from(rqq).unwrap().exact_durations);
+    }
+}
+
+#[test]
+fn resize_and_refine() {
+    assert_eq!(edited("(4 (1 2 1))", &[0], TreeEdit::Resize(1)), Ok("(4 (2 1 1))".to_string()));
+    assert_eq!(edited("(4 (1 2 1))", &[1], TreeEdit::Resize(-1)), Ok("(4 (1 1 2))".to_string()));
+    assert!(edited("(4 (1 2 1))", &[0], TreeEdit::Resize(2)).is_err());
+    assert!(edited("(4 (1 2 1))", &[2], TreeEdit::Resize(1)).is_err());
+    assert!(edited("(4 (1 2 1))", &[], TreeEdit::Resize(1)).is_err());
+
+    assert_eq!(edited("(4 (1 (1 (1 1)) 1))", &[1, 0], TreeEdit::Refine), Ok("(4 (1 (1 (2 2)) 1))".to_string()));
+}
+
+#[test]
+fn edits_give_valid_metres() {
+    let mut tree = RqqNode::parse("(4 (1 1 1 1))").unwrap();
+    let edits = [
+        (vec![0], TreeEdit::Split(2)),
+        (vec![0, 1], TreeEdit::Split(3)),
+        (vec![1], TreeEdit::GroupWithNext),
+        (vec![1, 0], TreeEdit::Refine),
+        (vec![1, 0], TreeEdit::Resize(1)),
+        (vec![0], TreeEdit::Ungroup),
+        (vec![2], TreeEdit::MergeWithNext),
+    ];
+
+    for (path, edit) in edits {
+        tree.edit(&path, edit).unwrap();
+        let metre = MetreData::try_from(tree.to_string().as_str()).unwrap();
+        assert_eq!(RqqNode::parse(&metre.string).unwrap(), tree);
+    }
+    assert_eq!(tree.to_string(), "(4 (1 (1 (1 1 1)) 6))");
+}
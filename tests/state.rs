@@ -0,0 +1,102 @@
+use nih_plug::wrapper::state::{ParamValue, PluginState};
+use metrefiddler::metre::combined_metre_data::CombinedMetreData;
+use metrefiddler::metre::metre_data::MetreData;
+use metrefiddler::state::{migrate_state, MetreState, STATE_VERSION};
+
+const FOUR_FOUR: &str = "(4 ((2 (1 1)) (2 (1 1))))";
+const THREE_FOUR: &str = "(3 (1 1 1))";
+
+/// combined_metre_data as saved by version 1, with all derived data.
+const VERSION_1_METRES: &str = r#"{"metre_a":{"string":"(4 ((2 (1 1)) (2 (1 1))))","value":[0,3,1,2],"gnsm":[2,0,1,0],"durations":[0.25,0.25,0.25,0.25],"max":3},"metre_b":{"string":"(3 (1 1 1))","value":[0,2,1],"gnsm":[1,0,0],"durations":[0.33333334,0.33333334,0.33333334],"max":2},"interpolation_data":{"duration_pairs":[[0.25,0.33333334],[0.25,0.33333334],[0.25,0.33333334],[0.25,0.0]],"interleaved_durations":[0.25,0.08333334,0.16666666,0.16666669,0.08333331,0.25],"interleaved_gnsm":[2,0,0,1,0,0,2],"unique_start_times":[0.0,0.25,0.33333334,0.5,0.6666667,0.75,1.0],"unique_start_time_origins":["Both","MetreA","MetreB","MetreA","MetreB","MetreA","Both"]}}"#;
+
+/// combined_metre_data in the format of version 1, with derived data that doesn't match the
+/// strings.
+const VERSION_1_METRES_WITH_OUTDATED_DATA: &str = r#"{"metre_a":{"string":"(4 ((2 (1 1)) (2 (1 1))))","value":[0,1],"gnsm":[1,0],"durations":[0.5,0.5],"max":1},"metre_b":{"string":"(3 (1 1 1))","value":[0],"gnsm":[0],"durations":[1.0],"max":0},"interpolation_data":{"duration_pairs":[],"interleaved_durations":[],"interleaved_gnsm":[],"unique_start_times":[],"unique_start_time_origins":[]}}"#;
+
+fn expected_metres() -> CombinedMetreData {
+    CombinedMetreData::new(MetreData::try_from(FOUR_FOUR).unwrap(), MetreData::try_from(THREE_FOUR).unwrap()).unwrap()
+}
+
+fn assert_same_metres(actual: &CombinedMetreData, expected: &CombinedMetreData) {
+    for (actual, expected) in [(actual.metre_a(), expected.metre_a()), (actual.metre_b(), expected.metre_b())] {
+        assert_eq!(actual.string, expected.string);
+        assert_eq!(actual.value, expected.value);
+        assert_eq!(actual.durations, expected.durations);
+        assert_eq!(actual.exact_durations, expected.exact_durations);
+    }
+    assert_eq!(actual.interpolation_data().duration_pairs(), expected.interpolation_data().duration_pairs());
+    assert_eq!(actual.interpolation_data().interleaved_durations(), expected.interpolation_data().interleaved_durations());
+}
+
+fn old_state(fields: &[(&str, &str)]) -> PluginState {
+    PluginState {
+        version: String::from("0.1.0"),
+        params: Default::default(),
+        fields: fields.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect(),
+    }
+}
+
+#[test]
+fn only_the_strings_are_persisted() {
+    let json = serde_json::to_string(&expected_metres()).unwrap();
+    assert_eq!(json, format!(r#"{{"version":{},"metre_a":"{}","metre_b":"{}"}}"#, STATE_VERSION, FOUR_FOUR, THREE_FOUR));
+
+    let restored: CombinedMetreData = serde_json::from_str(&json).unwrap();
+    assert_same_metres(&restored, &expected_metres());
+}
+
+#[test]
+fn version_1_metres_are_recalculated() {
+    for blob in [VERSION_1_METRES, VERSION_1_METRES_WITH_OUTDATED_DATA] {
+        let state: MetreState = serde_json::from_str(blob).unwrap();
+        assert_eq!(state, MetreState { version: 1, metre_a: FOUR_FOUR.to_string(), metre_b: THREE_FOUR.to_string() });
+
+        let restored: CombinedMetreData = serde_json::from_str(blob).unwrap();
+        assert_same_metres(&restored, &expected_metres());
+    }
+}
+
+#[test]
+fn metres_from_newer_versions_are_not_loaded() {
+    let json = format!(r#"{{"version":{},"metre_a":"{}","metre_b":"{}"}}"#, STATE_VERSION + 1, FOUR_FOUR, THREE_FOUR);
+    assert!(serde_json::from_str::<MetreState>(&json).is_err());
+    assert!(serde_json::from_str::<CombinedMetreData>(&json).is_err());
+}
+
+#[test]
+fn invalid_metres_are_not_loaded() {
+    let json = format!(r#"{{"version":{},"metre_a":"","metre_b":"{}"}}"#, STATE_VERSION, THREE_FOUR);
+    assert!(serde_json::from_str::<CombinedMetreData>(&json).is_err());
+}
+
+#[test]
+fn persisted_settings_become_parameters() {
+    let mut state = old_state(&[
+        ("interpolate_durations", "false"),
+        ("interpolate_indisp", "true"),
+        ("retain_metric_phase", "false"),
+        ("many_velocities", "false"),
+        ("midi_out_one_note", "true"),
+        ("current_nr_of_beats", "4"),
+        ("combined_metre_data", VERSION_1_METRES),
+    ]);
+    migrate_state(&mut state);
+
+    assert!(matches!(state.params.get("interpolate_durations"), Some(ParamValue::Bool(false))));
+    assert!(matches!(state.params.get("interpolate_indisp"), Some(ParamValue::Bool(true))));
+    assert!(matches!(state.params.get("retain_metric_phase"), Some(ParamValue::Bool(false))));
+    assert!(matches!(state.params.get("velocity_mode"), Some(ParamValue::String(mode)) if mode == "accents"));
+    assert!(matches!(state.params.get("note_output"), Some(ParamValue::String(output)) if output == "one_pitch"));
+    // The metres are migrated when they are deserialized, derived values are dropped.
+    assert_eq!(state.fields.keys().collect::<Vec<_>>(), vec!["combined_metre_data"]);
+}
+
+#[test]
+fn parameters_win_over_persisted_settings() {
+    let mut state = old_state(&[("interpolate_durations", "false")]);
+    state.params.insert(String::from("interpolate_durations"), ParamValue::Bool(true));
+    migrate_state(&mut state);
+
+    assert!(matches!(state.params.get("interpolate_durations"), Some(ParamValue::Bool(true))));
+    assert!(state.fields.is_empty());
+}